@@ -0,0 +1,343 @@
+//! Sources whose filters come from an archive of a repository (GitHub and
+//! GitLab): downloading it into the cache, checking it against the published
+//! checksums, and extracting the filters from it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Report, Result,
+};
+use futures_util::future::BoxFuture;
+use log::{debug, info, warn};
+use reqwest::{RequestBuilder, Response};
+use tokio::fs;
+
+use crate::{
+    cache,
+    checksum::{self, Checksums},
+    http, install, logging, minisign,
+    progress::Event,
+    text, Error, Globals, VersionInfo,
+};
+
+/// Where archives are downloaded from, and what differs between the hosts.
+pub(crate) trait Host: Sync {
+    /// The request that downloads an archive, failing if it can't be made
+    /// right now (e.g. while rate limited).
+    fn archive_request(&self, globals: &Globals, url: &str) -> Result<RequestBuilder>;
+
+    /// Checks the response to an archive download before it is read.
+    fn check(&self, globals: &Globals, response: &Response) -> Result<()>;
+
+    /// Downloads a (small) release asset, such as the checksums.
+    fn fetch_asset<'a>(
+        &'a self,
+        globals: &'a Globals,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Installs the filters of a version: downloads its archive (unless it is
+/// cached, or another source already downloaded it during this run), reads
+/// the checksums of the release and extracts the filters.
+pub(crate) async fn fetch(
+    globals: &Globals,
+    host: &impl Host,
+    cache_key: &str,
+    version: &VersionInfo,
+) -> Result<Vec<PathBuf>> {
+    let zipball_path = cache::zipball_path(cache_key, &version.watermark).await?;
+    if zipball_path.exists() {
+        info!("using cached {zipball_path:?}");
+        globals.counters.cache_hit();
+    } else if globals.offline {
+        bail!("{cache_key} at {} is not in the cache", version.watermark);
+    } else {
+        let cell = globals.run_cache.archive(&version.zipball_url);
+        let downloaded = cell
+            .get_or_try_init(|| async {
+                info!("downloading archive");
+                http::download(
+                    globals,
+                    host.archive_request(globals, &version.zipball_url)?,
+                    &zipball_path,
+                    |response| host.check(globals, response),
+                    |downloaded, total| {
+                        globals.progress.emit(Event::Downloading {
+                            source: cache_key.to_string(),
+                            downloaded,
+                            total,
+                        })
+                    },
+                )
+                .await?;
+                Ok::<_, Report>(zipball_path.clone())
+            })
+            .await?;
+        if *downloaded != zipball_path {
+            // Another source downloaded the same archive.
+            info!("using {downloaded:?} downloaded for another source");
+            globals.counters.cache_hit();
+            if fs::hard_link(downloaded, &zipball_path).await.is_err() {
+                fs::copy(downloaded, &zipball_path).await?;
+            }
+        }
+        cache::remove_zipballs(cache_key, Some(&zipball_path)).await;
+    }
+
+    // The checksums can't be downloaded while offline, signed sources are
+    // refused before getting here.
+    let checksums = match globals.signing_keys.get(cache_key) {
+        _ if globals.offline => Checksums::default(),
+        Some(key) => fetch_signed_checksums(globals, host, version, key).await?,
+        None => match &version.checksums_url {
+            Some(url) => Checksums::parse(&host.fetch_asset(globals, url).await?),
+            None => Checksums::default(),
+        },
+    };
+    if version.checksums_url.is_some() && checksums.is_empty() {
+        warn!("the release publishes checksums, but none could be read");
+    }
+
+    let files = extract(globals, cache_key, zipball_path, checksums).await?;
+    info!("updated {cache_key}");
+
+    Ok(files)
+}
+
+/// Downloads the `SHA256SUMS` of a release and checks its signature. Every
+/// installed filter then has to be listed in it.
+async fn fetch_signed_checksums(
+    globals: &Globals,
+    host: &impl Host,
+    release: &VersionInfo,
+    key: &minisign::PublicKey,
+) -> Result<Checksums> {
+    let (Some(checksums_url), Some(signature_url)) =
+        (&release.checksums_url, &release.signature_url)
+    else {
+        bail!("the release is not signed, it needs SHA256SUMS and SHA256SUMS.minisig assets");
+    };
+
+    info!("downloading signed checksums");
+    let text = host.fetch_asset(globals, checksums_url).await?;
+    let signature = host.fetch_asset(globals, signature_url).await?;
+    key.verify(text.as_bytes(), &signature)
+        .wrap_err("could not verify the signature of the release")?;
+    info!("the release signature is valid");
+
+    let mut checksums = Checksums::parse(&text);
+    checksums.require_all();
+    Ok(checksums)
+}
+
+/// Extracts the filters from a cached archive. This blocks, so it runs on its
+/// own thread while other sources keep downloading.
+async fn extract(
+    globals: &Globals,
+    cache_key: &str,
+    zipball_path: PathBuf,
+    checksums: Checksums,
+) -> Result<Vec<PathBuf>> {
+    globals.progress.emit(Event::Extracting {
+        source: cache_key.to_string(),
+    });
+    let extraction = logging::spawn_blocking({
+        let (globals, source) = (globals.clone(), cache_key.to_string());
+        move || {
+            extract_filters(&zipball_path, &globals, &source, &checksums, |path| {
+                globals.progress.emit(Event::Writing {
+                    source: source.clone(),
+                    path: path.to_path_buf(),
+                })
+            })
+        }
+    });
+
+    let result = extraction.await?;
+    if result.is_err() {
+        // Don't keep using a broken archive.
+        cache::remove_zipballs(cache_key, None).await;
+    }
+    result.map_err(|error| match error.downcast_ref::<Error>() {
+        Some(_) => error,
+        None => Error::Extraction(format!("{error:#}")).into(),
+    })
+}
+
+/// Extracts the filters in the archive into the game directory, streaming
+/// each one straight to disk.
+fn extract_filters(
+    zipball_path: &Path,
+    globals: &Globals,
+    source: &str,
+    checksums: &Checksums,
+    writing: impl Fn(&Path),
+) -> Result<Vec<PathBuf>> {
+    let game_directory = &globals.game_directory;
+    let max_size = globals.max_download_size;
+    let pipeline = globals.pipelines.get(source).cloned().unwrap_or_default();
+
+    info!("opening release zipball");
+    let zipball = BufReader::new(std::fs::File::open(zipball_path)?);
+    let mut zipfile = zip::ZipArchive::new(zipball)?;
+    let filenames: Vec<_> = zipfile.file_names().map(|v| v.to_string()).collect();
+    let mut used_filenames: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut files = Vec::new();
+    let mut failed = Vec::new();
+    // Nothing is put in place until every filter was extracted, so that a
+    // failure or cancellation never leaves a mix of old and new filters.
+    let mut transaction = install::Transaction::new(game_directory);
+
+    for filename in filenames {
+        if globals.cancel.is_cancelled() {
+            bail!(Error::Cancelled);
+        }
+
+        // Checksums are published for the original name.
+        let original_name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+        let Some(target) = globals.targets.iter().find(|v| v.matches(original_name)) else {
+            continue;
+        };
+        let is_filter = Path::new(original_name)
+            .extension()
+            .is_some_and(|v| v == "filter");
+
+        let Some(entry_path) = install::entry_path(&filename) else {
+            warn!("skipping {filename:?}, it has no usable path inside of the game directory");
+            continue;
+        };
+
+        let file = match zipfile.by_name(&filename) {
+            Ok(file) => file,
+            Err(error) => {
+                warn!("could not read {filename:?} from the archive: {error}");
+                failed.push((filename, error.to_string()));
+                continue;
+            }
+        };
+        if !install::is_regular_file(file.unix_mode()) {
+            warn!("skipping {filename:?}, it is a symlink or special file");
+            continue;
+        }
+        if let Some(max_size) = max_size {
+            if file.size() > max_size {
+                drop(file);
+                let error = format!("larger than the limit of {} MiB", max_size / http::MIB);
+                warn!("skipping {filename:?}, it is {error}");
+                failed.push((filename, error));
+                continue;
+            }
+        }
+        drop(file);
+
+        if !pipeline.selects(original_name) {
+            debug!("skipping {filename:?}, it isn't selected");
+            continue;
+        }
+
+        // Files are installed next to each other, the game doesn't look for
+        // filters in subdirectories.
+        let Some(name) = entry_path.file_name().and_then(|v| v.to_str()) else {
+            continue;
+        };
+        let used = used_filenames.entry(target.directory.clone()).or_default();
+        let name = install::unique_filename(pipeline.rename(name), used);
+        let full_path = install::destination(&target.directory, Path::new(&name))?;
+        if !target.directory.exists() {
+            std::fs::create_dir_all(&target.directory)?;
+        }
+
+        info!("extracting {filename}");
+        writing(&full_path);
+        // Errors reading the entry only fail this file, anything else (such as
+        // a checksum mismatch or a failing disk) fails the whole source.
+        let mut corrupt = None;
+        let result = transaction.write_file_in(&target.directory, &full_path, |dest| {
+            let mut file = match zipfile.by_name(&filename) {
+                Ok(file) => TrackedReader::new(file),
+                Err(error) => {
+                    corrupt = Some(error.to_string());
+                    bail!("could not read {filename:?}");
+                }
+            };
+            // The size in the header can't be trusted, so limit the reader too.
+            let mut data = Vec::new();
+            let read = (&mut file)
+                .take(max_size.unwrap_or(u64::MAX))
+                .read_to_end(&mut data);
+            if let Some(error) = file.error {
+                corrupt = Some(error);
+            }
+            read?;
+
+            // Checksums are for the file as published, before it is
+            // converted.
+            checksums.verify(original_name, &checksum::sha256(&data))?;
+            // Other files (like sounds) are installed as they are.
+            if !is_filter {
+                dest.write_all(&data)?;
+                return Ok(());
+            }
+            let data = text::to_utf8(&filename, data);
+            dest.write_all(&pipeline.process(source, original_name, data, &globals.cancel)?)?;
+            Ok(())
+        });
+        match (result, corrupt) {
+            (Ok(()), _) => files.push(full_path),
+            (Err(_), Some(error)) => {
+                warn!("could not extract {filename:?}: {error}");
+                failed.push((filename, error));
+            }
+            (Err(error), None) => return Err(error),
+        }
+    }
+
+    if globals.cancel.is_cancelled() {
+        bail!(Error::Cancelled);
+    }
+
+    if !failed.is_empty() {
+        // Dropping the transaction keeps every previous filter, and the source
+        // is tried again next time, with a fresh download.
+        drop(transaction);
+        let details: String = failed
+            .iter()
+            .map(|(filename, error)| format!("\n  {filename}: {error}"))
+            .collect();
+        bail!(
+            "{} of {} filter(s) could not be extracted, none were installed:{details}",
+            failed.len(),
+            failed.len() + files.len()
+        );
+    }
+    transaction.commit()?;
+
+    Ok(files)
+}
+
+/// Remembers the error if reading fails, to tell a corrupt archive entry apart
+/// from a failing write.
+struct TrackedReader<R> {
+    inner: R,
+    error: Option<String>,
+}
+
+impl<R> TrackedReader<R> {
+    fn new(inner: R) -> Self {
+        TrackedReader { inner, error: None }
+    }
+}
+
+impl<R: Read> Read for TrackedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).inspect_err(|error| {
+            self.error = Some(error.to_string());
+        })
+    }
+}
@@ -0,0 +1,308 @@
+//! Downloaded archives and data, kept in the XDG cache directory.
+
+use std::{
+    env::temp_dir,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::bail, Result};
+use log::{debug, info, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{
+    header::{
+        HeaderMap, AGE, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    },
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{dirs, http, Globals, VersionInfo};
+
+/// Separates the source from the watermark in cache file names.
+const SEPARATOR: &str = "@";
+
+/// Without a home directory the archives still need to go somewhere while
+/// they are being downloaded.
+fn zipball_directory() -> PathBuf {
+    dirs::cache_home()
+        .unwrap_or_else(temp_dir)
+        .join("poe2filter/zipballs")
+}
+
+/// Characters that are escaped in file names, the escaping is reversible so
+/// that the watermark can be recovered from the name.
+const ESCAPED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Turns anything into a safe, flat filename component.
+fn escape(v: &str) -> String {
+    utf8_percent_encode(v, ESCAPED).to_string()
+}
+
+/// Where the archive of a source at the given watermark is cached. The
+/// directory is created if needed.
+pub async fn zipball_path(source: &str, watermark: &str) -> std::io::Result<PathBuf> {
+    let directory = zipball_directory();
+    fs::create_dir_all(&directory).await?;
+
+    let name = format!("{}{SEPARATOR}{}.zip", escape(source), escape(watermark));
+    Ok(directory.join(name))
+}
+
+/// Finds the (complete) cached archive of a source, and its watermark.
+pub async fn cached_zipball(source: &str) -> Option<(String, PathBuf)> {
+    let mut entries = fs::read_dir(zipball_directory()).await.ok()?;
+
+    let prefix = format!("{}{SEPARATOR}", escape(source));
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let Some(watermark) = name
+            .to_str()
+            .and_then(|v| v.strip_prefix(&prefix))
+            .and_then(|v| v.strip_suffix(".zip"))
+        else {
+            continue;
+        };
+
+        if let Ok(watermark) = percent_decode_str(watermark).decode_utf8() {
+            return Some((watermark.into_owned(), entry.path()));
+        }
+    }
+
+    None
+}
+
+/// The version of the archive in the cache, which is all that can be
+/// installed while offline.
+pub(crate) async fn cached_version(
+    globals: &Globals,
+    cache_key: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let Some((watermark, _)) = cached_zipball(cache_key).await else {
+        return Ok(None);
+    };
+    if existing == Some(&watermark) {
+        return Ok(None);
+    }
+    if globals.signing_keys.contains_key(cache_key) {
+        bail!("the signature of the cached archive can't be checked while offline");
+    }
+
+    info!("installing {watermark} from the cache while offline");
+    Ok(Some(VersionInfo {
+        zipball_url: String::new(),
+        watermark,
+        body: None,
+        files: Vec::new(),
+        checksums_url: None,
+        signature_url: None,
+    }))
+}
+
+/// Removes the cached archives (and partial downloads) of every version of a
+/// source, except for `keep`.
+pub async fn remove_zipballs(source: &str, keep: Option<&Path>) {
+    let Ok(mut entries) = fs::read_dir(zipball_directory()).await else {
+        return;
+    };
+
+    let prefix = format!("{}{SEPARATOR}", escape(source));
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if Some(path.as_path()) == keep {
+            continue;
+        }
+
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            debug!("removing cached {path:?}");
+            let _ = fs::remove_file(path).await;
+        }
+    }
+}
+
+/// A cached response, with what is needed to revalidate it.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResponse {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Until when (as a Unix timestamp) the response can be used without
+    /// asking the server.
+    fresh_until: u64,
+}
+
+fn response_path(url: &str) -> PathBuf {
+    dirs::cache_home()
+        .unwrap_or_else(temp_dir)
+        .join("poe2filter/responses")
+        .join(format!("{}.json", escape(url)))
+}
+
+/// Fetches data that rarely changes (such as the league list), honoring the
+/// `Cache-Control` of the response: it is reused without a request while it is
+/// fresh, and revalidated with its `ETag` or `Last-Modified` after that.
+pub async fn fetch(globals: &Globals, url: &str) -> Result<String> {
+    let path = response_path(url);
+    let cached: Option<CachedResponse> = fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|v| serde_json::from_str(&v).ok());
+
+    let now = unix_time();
+    if let Some(cached) = cached.as_ref().filter(|v| v.fresh_until > now) {
+        debug!(
+            "using cached {url}, fresh for {}s",
+            cached.fresh_until - now
+        );
+        globals.counters.cache_hit();
+        return Ok(cached.body.clone());
+    }
+
+    let mut request = globals.client.get(url);
+    if let Some(cached) = &cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = http::send(&*globals.transport, request, globals.retries).await?;
+    let headers = response.headers().clone();
+    let (cached, store) = match cached {
+        Some(mut cached) if response.status() == StatusCode::NOT_MODIFIED => {
+            debug!("{url} was not modified");
+            globals.counters.cache_hit();
+            cached.fresh_until = fresh_until(&headers, now).unwrap_or(now);
+            (cached, true)
+        }
+        _ => {
+            let response = response.error_for_status()?;
+            let header = |name| {
+                headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string())
+            };
+            let cached = CachedResponse {
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+                fresh_until: fresh_until(&headers, now).unwrap_or(now),
+                body: response.text().await?,
+            };
+            (cached, !has_directive(&headers, "no-store"))
+        }
+    };
+
+    if store {
+        if let Err(error) = save_response(&path, &cached).await {
+            warn!("could not cache {url}: {error}");
+        }
+    }
+    Ok(cached.body)
+}
+
+async fn save_response(path: &Path, cached: &CachedResponse) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, serde_json::to_string(cached)?).await?;
+    Ok(())
+}
+
+/// When a response stops being fresh, from its `max-age` (minus its `Age`).
+/// `None` if it has to be revalidated every time.
+fn fresh_until(headers: &HeaderMap, now: u64) -> Option<u64> {
+    if has_directive(headers, "no-cache") || has_directive(headers, "no-store") {
+        return None;
+    }
+
+    let max_age: u64 = directives(headers).find_map(|v| {
+        v.strip_prefix("max-age=")
+            .and_then(|v| v.trim_matches('"').parse().ok())
+    })?;
+    let age: u64 = headers
+        .get(AGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    Some(now + max_age.saturating_sub(age))
+}
+
+fn directives(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+}
+
+fn has_directive(headers: &HeaderMap, name: &str) -> bool {
+    directives(headers).any(|v| v == name)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue, AGE, CACHE_CONTROL};
+
+    use super::{fresh_until, has_directive};
+
+    const NOW: u64 = 1_735_689_600;
+
+    fn headers(cache_control: &[&'static str], age: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in cache_control {
+            headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        if let Some(age) = age {
+            headers.insert(AGE, HeaderValue::from_static(age));
+        }
+        headers
+    }
+
+    #[test]
+    fn is_fresh_for_max_age() {
+        let fresh = |cache_control, age| fresh_until(&headers(cache_control, age), NOW);
+        assert_eq!(fresh(&["max-age=300"], None), Some(NOW + 300));
+        assert_eq!(fresh(&["public, MAX-AGE=\"60\""], None), Some(NOW + 60));
+        assert_eq!(fresh(&["public", "max-age=60"], None), Some(NOW + 60));
+        assert_eq!(fresh(&["max-age=300"], Some("100")), Some(NOW + 200));
+        assert_eq!(fresh(&["max-age=300"], Some("1000")), Some(NOW));
+        assert_eq!(fresh(&["max-age=300"], Some("soon")), Some(NOW + 300));
+    }
+
+    #[test]
+    fn revalidates_without_max_age() {
+        let fresh = |cache_control| fresh_until(&headers(cache_control, None), NOW);
+        assert_eq!(fresh(&[]), None);
+        assert_eq!(fresh(&["public"]), None);
+        assert_eq!(fresh(&["max-age=soon"]), None);
+        assert_eq!(fresh(&["max-age=300, no-cache"]), None);
+        assert_eq!(fresh(&["max-age=300", "No-Store"]), None);
+    }
+
+    #[test]
+    fn finds_no_store() {
+        assert!(has_directive(
+            &headers(&["private, no-store"], None),
+            "no-store"
+        ));
+        assert!(has_directive(
+            &headers(&["max-age=1", " NO-STORE "], None),
+            "no-store"
+        ));
+        assert!(!has_directive(&headers(&["no-cache"], None), "no-store"));
+        assert!(!has_directive(&headers(&[], None), "no-store"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Graceful handling of SIGINT/SIGTERM: the first signal cancels the update
+//! (keeping whatever was already completed), a second one terminates as usual.
+//! On Windows, Ctrl+C terminates right away.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_util::future::{select, Either};
+use tokio::time::sleep;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_: nix::libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn install_handlers() {
+    use log::warn;
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+    // SA_RESETHAND restores the default action after the first signal.
+    let action = SigAction::new(
+        SigHandler::Handler(handle_signal),
+        SaFlags::SA_RESETHAND | SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        if let Err(error) = unsafe { sigaction(signal, &action) } {
+            warn!("could not install a handler for {signal}: {error}");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handlers() {}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Cancels one update, either explicitly (e.g. when the deadline passes) or
+/// when a signal is received. Work that can't simply be dropped, like
+/// extracting on a blocking thread, checks it between steps.
+#[derive(Debug, Clone, Default)]
+pub struct Token(Arc<AtomicBool>);
+
+impl Token {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst) || is_cancelled()
+    }
+
+    /// Resolves once cancelled.
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Runs the future until it completes, or returns `None` if cancelled
+    /// first. Cancelling drops the future, so in-flight requests are
+    /// abandoned right away.
+    pub async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+        match select(pin!(future), pin!(self.cancelled())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Verifying files against a published `SHA256SUMS`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use color_eyre::{eyre::bail, Result};
+use log::debug;
+use ring::digest::{Context, SHA256};
+
+/// Names of release assets that contain checksums.
+pub const CHECKSUM_ASSETS: [&str; 2] = ["SHA256SUMS", "SHA256SUMS.txt"];
+
+/// Expected SHA-256 digests by lowercased file name.
+#[derive(Debug, Default)]
+pub struct Checksums {
+    digests: HashMap<String, String>,
+    /// Reject files that are not listed.
+    required: bool,
+}
+
+impl Checksums {
+    /// Parses the output of `sha256sum`, `<hex digest>  <path>` per line. Only
+    /// the file name of the path is kept, since archives are flattened.
+    pub fn parse(text: &str) -> Self {
+        let digests = text
+            .lines()
+            .filter_map(|line| {
+                let (digest, path) = line.trim().split_once(char::is_whitespace)?;
+                // `sha256sum -b` marks binary files with a `*`.
+                let path = path.trim_start().trim_start_matches('*');
+                let name = path.rsplit(['/', '\\']).next()?;
+                (digest.len() == 64 && digest.bytes().all(|v| v.is_ascii_hexdigit()))
+                    .then(|| (name.to_lowercase(), digest.to_lowercase()))
+            })
+            .collect();
+
+        Checksums {
+            digests,
+            required: false,
+        }
+    }
+
+    /// Makes files that are not listed fail verification, for checksums that
+    /// are signed.
+    pub fn require_all(&mut self) {
+        self.required = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    /// Checks the SHA-256 digest of a file against its published one. Files
+    /// that are not listed are accepted, unless all are required.
+    pub fn verify(&self, name: &str, actual: &str) -> Result<()> {
+        let Some(expected) = self.digests.get(&name.to_lowercase()) else {
+            if self.required {
+                bail!("{name} is not listed in the signed checksums");
+            }
+            debug!("no checksum for {name}");
+            return Ok(());
+        };
+
+        if actual != expected {
+            bail!(
+                "checksum mismatch for {name} (expected {expected}, got {actual}), \
+                the download may be truncated or tampered with"
+            );
+        }
+
+        debug!("checksum of {name} matches");
+        Ok(())
+    }
+}
+
+/// Computes the SHA-256 digest of everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    context: Context,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            context: Context::new(&SHA256),
+        }
+    }
+
+    /// Returns the writer and the hex digest.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, hex(self.context.finish().as_ref()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.context.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The SHA-256 hex digest of some data.
+pub fn sha256(data: &[u8]) -> String {
+    hex(ring::digest::digest(&SHA256, data).as_ref())
+}
+
+/// The SHA-256 hex digest of a file.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut writer)?;
+    Ok(writer.finish().1)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::{sha256, Checksums, HashingWriter};
+
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn parses_sha256sum_output() {
+        let text = format!(
+            "{ABC}  NeverSink.filter\n\
+            {}  *dist/Strict.FILTER\r\n\
+            {EMPTY}  C:\\release\\Empty.filter\n\
+            \n\
+            # a comment\n\
+            {}  Short.filter\n\
+            {}  Invalid.filter\n\
+            {ABC}\n",
+            EMPTY.to_uppercase(),
+            &ABC[1..],
+            ABC.replace('a', "g"),
+        );
+        let checksums = Checksums::parse(&text);
+
+        assert!(checksums.verify("NeverSink.filter", ABC).is_ok());
+        assert!(checksums.verify("neversink.FILTER", ABC).is_ok());
+        assert!(checksums.verify("strict.filter", EMPTY).is_ok());
+        assert!(checksums.verify("Empty.filter", EMPTY).is_ok());
+        assert_eq!(checksums.digests.len(), 3);
+    }
+
+    #[test]
+    fn rejects_mismatches() {
+        let checksums = Checksums::parse(&format!("{ABC}  A.filter\n"));
+        let error = checksums.verify("A.filter", EMPTY).unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch for A.filter"));
+        assert!(checksums.verify("Unlisted.filter", EMPTY).is_ok());
+    }
+
+    #[test]
+    fn requires_all_files_when_signed() {
+        let mut checksums = Checksums::parse(&format!("{ABC}  A.filter\n"));
+        checksums.require_all();
+        assert!(checksums.verify("A.filter", ABC).is_ok());
+        assert!(checksums.verify("Unlisted.filter", ABC).is_err());
+
+        let mut empty = Checksums::parse("not a checksum file");
+        assert!(empty.is_empty());
+        empty.require_all();
+        assert!(empty.verify("A.filter", ABC).is_err());
+    }
+
+    #[test]
+    fn hashes_what_is_written() {
+        assert_eq!(sha256(b"abc"), ABC);
+        assert_eq!(sha256(b""), EMPTY);
+
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"bc").unwrap();
+        let (data, digest) = writer.finish();
+        assert_eq!(data, b"abc");
+        assert_eq!(digest, ABC);
+    }
+}
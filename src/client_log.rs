@@ -0,0 +1,292 @@
+//! Reading the game's `Client.txt` log.
+
+use std::{
+    ffi::OsString,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use log::{debug, error, info};
+use tokio::{
+    fs,
+    io::{AsyncReadExt as _, AsyncSeekExt as _},
+};
+
+use crate::{app_id, notify, steam, Options};
+
+/// Only the end of the log is interesting, and it can grow to hundreds of MB.
+const TAIL_SIZE: u64 = 256 * 1024;
+
+/// Words that, together with "filter", mark a line about a filter that
+/// could not be loaded.
+const FILTER_ERROR_WORDS: [&str; 4] = ["error", "failed", "invalid", "unknown"];
+
+/// A named set of sources, selected when its name shows up in the game log.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub sources: Vec<OsString>,
+}
+
+impl Profile {
+    /// Parses `name=source,source,...`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (name, sources) = value
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| eyre!("--profile must be in the form name=source,source"))?;
+
+        Ok(Profile {
+            name: name.to_string(),
+            sources: sources
+                .split(',')
+                .filter(|v| !v.is_empty())
+                .map(OsString::from)
+                .collect(),
+        })
+    }
+}
+
+/// Locates `Client.txt`, either as configured or in the Steam install
+/// directory of the game.
+pub async fn log_path(options: &Options) -> Option<PathBuf> {
+    if let Some(path) = options.client_log.clone() {
+        return Some(path);
+    }
+
+    let directory = steam::install_directory(&app_id(options)).await?;
+    let path = directory.join("logs/Client.txt");
+    path.is_file().then_some(path)
+}
+
+/// Reads the last part of the log.
+pub async fn tail(path: &Path) -> Result<String> {
+    read_from(path, 0).await
+}
+
+/// Picks the profile whose name was mentioned most recently in the log, such
+/// as a `hardcore` profile when a Hardcore league character was played.
+pub async fn select_profile(options: &Options) -> Option<&Profile> {
+    if options.profiles.is_empty() {
+        return None;
+    }
+
+    let path = log_path(options).await?;
+    let text = tail(&path)
+        .await
+        .inspect_err(|error| debug!("could not read {path:?}: {error}"))
+        .ok()?;
+
+    let profile = latest_profile(&text, &options.profiles)?;
+    info!("selected profile {} from {path:?}", profile.name);
+    Some(profile)
+}
+
+/// The profile named by the last line about the area or league that was
+/// joined. Names only match whole words, so that e.g. `ssf` doesn't match
+/// `Crossfire`, and chat messages are ignored.
+fn latest_profile<'a>(text: &str, profiles: &'a [Profile]) -> Option<&'a Profile> {
+    text.lines().rev().filter_map(joined).find_map(|joined| {
+        let joined = joined.to_lowercase();
+        profiles
+            .iter()
+            .filter_map(|profile| {
+                find_word(&joined, &profile.name.to_lowercase()).map(|index| (index, profile))
+            })
+            .max_by_key(|(index, _)| *index)
+            .map(|(_, profile)| profile)
+    })
+}
+
+/// What a log line says was joined: the area of
+/// `[INFO Client 42] : You have entered Clearfell.`, or a line about a
+/// league. Chat messages have the form `name: message` and are skipped.
+fn joined(line: &str) -> Option<&str> {
+    let (_, message) = line.split_once("] ")?;
+    if let Some(area) = message.strip_prefix(": You have entered ") {
+        return Some(area.trim_end_matches('.'));
+    }
+
+    let league = find_word(&message.to_lowercase(), "league").is_some();
+    (league && !message.contains(':')).then_some(message)
+}
+
+/// The position of the last occurrence of `word` that isn't part of a longer
+/// word.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    if word.is_empty() {
+        return None;
+    }
+
+    text.rmatch_indices(word)
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let before = text[..index].chars().next_back();
+            let after = text[index + word.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+}
+
+/// An error the game logged while loading a filter.
+#[derive(Debug)]
+pub struct FilterError {
+    /// The log line, without the timestamp and thread prefix.
+    pub message: String,
+    /// The installed filter the error is about, if it could be determined.
+    pub file: Option<PathBuf>,
+}
+
+/// The current end of the log, errors are only looked for after it.
+pub async fn position(options: &Options) -> Option<(PathBuf, u64)> {
+    let path = log_path(options).await?;
+    let length = fs::metadata(&path).await.ok()?.len();
+    Some((path, length))
+}
+
+/// Looks for filter errors the game logged since the given position, and
+/// matches them up with the `.filter` files in the game directory.
+pub async fn filter_errors(path: &Path, from: u64, game_directory: &Path) -> Vec<FilterError> {
+    let text = match read_from(path, from).await {
+        Ok(text) => text,
+        Err(error) => {
+            debug!("could not read {path:?}: {error}");
+            return Vec::new();
+        }
+    };
+
+    let filters = installed_filters(game_directory).await;
+    text.lines()
+        .filter(|line| is_filter_error(line))
+        .map(|line| {
+            let lower = line.to_lowercase();
+            let file = filters
+                .iter()
+                .find(|(name, _)| lower.contains(name.as_str()))
+                .map(|(_, path)| path.clone());
+
+            // 2025/01/01 12:00:00 123456 abcdef [WARN Client 42] ...
+            let message = line.split_once("] ").map_or(line, |(_, v)| v).to_string();
+            FilterError { message, file }
+        })
+        .collect()
+}
+
+/// Logs filter errors since the given position, and shows them in a desktop
+/// notification if `notify` is set.
+pub async fn report_filter_errors(path: &Path, from: u64, game_directory: &Path, notify: bool) {
+    let errors = filter_errors(path, from, game_directory).await;
+    if errors.is_empty() {
+        return;
+    }
+
+    let lines: Vec<_> = errors
+        .iter()
+        .map(|error| match &error.file {
+            Some(file) => format!("{}: {}", file.display(), error.message),
+            None => error.message.clone(),
+        })
+        .collect();
+
+    for line in &lines {
+        error!("the game could not load a filter: {line}");
+    }
+    if notify {
+        notify::filter_errors(&lines);
+    }
+}
+
+async fn read_from(path: &Path, from: u64) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let length = file.metadata().await?.len();
+    // The log was truncated or replaced, so everything in it is new.
+    let from = if from > length { 0 } else { from };
+    file.seek(SeekFrom::Start(from.max(length.saturating_sub(TAIL_SIZE))))
+        .await?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn is_filter_error(line: &str) -> bool {
+    let line = line.to_lowercase();
+    line.contains("filter") && FILTER_ERROR_WORDS.iter().any(|word| line.contains(word))
+}
+
+/// Lowercased filter names (the game refers to them without the extension),
+/// longest first so that `neversink (strict)` wins over `neversink`.
+async fn installed_filters(game_directory: &Path) -> Vec<(String, PathBuf)> {
+    let mut filters = Vec::new();
+    let Ok(mut entries) = fs::read_dir(game_directory).await else {
+        return filters;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_some_and(|v| v == "filter") {
+            if let Some(stem) = path.file_stem() {
+                filters.push((stem.to_string_lossy().to_lowercase(), path));
+            }
+        }
+    }
+
+    filters.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{latest_profile, Profile};
+
+    fn profiles() -> Vec<Profile> {
+        ["hardcore=github:a/hc", "ssf=github:a/ssf", "standard=cdrg"]
+            .into_iter()
+            .map(|v| Profile::parse(v).unwrap())
+            .collect()
+    }
+
+    fn select(log: &str) -> Option<String> {
+        let profiles = profiles();
+        latest_profile(log, &profiles).map(|v| v.name.clone())
+    }
+
+    #[test]
+    fn picks_the_last_league_or_area_joined() {
+        let log = "\
+2025/01/01 12:00:00 1000 a1b2c3 [INFO Client 42] : You have entered Hardcore Hideout.
+2025/01/01 12:05:00 2000 a1b2c3 [INFO Client 42] : You have entered Clearfell.
+2025/01/01 12:10:00 3000 a1b2c3 [INFO Client 42] Joined the Standard league
+2025/01/01 12:15:00 4000 a1b2c3 [INFO Client 42] : You have entered Clearfell Encampment.
+";
+        assert_eq!(select(log).as_deref(), Some("standard"));
+
+        let log = format!(
+            "{log}2025/01/01 12:20:00 5000 a1b2c3 [INFO Client 42] : You have entered SSF Hardcore Hideout.\n"
+        );
+        assert_eq!(select(&log).as_deref(), Some("hardcore"));
+    }
+
+    #[test]
+    fn ignores_chat_and_other_lines() {
+        let log = "\
+2025/01/01 12:00:00 1000 a1b2c3 [INFO Client 42] : You have entered Hardcore Hideout.
+2025/01/01 12:01:00 2000 a1b2c3 [INFO Client 42] #Exile: anyone in the standard league?
+2025/01/01 12:02:00 3000 a1b2c3 [INFO Client 42] @From Exile: standard league trade
+2025/01/01 12:03:00 4000 a1b2c3 [DEBUG Client 42] Loading standard textures
+2025/01/01 12:04:00 5000 a1b2c3 [INFO Client 42] Exile: ssf
+";
+        assert_eq!(select(log).as_deref(), Some("hardcore"));
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        let line =
+            |area| format!("2025/01/01 12:00:00 1 a [INFO Client 42] : You have entered {area}.");
+        assert_eq!(select(&line("Crossfire Canyon")), None);
+        assert_eq!(select(&line("Hardcoregrounds")), None);
+        assert_eq!(select(&line("(SSF) Hideout")).as_deref(), Some("ssf"));
+        assert_eq!(select(&line("hardcore")).as_deref(), Some("hardcore"));
+        assert_eq!(select("no profiles here"), None);
+    }
+}
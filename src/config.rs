@@ -0,0 +1,537 @@
+//! The configuration file, `$XDG_CONFIG_HOME/poe2filter/config.toml` (or
+//! `POE2FILTER_CONFIG`), for what is tedious to put in the launch options:
+//!
+//! ```toml
+//! game_directory = "/path/to/My Games/Path of Exile 2"
+//! game = "poe2"
+//! log_level = "info"
+//! github_token = "ghp_..."
+//! sources = ["cdrg"]
+//! post_update = "notify-send 'filters updated'"
+//! env = { DXVK_HUD = "fps" }
+//!
+//! [[source]]
+//! name = "neversink-lite"
+//! branch = "main"
+//! select = ["*SOFT*"]
+//! rename = { "*SOFT*" = "NeverSink.filter" }
+//! stages = ["validate"]
+//!
+//! [[source]]
+//! name = "github:NeverSinkDev/NeverSink-Filter"
+//! game = "poe1"
+//! ```
+//!
+//! Most settings are named after the flag they stand for. Arguments take
+//! precedence: sources given on the command line replace the ones in the
+//! file.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Report, Result,
+};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::{dirs, hooks::Hooks, http, pipeline::Stage, resolve_alias, Game};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Used when no sources are given on the command line.
+    pub sources: Vec<String>,
+    pub game_directory: Option<PathBuf>,
+    /// Like `--prefix`.
+    pub prefix: Option<PathBuf>,
+    /// Like `--game`.
+    pub game: Option<Game>,
+    /// Sources that are for another game than `game`.
+    pub source_games: Vec<(String, Game)>,
+    /// Like `--app-id`.
+    pub app_id: Option<OsString>,
+    /// Like `--log-level`.
+    pub log_level: Option<String>,
+    /// Like `--github-token`.
+    pub github_token: Option<String>,
+    /// Like `--gitlab-token`.
+    pub gitlab_token: Option<String>,
+    /// Like `--proxy`.
+    pub proxy: Option<String>,
+    /// Like `--retries`.
+    pub retries: u32,
+    /// Like `--connect-timeout`, `--read-timeout` and `--request-timeout`.
+    pub timeouts: http::Timeouts,
+    /// Like `--max-download-size`.
+    pub max_download_size: Option<u64>,
+    /// Like `--pre-update`, `--post-update`, `--pre-launch` and
+    /// `--post-game`.
+    pub hooks: Hooks,
+    /// Like `--env` and `--unset-env`.
+    pub environment: Vec<(OsString, Option<OsString>)>,
+    /// Stages by (resolved) source, like `--stage`.
+    pub stages: Vec<(String, Stage)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sources: Vec::new(),
+            game_directory: None,
+            prefix: None,
+            game: None,
+            source_games: Vec::new(),
+            app_id: None,
+            log_level: None,
+            github_token: None,
+            gitlab_token: None,
+            proxy: None,
+            retries: http::DEFAULT_RETRIES,
+            timeouts: http::Timeouts::default(),
+            max_download_size: Some(http::DEFAULT_MAX_DOWNLOAD_SIZE),
+            hooks: Hooks::default(),
+            environment: Vec::new(),
+            stages: Vec::new(),
+        }
+    }
+}
+
+/// The file as it is written.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    #[serde(deserialize_with = "strings")]
+    sources: Vec<String>,
+    game_directory: Option<PathBuf>,
+    prefix: Option<PathBuf>,
+    #[serde(deserialize_with = "parsed")]
+    game: Option<Game>,
+    app_id: Option<u32>,
+    log_level: Option<String>,
+    github_token: Option<String>,
+    gitlab_token: Option<String>,
+    proxy: Option<String>,
+    retries: Option<u32>,
+    /// In seconds, 0 for no limit.
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    /// In MiB, 0 for no limit.
+    max_download_size: Option<u64>,
+    pre_update: Option<String>,
+    post_update: Option<String>,
+    pre_launch: Option<String>,
+    post_game: Option<String>,
+    env: BTreeMap<String, String>,
+    unset_env: Vec<String>,
+    source: Vec<SourceTable>,
+}
+
+/// A `[[source]]` table: the source, and the options that become part of
+/// its name or its pipeline. The stages are kept in the order they are
+/// written.
+#[derive(Debug, Default)]
+struct SourceTable {
+    name: Option<String>,
+    branch: Option<String>,
+    game: Option<Game>,
+    stages: Vec<Stage>,
+}
+
+impl<'de> Deserialize<'de> for SourceTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Table;
+
+        impl<'de> Visitor<'de> for Table {
+            type Value = SourceTable;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a [[source]] table")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut table = SourceTable::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "name" => table.name = Some(map.next_value()?),
+                        "branch" => table.branch = Some(map.next_value()?),
+                        "game" => {
+                            let game: String = map.next_value()?;
+                            table.game = Some(game.parse().map_err(de::Error::custom)?);
+                        }
+                        "select" => {
+                            let Strings(patterns) = map.next_value()?;
+                            table.stages.extend(patterns.into_iter().map(Stage::Select));
+                        }
+                        "transform" => {
+                            let Strings(scripts) = map.next_value()?;
+                            table
+                                .stages
+                                .extend(scripts.into_iter().map(Stage::Transform));
+                        }
+                        "rename" => {
+                            let renames: toml::Table = map.next_value()?;
+                            for (pattern, name) in renames {
+                                let toml::Value::String(name) = name else {
+                                    let error =
+                                        format!("the new name of {pattern} must be a string");
+                                    return Err(de::Error::custom(error));
+                                };
+                                table.stages.push(Stage::Rename { pattern, name });
+                            }
+                        }
+                        "stages" => {
+                            let Strings(stages) = map.next_value()?;
+                            for stage in stages {
+                                table.stages.push(stage.parse().map_err(de::Error::custom)?);
+                            }
+                        }
+                        _ => return Err(de::Error::unknown_field(&key, SOURCE_FIELDS)),
+                    }
+                }
+                Ok(table)
+            }
+        }
+
+        deserializer.deserialize_map(Table)
+    }
+}
+
+const SOURCE_FIELDS: &[&str] = &[
+    "name",
+    "branch",
+    "game",
+    "select",
+    "transform",
+    "rename",
+    "stages",
+];
+
+/// Where the configuration is read from, unless `--config` is given.
+pub fn path() -> Option<PathBuf> {
+    std::env::var_os("POE2FILTER_CONFIG")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_home().map(|v| v.join("poe2filter/config.toml")))
+}
+
+/// Reads the configuration. Without one the defaults are used, unless the
+/// file was asked for explicitly.
+pub fn load(explicit: Option<&Path>) -> Result<Config> {
+    let Some(path) = explicit.map(Path::to_path_buf).or_else(path) else {
+        return Ok(Config::default());
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound && explicit.is_none() => {
+            return Ok(Config::default())
+        }
+        Err(error) => return Err(error).wrap_err_with(|| format!("could not read {path:?}")),
+    };
+
+    parse(&text).wrap_err_with(|| format!("invalid configuration in {path:?}"))
+}
+
+fn parse(text: &str) -> Result<Config> {
+    let file: File = toml::from_str(text)?;
+    let seconds = |value: Option<u64>, default| match value {
+        Some(seconds) => Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero()),
+        None => default,
+    };
+    let defaults = Config::default();
+
+    let mut config = Config {
+        sources: file.sources,
+        game_directory: file.game_directory,
+        prefix: file.prefix,
+        game: file.game,
+        app_id: file.app_id.map(|v| v.to_string().into()),
+        log_level: file.log_level,
+        github_token: file.github_token,
+        gitlab_token: file.gitlab_token,
+        proxy: file.proxy,
+        retries: file.retries.unwrap_or(defaults.retries),
+        timeouts: http::Timeouts {
+            connect: seconds(file.connect_timeout, defaults.timeouts.connect),
+            read: seconds(file.read_timeout, defaults.timeouts.read),
+            total: seconds(file.request_timeout, defaults.timeouts.total),
+        },
+        max_download_size: match file.max_download_size {
+            Some(megabytes) => Some(megabytes.saturating_mul(http::MIB)).filter(|v| *v > 0),
+            None => defaults.max_download_size,
+        },
+        hooks: Hooks {
+            pre_update: file.pre_update,
+            post_update: file.post_update,
+            pre_launch: file.pre_launch,
+            post_game: file.post_game,
+        },
+        environment: file
+            .env
+            .into_iter()
+            .map(|(key, value)| (key.into(), Some(value.into())))
+            .chain(file.unset_env.into_iter().map(|key| (key.into(), None)))
+            .collect(),
+        ..defaults
+    };
+    for table in file.source {
+        source(&mut config, table)?;
+    }
+    Ok(config)
+}
+
+fn source(config: &mut Config, table: SourceTable) -> Result<()> {
+    let Some(name) = table.name else {
+        bail!("[[source]] needs a name");
+    };
+    let name = match table.branch {
+        Some(branch) => format!("{}/{branch}", resolve_alias(&name)),
+        None => resolve_alias(&name).to_string(),
+    };
+    config
+        .stages
+        .extend(table.stages.into_iter().map(|v| (name.clone(), v)));
+    if let Some(game) = table.game {
+        config.source_games.push((name.clone(), game));
+    }
+    config.sources.push(name);
+    Ok(())
+}
+
+/// A string, or an array of strings.
+struct Strings(Vec<String>);
+
+impl<'de> Deserialize<'de> for Strings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visit;
+
+        impl<'de> Visitor<'de> for Visit {
+            type Value = Strings;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or an array of strings")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(Strings(vec![value.to_string()]))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut values = Vec::new();
+                while let Some(value) = seq.next_element()? {
+                    values.push(value);
+                }
+                Ok(Strings(values))
+            }
+        }
+
+        deserializer.deserialize_any(Visit)
+    }
+}
+
+fn strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Strings::deserialize(deserializer).map(|Strings(values)| values)
+}
+
+/// A string that is parsed, such as a game.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = Report>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+    use super::parse;
+    use crate::{http, pipeline::Stage, Game};
+
+    #[test]
+    fn parses_the_example() {
+        let config = parse(
+            r#"
+            # A comment.
+            game_directory = "/path/to/My Games/Path of Exile 2"
+            game = "poe2"
+            log_level = 'info'
+            sources = ["cdrg"]
+
+            [[source]]
+            name = "neversink-lite"
+            branch = "main"
+            select = ["*SOFT*"]
+            rename = { "*SOFT*" = "NeverSink.filter" }
+            stages = ["validate"]
+
+            [[source]]
+            name = "github:NeverSinkDev/NeverSink-Filter"
+            game = "poe1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.game_directory,
+            Some(PathBuf::from("/path/to/My Games/Path of Exile 2"))
+        );
+        assert_eq!(config.game, Some(Game::Poe2));
+        assert_eq!(config.log_level.as_deref(), Some("info"));
+        assert_eq!(
+            config.sources,
+            [
+                "cdrg",
+                "github:NeverSinkDev/NeverSink-PoE2litefilter/main",
+                "github:NeverSinkDev/NeverSink-Filter",
+            ]
+        );
+        let source = &config.sources[1];
+        assert_eq!(
+            config.stages,
+            [
+                (source.clone(), Stage::Select("*SOFT*".to_string())),
+                (
+                    source.clone(),
+                    Stage::Rename {
+                        pattern: "*SOFT*".to_string(),
+                        name: "NeverSink.filter".to_string()
+                    }
+                ),
+                (source.clone(), Stage::Validate),
+            ]
+        );
+        assert_eq!(
+            config.source_games,
+            [(config.sources[2].clone(), Game::Poe1)]
+        );
+    }
+
+    #[test]
+    fn parses_strings() {
+        let config = parse(
+            r#"
+            log_level = "a \"quoted\" \\ \u00e9\tb"
+            github_token = 'C:\literal'
+            sources = "one"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log_level.as_deref(), Some("a \"quoted\" \\ é\tb"));
+        assert_eq!(config.github_token.as_deref(), Some(r"C:\literal"));
+        assert_eq!(config.sources, ["one"]);
+    }
+
+    #[test]
+    fn parses_arrays() {
+        let config = parse(
+            r#"
+            sources = [
+                "github:a/b", # a comment
+                "github:c/d",
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sources, ["github:a/b", "github:c/d"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_stages() {
+        let config = parse(
+            r#"
+            [[source]]
+            name = "github:a/b"
+            stages = ["validate"]
+            rename = { "z*" = "first.filter", "a*" = "second.filter" }
+            select = "*.filter"
+            "#,
+        )
+        .unwrap();
+        let patterns: Vec<_> = config
+            .stages
+            .iter()
+            .map(|(_, stage)| stage.to_string())
+            .collect();
+        assert_eq!(
+            patterns,
+            [
+                "validate",
+                "rename:z*=first.filter",
+                "rename:a*=second.filter",
+                "select:*.filter"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_the_settings_of_flags() {
+        let config = parse(
+            r#"
+            app_id = 238960
+            proxy = "socks5h://localhost:1080"
+            retries = 5
+            connect_timeout = 3
+            read_timeout = 0
+            max_download_size = 0
+            pre_update = "echo pre"
+            post_game = "echo post"
+            env = { DXVK_HUD = "fps" }
+            unset_env = ["LD_PRELOAD"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.app_id, Some(OsString::from("238960")));
+        assert_eq!(config.proxy.as_deref(), Some("socks5h://localhost:1080"));
+        assert_eq!(config.retries, 5);
+        assert_eq!(config.timeouts.connect, Some(Duration::from_secs(3)));
+        assert_eq!(config.timeouts.read, None);
+        assert_eq!(config.timeouts.total, http::Timeouts::default().total);
+        assert_eq!(config.max_download_size, None);
+        assert_eq!(config.hooks.pre_update.as_deref(), Some("echo pre"));
+        assert_eq!(config.hooks.post_update, None);
+        assert_eq!(config.hooks.post_game.as_deref(), Some("echo post"));
+        assert_eq!(
+            config.environment,
+            [
+                ("DXVK_HUD".into(), Some("fps".into())),
+                ("LD_PRELOAD".into(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_the_defaults() {
+        let config = parse("").unwrap();
+        assert_eq!(config.retries, http::DEFAULT_RETRIES);
+        assert_eq!(
+            config.max_download_size,
+            Some(http::DEFAULT_MAX_DOWNLOAD_SIZE)
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        let error = |text| format!("{:#}", parse(text).unwrap_err());
+
+        assert!(error("log_level = \"info\"\ngame = 5\n").contains("line 2"));
+        assert!(error("log_level = \"info\"\ngame = \"poe3\"\n").contains("unknown game"));
+        assert!(error("\n\nunknown = 1\n").contains("line 3"));
+        assert!(error("sources = [1]").contains("a string"));
+        assert!(error("log_level = \"not closed\n").contains("line 1"));
+        assert!(error("log_level = \"a\"\nlog_level = \"b\"\n").contains("line 2"));
+        assert!(error("[[source]]\nbranch = \"main\"\n").contains("needs a name"));
+        assert!(error("[[source]]\nname = \"a\"\nstages = [\"nope\"]\n").contains("unknown stage"));
+    }
+}
@@ -0,0 +1,814 @@
+//! `poe2filter serve --dbus`: a session bus service, so that desktop widgets
+//! and shell extensions can trigger updates and show the installed versions.
+//!
+//! Only the small part of the DBus protocol that is needed is implemented:
+//! connecting to the session bus, owning a name, answering method calls and
+//! emitting a signal.
+
+use std::{
+    env::var,
+    ffi::OsString,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    path::PathBuf,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Result,
+};
+use log::{debug, error, info};
+use nix::unistd::getuid;
+
+use crate::{block_on, cancel, installed_versions, run, Options, Summary};
+
+const NAME: &str = "io.github.jcdickinson.poe2filter";
+const PATH: &str = "/io/github/jcdickinson/poe2filter";
+const INTERFACE: &str = "io.github.jcdickinson.poe2filter";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.github.jcdickinson.poe2filter">
+    <method name="Update">
+      <arg name="updated" type="a{ss}" direction="out"/>
+      <arg name="failed" type="a{ss}" direction="out"/>
+    </method>
+    <method name="Status">
+      <arg name="watermarks" type="a{ss}" direction="out"/>
+    </method>
+    <signal name="Updated">
+      <arg name="updated" type="a{ss}"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+/// An `a{ss}`.
+type Dict = Vec<(String, String)>;
+
+/// How often the cancellation flag is checked while waiting for calls.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Message types.
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+/// The largest message the protocol allows.
+const MAX_MESSAGE_LENGTH: u32 = 1 << 27;
+
+/// The caller doesn't want a reply.
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+// Header fields.
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Owns the bus name and answers calls until cancelled.
+pub fn serve(mut args: Vec<OsString>, options: &Options) -> Result<()> {
+    let Some(index) = args.iter().position(|v| v == "--dbus") else {
+        bail!("usage: poe2filter serve --dbus|--stdio <sources>");
+    };
+    args.remove(index);
+
+    let mut connection = Connection::session()?;
+    connection.request_name(NAME)?;
+    info!("serving {NAME} on the session bus");
+
+    while !cancel::is_cancelled() {
+        let Some(message) = connection.next_message()? else {
+            continue;
+        };
+        if message.kind != METHOD_CALL {
+            continue;
+        }
+        if let Err(error) = handle(&mut connection, &message, &args, options) {
+            error!("could not answer {:?}: {error:?}", message.member);
+        }
+    }
+
+    Ok(())
+}
+
+/// What a method call asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum Call {
+    Update,
+    Status,
+    Introspect,
+    Ping,
+    /// Anything else, answered with the error name and message.
+    Unknown(&'static str, String),
+}
+
+/// Works out what a call is for from its path, interface and member. The
+/// interface is optional in calls, the first method with the name is meant
+/// then.
+fn route(call: &Message) -> Call {
+    let member = call.member.as_deref().unwrap_or_default();
+    let interface = call.interface.as_deref();
+
+    // Every object is a peer.
+    if matches!(interface, Some(PEER) | None) && member == "Ping" {
+        return Call::Ping;
+    }
+    let path = call.path.as_deref().unwrap_or_default();
+    if path != PATH {
+        return Call::Unknown(
+            "org.freedesktop.DBus.Error.UnknownObject",
+            format!("there is no object at {path}"),
+        );
+    }
+
+    match (interface, member) {
+        (Some(INTERFACE) | None, "Update") => Call::Update,
+        (Some(INTERFACE) | None, "Status") => Call::Status,
+        (Some(INTROSPECTABLE) | None, "Introspect") => Call::Introspect,
+        (Some(INTERFACE | INTROSPECTABLE | PEER) | None, _) => Call::Unknown(
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!(
+                "{member} is not a method of {}",
+                interface.unwrap_or(INTERFACE)
+            ),
+        ),
+        (Some(interface), _) => Call::Unknown(
+            "org.freedesktop.DBus.Error.UnknownInterface",
+            format!("{PATH} has no interface {interface}"),
+        ),
+    }
+}
+
+fn handle(
+    connection: &mut Connection,
+    call: &Message,
+    sources: &[OsString],
+    options: &Options,
+) -> Result<()> {
+    debug!("{:?} was called by {:?}", call.member, call.sender);
+
+    match route(call) {
+        Call::Update => {
+            let summary = match run(sources.to_vec(), options) {
+                Ok(summary) => summary,
+                Err(error) => return connection.error(call, "Failed", &format!("{error:#}")),
+            };
+            let (updated, failed) = summary_entries(&summary);
+
+            let mut body = Writer::default();
+            body.dict(&updated);
+            body.dict(&failed);
+            connection.reply(call, "a{ss}a{ss}", &body.buf)?;
+
+            if !updated.is_empty() {
+                let mut body = Writer::default();
+                body.dict(&updated);
+                connection.signal("Updated", "a{ss}", &body.buf)?;
+            }
+            Ok(())
+        }
+        Call::Status => {
+            let watermarks: Dict = block_on(installed_versions(options)).into_iter().collect();
+            let mut body = Writer::default();
+            body.dict(&watermarks);
+            connection.reply(call, "a{ss}", &body.buf)
+        }
+        Call::Introspect => {
+            let mut body = Writer::default();
+            body.string(INTROSPECTION);
+            connection.reply(call, "s", &body.buf)
+        }
+        Call::Ping => connection.reply(call, "", &[]),
+        Call::Unknown(name, text) => connection.error(call, name, &text),
+    }
+}
+
+fn summary_entries(summary: &Summary) -> (Dict, Dict) {
+    let updated = summary
+        .updated
+        .iter()
+        .map(|v| (v.source.clone(), v.watermark.clone()))
+        .collect();
+    let failed = summary
+        .failed
+        .iter()
+        .map(|(source, error)| (source.clone(), format!("{error:#}")))
+        .collect();
+    (updated, failed)
+}
+
+/// A connection to the bus.
+struct Connection {
+    stream: BufReader<UnixStream>,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connects and authenticates to the session bus.
+    fn session() -> Result<Self> {
+        let address = session_address()?;
+        debug!("connecting to the session bus at {address:?}");
+        let stream =
+            UnixStream::connect_addr(&address).wrap_err("could not connect to the session bus")?;
+        let mut stream = BufReader::new(stream);
+
+        // The EXTERNAL mechanism authenticates with the uid of the socket,
+        // which has to be sent as hex-encoded ASCII.
+        let uid: String = getuid()
+            .to_string()
+            .bytes()
+            .map(|v| format!("{v:02x}"))
+            .collect();
+        stream
+            .get_mut()
+            .write_all(format!("\0AUTH EXTERNAL {uid}\r\n").as_bytes())?;
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            bail!("the session bus rejected authentication: {}", line.trim());
+        }
+        stream.get_mut().write_all(b"BEGIN\r\n")?;
+
+        let mut connection = Connection { stream, serial: 0 };
+        let reply = connection.call_bus("Hello", "", &[])?;
+        debug!(
+            "connected as {:?}",
+            Reader::new(&reply.body, reply.little_endian).string()
+        );
+        Ok(connection)
+    }
+
+    /// Owns the well-known name, failing if another instance already does.
+    fn request_name(&mut self, name: &str) -> Result<()> {
+        const DO_NOT_QUEUE: u32 = 0x4;
+        const PRIMARY_OWNER: u32 = 1;
+
+        let mut body = Writer::default();
+        body.string(name);
+        body.u32(DO_NOT_QUEUE);
+        let reply = self.call_bus("RequestName", "su", &body.buf)?;
+        match Reader::new(&reply.body, reply.little_endian).u32() {
+            Some(PRIMARY_OWNER) => Ok(()),
+            _ => bail!("{name} is already owned, is poe2filter already serving?"),
+        }
+    }
+
+    /// Calls a method of the bus itself and waits for its reply.
+    fn call_bus(&mut self, member: &str, signature: &str, body: &[u8]) -> Result<Message> {
+        let serial = self.next_serial();
+        let mut fields = vec![
+            (FIELD_PATH, Value::Path("/org/freedesktop/DBus")),
+            (FIELD_INTERFACE, Value::String("org.freedesktop.DBus")),
+            (FIELD_MEMBER, Value::String(member)),
+            (FIELD_DESTINATION, Value::String("org.freedesktop.DBus")),
+        ];
+        if !signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Value::Signature(signature)));
+        }
+        self.send(METHOD_CALL, 0, serial, &fields, body)?;
+
+        loop {
+            let Some(message) = self.next_message()? else {
+                continue;
+            };
+            if message.reply_serial != Some(serial) {
+                continue;
+            }
+            match message.kind {
+                METHOD_RETURN => return Ok(message),
+                _ => bail!(
+                    "the session bus refused {member}: {}",
+                    message.error_name.unwrap_or_default()
+                ),
+            }
+        }
+    }
+
+    fn reply(&mut self, call: &Message, signature: &str, body: &[u8]) -> Result<()> {
+        if call.flags & NO_REPLY_EXPECTED != 0 {
+            return Ok(());
+        }
+
+        let serial = self.next_serial();
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Value::U32(call.serial))];
+        if let Some(sender) = &call.sender {
+            fields.push((FIELD_DESTINATION, Value::String(sender)));
+        }
+        if !signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Value::Signature(signature)));
+        }
+        self.send(METHOD_RETURN, 0, serial, &fields, body)
+    }
+
+    fn error(&mut self, call: &Message, name: &str, text: &str) -> Result<()> {
+        if call.flags & NO_REPLY_EXPECTED != 0 {
+            return Ok(());
+        }
+
+        let name = if name.contains('.') {
+            name.to_string()
+        } else {
+            format!("{INTERFACE}.Error.{name}")
+        };
+        let serial = self.next_serial();
+        let mut fields = vec![
+            (FIELD_ERROR_NAME, Value::String(&name)),
+            (FIELD_REPLY_SERIAL, Value::U32(call.serial)),
+            (FIELD_SIGNATURE, Value::Signature("s")),
+        ];
+        if let Some(sender) = &call.sender {
+            fields.push((FIELD_DESTINATION, Value::String(sender)));
+        }
+        let mut body = Writer::default();
+        body.string(text);
+        self.send(ERROR, 0, serial, &fields, &body.buf)
+    }
+
+    fn signal(&mut self, member: &str, signature: &str, body: &[u8]) -> Result<()> {
+        let serial = self.next_serial();
+        let fields = [
+            (FIELD_PATH, Value::Path(PATH)),
+            (FIELD_INTERFACE, Value::String(INTERFACE)),
+            (FIELD_MEMBER, Value::String(member)),
+            (FIELD_SIGNATURE, Value::Signature(signature)),
+        ];
+        self.send(SIGNAL, NO_REPLY_EXPECTED, serial, &fields, body)
+    }
+
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    fn send(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        serial: u32,
+        fields: &[(u8, Value)],
+        body: &[u8],
+    ) -> Result<()> {
+        let message = marshal(kind, flags, serial, fields, body);
+        self.stream.get_mut().write_all(&message)?;
+        Ok(())
+    }
+
+    /// Reads the next message, or `None` if nothing arrived for a while
+    /// (so that the caller can check for cancellation).
+    fn next_message(&mut self) -> Result<Option<Message>> {
+        self.stream
+            .get_ref()
+            .set_read_timeout(Some(POLL_INTERVAL))?;
+        let waiting = self.stream.fill_buf().map(|v| v.is_empty());
+        self.stream.get_ref().set_read_timeout(None)?;
+        match waiting {
+            Ok(true) => bail!("the session bus closed the connection"),
+            Ok(false) => {}
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        read_message(&mut self.stream).map(Some)
+    }
+}
+
+/// Builds a message, in little endian.
+fn marshal(kind: u8, flags: u8, serial: u32, fields: &[(u8, Value)], body: &[u8]) -> Vec<u8> {
+    let mut message = Writer::default();
+    message.u8(b'l');
+    message.u8(kind);
+    message.u8(flags);
+    message.u8(1); // Protocol version.
+    message.u32(body.len() as u32);
+    message.u32(serial);
+    message.array(8, |message| {
+        for (code, value) in fields {
+            message.pad(8);
+            message.u8(*code);
+            value.write(message);
+        }
+    });
+    message.pad(8);
+    message.buf.extend_from_slice(body);
+    message.buf
+}
+
+/// Reads a message, in either endianness.
+fn read_message(stream: &mut impl Read) -> Result<Message> {
+    let mut header = [0; 16];
+    stream.read_exact(&mut header)?;
+    let little_endian = match header[0] {
+        b'l' => true,
+        b'B' => false,
+        other => bail!("invalid message endianness {other:?}"),
+    };
+    let mut fixed = Reader::new(&header, little_endian);
+    fixed.pos = 4;
+    let (Some(body_length), Some(serial), Some(fields_length)) =
+        (fixed.u32(), fixed.u32(), fixed.u32())
+    else {
+        bail!("invalid message header");
+    };
+    if body_length > MAX_MESSAGE_LENGTH || fields_length > MAX_MESSAGE_LENGTH {
+        bail!("the message is too long");
+    }
+
+    let fields_end = 16 + fields_length as usize;
+    let body_start = fields_end.next_multiple_of(8);
+    let mut rest = vec![0; body_start - 16 + body_length as usize];
+    stream.read_exact(&mut rest)?;
+
+    let mut data = header.to_vec();
+    data.extend_from_slice(&rest);
+    let mut message = Message {
+        kind: header[1],
+        flags: header[2],
+        serial,
+        little_endian,
+        body: data[body_start..].to_vec(),
+        ..Default::default()
+    };
+
+    let mut reader = Reader::new(&data[..fields_end], little_endian);
+    reader.pos = 16;
+    while reader.pos < fields_end {
+        reader.align(8);
+        let (Some(code), Some(signature)) = (reader.u8(), reader.signature()) else {
+            break;
+        };
+        match (code, signature) {
+            (FIELD_PATH, "o") => message.path = reader.string(),
+            (FIELD_INTERFACE, "s") => message.interface = reader.string(),
+            (FIELD_MEMBER, "s") => message.member = reader.string(),
+            (FIELD_ERROR_NAME, "s") => message.error_name = reader.string(),
+            (FIELD_REPLY_SERIAL, "u") => message.reply_serial = reader.u32(),
+            (FIELD_SENDER, "s") => message.sender = reader.string(),
+            (_, "s" | "o") => drop(reader.string()),
+            (_, "u") => drop(reader.u32()),
+            (_, "g") => drop(reader.signature()),
+            // Nothing else is used in headers.
+            _ => break,
+        }
+    }
+
+    Ok(message)
+}
+
+/// The session bus socket, from `DBUS_SESSION_BUS_ADDRESS` or the usual
+/// location in the runtime directory.
+fn session_address() -> Result<SocketAddr> {
+    if let Ok(addresses) = var("DBUS_SESSION_BUS_ADDRESS") {
+        for address in addresses.split(';') {
+            let Some(parameters) = address.strip_prefix("unix:") else {
+                continue;
+            };
+            for parameter in parameters.split(',') {
+                match parameter.split_once('=') {
+                    Some(("path", path)) => return Ok(SocketAddr::from_pathname(path)?),
+                    Some(("abstract", name)) => {
+                        return Ok(SocketAddr::from_abstract_name(name.as_bytes())?)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let runtime = var("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .map_err(|_| eyre!("could not find the session bus, is DBUS_SESSION_BUS_ADDRESS set?"))?;
+    Ok(SocketAddr::from_pathname(runtime.join("bus"))?)
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    little_endian: bool,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    body: Vec<u8>,
+}
+
+/// A header field value.
+enum Value<'a> {
+    String(&'a str),
+    Path(&'a str),
+    Signature(&'a str),
+    U32(u32),
+}
+
+impl Value<'_> {
+    /// Writes the value as a variant.
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            Value::String(v) => {
+                writer.signature("s");
+                writer.string(v);
+            }
+            Value::Path(v) => {
+                writer.signature("o");
+                writer.string(v);
+            }
+            Value::Signature(v) => {
+                writer.signature("g");
+                writer.signature(v);
+            }
+            Value::U32(v) => {
+                writer.signature("u");
+                writer.u32(*v);
+            }
+        }
+    }
+}
+
+/// Marshals values in little endian. Alignment is relative to the start of
+/// the buffer, which is always at an 8 byte boundary of the message.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, alignment: usize) {
+        let length = self.buf.len().next_multiple_of(alignment);
+        self.buf.resize(length, 0);
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, v: &str) {
+        self.u8(v.len() as u8);
+        self.buf.extend_from_slice(v.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes an array of elements with the given alignment. Its length
+    /// doesn't include the padding before the first element.
+    fn array(&mut self, alignment: usize, elements: impl FnOnce(&mut Self)) {
+        self.pad(4);
+        let length_at = self.buf.len();
+        self.u32(0);
+        self.pad(alignment);
+        let start = self.buf.len();
+        elements(self);
+        let length = (self.buf.len() - start) as u32;
+        self.buf[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    /// Writes an `a{ss}`.
+    fn dict(&mut self, entries: &Dict) {
+        self.array(8, |writer| {
+            for (key, value) in entries {
+                writer.pad(8);
+                writer.string(key);
+                writer.string(value);
+            }
+        });
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], little_endian: bool) -> Self {
+        Reader {
+            data,
+            pos: 0,
+            little_endian,
+        }
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.pos = self.pos.next_multiple_of(alignment);
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let v = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(v)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes: [u8; 4] = self.data.get(self.pos..self.pos + 4)?.try_into().ok()?;
+        self.pos += 4;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        let v = self.data.get(self.pos..self.pos + length)?;
+        self.pos += length + 1;
+        Some(String::from_utf8_lossy(v).into_owned())
+    }
+
+    fn signature(&mut self) -> Option<&'a str> {
+        let length = usize::from(self.u8()?);
+        let v = self.data.get(self.pos..self.pos + length)?;
+        self.pos += length + 1;
+        std::str::from_utf8(v).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `Hello` every connection starts with, as the reference
+    /// implementation sends it.
+    const HELLO: &[u8] = b"l\x01\x00\x01\x00\x00\x00\x00\x01\x00\x00\x00m\x00\x00\x00\
+        \x01\x01o\x00\x15\x00\x00\x00/org/freedesktop/DBus\x00\x00\x00\
+        \x02\x01s\x00\x14\x00\x00\x00org.freedesktop.DBus\x00\x00\x00\x00\
+        \x03\x01s\x00\x05\x00\x00\x00Hello\x00\x00\x00\
+        \x06\x01s\x00\x14\x00\x00\x00org.freedesktop.DBus\x00\x00\x00\x00";
+
+    fn hello_fields() -> [(u8, Value<'static>); 4] {
+        [
+            (FIELD_PATH, Value::Path("/org/freedesktop/DBus")),
+            (FIELD_INTERFACE, Value::String("org.freedesktop.DBus")),
+            (FIELD_MEMBER, Value::String("Hello")),
+            (FIELD_DESTINATION, Value::String("org.freedesktop.DBus")),
+        ]
+    }
+
+    /// Reads an `a{ss}`.
+    fn read_dict(reader: &mut Reader) -> Dict {
+        let length = reader.u32().unwrap() as usize;
+        reader.align(8);
+        let end = reader.pos + length;
+        let mut entries = Vec::new();
+        while reader.pos < end {
+            reader.align(8);
+            entries.push((reader.string().unwrap(), reader.string().unwrap()));
+        }
+        entries
+    }
+
+    fn call(path: &str, interface: Option<&str>, member: &str) -> Message {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(path.to_string()),
+            interface: interface.map(str::to_string),
+            member: Some(member.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn marshals_like_the_reference_implementation() {
+        assert_eq!(marshal(METHOD_CALL, 0, 1, &hello_fields(), &[]), HELLO);
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let updated = vec![
+            ("github:a/b".to_string(), "v1".to_string()),
+            ("cdrg".to_string(), "".to_string()),
+        ];
+        let mut body = Writer::default();
+        body.dict(&updated);
+        body.dict(&Vec::new());
+        let fields = [
+            (FIELD_PATH, Value::Path(PATH)),
+            (FIELD_INTERFACE, Value::String(INTERFACE)),
+            (FIELD_MEMBER, Value::String("Updated")),
+            (FIELD_REPLY_SERIAL, Value::U32(7)),
+            (FIELD_SIGNATURE, Value::Signature("a{ss}a{ss}")),
+        ];
+        let data = marshal(SIGNAL, NO_REPLY_EXPECTED, 42, &fields, &body.buf);
+
+        let message = read_message(&mut data.as_slice()).unwrap();
+        assert_eq!(message.kind, SIGNAL);
+        assert_eq!(message.flags, NO_REPLY_EXPECTED);
+        assert_eq!(message.serial, 42);
+        assert_eq!(message.path.as_deref(), Some(PATH));
+        assert_eq!(message.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(message.member.as_deref(), Some("Updated"));
+        assert_eq!(message.reply_serial, Some(7));
+
+        let mut reader = Reader::new(&message.body, message.little_endian);
+        assert_eq!(read_dict(&mut reader), updated);
+        assert_eq!(read_dict(&mut reader), Vec::new());
+        assert_eq!(reader.pos, message.body.len());
+    }
+
+    #[test]
+    fn reads_big_endian_messages() {
+        // A reply to call 3 from :1, with the string ":12".
+        let mut data = b"B\x02\x00\x01\x00\x00\x00\x08\x00\x00\x00\x09\x00\x00\x00\x13".to_vec();
+        data.extend_from_slice(b"\x05\x01u\x00\x00\x00\x00\x03");
+        data.extend_from_slice(b"\x07\x01s\x00\x00\x00\x00\x02:1\x00\x00\x00\x00\x00\x00");
+        data.extend_from_slice(b"\x00\x00\x00\x03:12\x00");
+
+        let message = read_message(&mut data.as_slice()).unwrap();
+        assert!(!message.little_endian);
+        assert_eq!(message.kind, METHOD_RETURN);
+        assert_eq!(message.serial, 9);
+        assert_eq!(message.reply_serial, Some(3));
+        assert_eq!(message.sender.as_deref(), Some(":1"));
+        let mut reader = Reader::new(&message.body, false);
+        assert_eq!(reader.string().as_deref(), Some(":12"));
+    }
+
+    #[test]
+    fn rejects_broken_messages() {
+        assert!(read_message(&mut &HELLO[..40]).is_err());
+        assert!(read_message(&mut &b"x\x01\x00\x01"[..]).is_err());
+        let mut huge = HELLO.to_vec();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_message(&mut huge.as_slice()).is_err());
+    }
+
+    #[test]
+    fn routes_calls() {
+        assert_eq!(route(&call(PATH, Some(INTERFACE), "Update")), Call::Update);
+        assert_eq!(route(&call(PATH, None, "Status")), Call::Status);
+        assert_eq!(
+            route(&call(PATH, Some(INTROSPECTABLE), "Introspect")),
+            Call::Introspect
+        );
+        assert_eq!(route(&call("/", Some(PEER), "Ping")), Call::Ping);
+    }
+
+    #[test]
+    fn rejects_unknown_calls() {
+        let error = |call| match route(&call) {
+            Call::Unknown(name, _) => name,
+            other => panic!("{other:?} was accepted"),
+        };
+        assert_eq!(
+            error(call("/", Some(INTERFACE), "Update")),
+            "org.freedesktop.DBus.Error.UnknownObject"
+        );
+        assert_eq!(
+            error(call("/other", None, "Introspect")),
+            "org.freedesktop.DBus.Error.UnknownObject"
+        );
+        assert_eq!(
+            error(call(PATH, Some("org.example.Other"), "Update")),
+            "org.freedesktop.DBus.Error.UnknownInterface"
+        );
+        assert_eq!(
+            error(call(PATH, Some(INTERFACE), "Introspect")),
+            "org.freedesktop.DBus.Error.UnknownMethod"
+        );
+        assert_eq!(
+            error(call(PATH, None, "Delete")),
+            "org.freedesktop.DBus.Error.UnknownMethod"
+        );
+    }
+}
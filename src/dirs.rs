@@ -0,0 +1,38 @@
+use std::{env::var_os, path::PathBuf};
+
+/// `$XDG_CONFIG_HOME`, or `~/.config`. `%APPDATA%` on Windows.
+pub fn config_home() -> Option<PathBuf> {
+    match cfg!(windows) {
+        true => windows_dir("APPDATA"),
+        false => xdg_dir("XDG_CONFIG_HOME", ".config"),
+    }
+}
+
+/// `$XDG_CACHE_HOME`, or `~/.cache`. `%LOCALAPPDATA%` on Windows.
+pub fn cache_home() -> Option<PathBuf> {
+    match cfg!(windows) {
+        true => windows_dir("LOCALAPPDATA"),
+        false => xdg_dir("XDG_CACHE_HOME", ".cache"),
+    }
+}
+
+/// `$XDG_STATE_HOME`, or `~/.local/state`. `%LOCALAPPDATA%` on Windows.
+pub fn state_home() -> Option<PathBuf> {
+    match cfg!(windows) {
+        true => windows_dir("LOCALAPPDATA"),
+        false => xdg_dir("XDG_STATE_HOME", ".local/state"),
+    }
+}
+
+fn windows_dir(variable: &str) -> Option<PathBuf> {
+    var_os(variable)
+        .map(PathBuf::from)
+        .filter(|v| v.is_absolute())
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    var_os(variable)
+        .map(PathBuf::from)
+        .filter(|v| v.is_absolute())
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+}
@@ -0,0 +1,82 @@
+//! The errors of the library API, by class, so that tools embedding it can
+//! react to them without parsing messages.
+
+use color_eyre::Report;
+use reqwest::StatusCode;
+
+/// Why updating failed. The message of each variant is complete (it includes
+/// what was being done), so it can be shown as is.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A request failed: the network is unreachable, it timed out, or the
+    /// server returned an error.
+    #[error("{0}")]
+    Network(String),
+    /// The server refused to answer more requests for now. `reset` is the
+    /// Unix time at which it will again, if known.
+    #[error("{message}")]
+    RateLimited { message: String, reset: Option<u64> },
+    /// The repository, release or file doesn't exist (or is private).
+    #[error("{0}")]
+    NotFound(String),
+    /// A source that isn't in a known `kind:value` form.
+    #[error("{0}")]
+    InvalidSourceSpec(String),
+    /// The filters could not be extracted from the downloaded archive, e.g.
+    /// it is corrupt or doesn't match its checksums.
+    #[error("{0}")]
+    Extraction(String),
+    /// Neither a Proton prefix nor a Wine prefix with the game was found.
+    #[error("No steam path could be located")]
+    GameDirNotFound,
+    /// Ctrl+C was pressed or the deadline passed.
+    #[error("cancelled")]
+    Cancelled,
+    /// Anything else.
+    #[error("{0:#}")]
+    Other(Report),
+}
+
+impl Error {
+    /// The same class of error with a different message, e.g. one that
+    /// includes more context.
+    fn with_message(&self, message: String) -> Option<Self> {
+        Some(match self {
+            Error::Network(_) => Error::Network(message),
+            Error::RateLimited { reset, .. } => Error::RateLimited {
+                message,
+                reset: *reset,
+            },
+            Error::NotFound(_) => Error::NotFound(message),
+            Error::InvalidSourceSpec(_) => Error::InvalidSourceSpec(message),
+            Error::Extraction(_) => Error::Extraction(message),
+            Error::GameDirNotFound => Error::GameDirNotFound,
+            Error::Cancelled => Error::Cancelled,
+            Error::Other(_) => return None,
+        })
+    }
+}
+
+/// Errors are raised as [`Report`]s internally, with the class attached
+/// wherever it is known. Requests that failed are classified here.
+impl From<Report> for Error {
+    fn from(report: Report) -> Self {
+        let message = format!("{report:#}");
+        if let Some(error) = report
+            .downcast_ref::<Error>()
+            .and_then(|v| v.with_message(message.clone()))
+        {
+            return error;
+        }
+
+        let request = report
+            .chain()
+            .find_map(|v| v.downcast_ref::<reqwest::Error>());
+        match request.map(|v| v.status()) {
+            Some(Some(StatusCode::NOT_FOUND)) => Error::NotFound(message),
+            Some(_) => Error::Network(message),
+            None => Error::Other(report),
+        }
+    }
+}
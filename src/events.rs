@@ -0,0 +1,123 @@
+//! `--events jsonl`: a machine-readable stream of what happens during an
+//! update, one JSON object per line, for overlays and other integrations.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::{eyre::Context, Result};
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::{
+    progress::{Event, Progress},
+    Summary,
+};
+
+/// Where events are written: a file (appended to), a file descriptor that
+/// was passed to poe2filter (`fd:3`), or stdout (`-`).
+#[derive(Clone)]
+pub struct EventLog(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl EventLog {
+    pub fn open(target: &str) -> Result<Self> {
+        let output: Box<dyn Write + Send> = match target {
+            "-" => Box::new(io::stdout()),
+            _ => {
+                // Reopening the descriptor keeps it open for the next run in
+                // watch mode.
+                let path = match target.strip_prefix("fd:") {
+                    Some(fd) => format!("/dev/fd/{fd}"),
+                    None => target.to_string(),
+                };
+                let file: File = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .wrap_err_with(|| format!("could not open {path} for events"))?;
+                Box::new(file)
+            }
+        };
+        Ok(EventLog(Arc::new(Mutex::new(output))))
+    }
+
+    /// Reports progress events to the log.
+    pub fn progress(&self) -> Progress {
+        let log = self.clone();
+        Progress::new(move |event| log.write(&event_json(&event)))
+    }
+
+    pub fn summary(&self, summary: &Summary) {
+        let mut value = summary_json(summary);
+        value["event"] = json!("summary");
+        self.write(&value);
+    }
+
+    pub(crate) fn write(&self, value: &Value) {
+        let Ok(mut output) = self.0.lock() else {
+            return;
+        };
+        // Each event is written in one go, so that lines from concurrent
+        // updates don't interleave.
+        let line = format!("{value}\n");
+        if let Err(error) = output
+            .write_all(line.as_bytes())
+            .and_then(|_| output.flush())
+        {
+            warn!("could not write event: {error}");
+        }
+    }
+}
+
+/// What was updated, what failed and the metrics of the run.
+pub(crate) fn summary_json(summary: &Summary) -> Value {
+    json!({
+        "updated": summary.updated.iter().map(|update| json!({
+            "source": update.source,
+            "watermark": update.watermark,
+            "files": update.files,
+        })).collect::<Vec<_>>(),
+        "failed": summary.failed.iter().map(|(source, error)| json!({
+            "source": source,
+            "error": format!("{error:#}"),
+        })).collect::<Vec<_>>(),
+        "metrics": {
+            "requests": summary.metrics.requests,
+            "bytes_downloaded": summary.metrics.bytes_downloaded,
+            "cache_hits": summary.metrics.cache_hits,
+            "total_seconds": summary.metrics.total.as_secs_f64(),
+            "sources": summary.metrics.sources.iter().map(|(source, duration)| json!({
+                "source": source,
+                "seconds": duration.as_secs_f64(),
+            })).collect::<Vec<_>>(),
+        },
+    })
+}
+
+pub(crate) fn event_json(event: &Event) -> Value {
+    match event {
+        Event::Resolving { source } => json!({ "event": "resolving", "source": source }),
+        Event::Downloading {
+            source,
+            downloaded,
+            total,
+        } => json!({
+            "event": "downloading",
+            "source": source,
+            "downloaded": downloaded,
+            "total": total,
+        }),
+        Event::Extracting { source } => json!({ "event": "extracting", "source": source }),
+        Event::Writing { source, path } => {
+            json!({ "event": "writing", "source": source, "path": path })
+        }
+        Event::Done { source, watermark } => {
+            json!({ "event": "done", "source": source, "watermark": watermark })
+        }
+        Event::Failed { source, error } => {
+            json!({ "event": "error", "source": source, "error": error })
+        }
+    }
+}
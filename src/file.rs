@@ -0,0 +1,200 @@
+//! Filters on the same machine (`file:/path/to/dir-or-file`), e.g. a git
+//! checkout of a filter that is being edited. They are copied into the game
+//! directory whenever their contents change.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::bail, Result};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use log::{debug, info, warn};
+
+use crate::{
+    checksum::HashingWriter, install, logging, progress::Event, text, Error, Globals, Source,
+    VersionInfo,
+};
+
+/// Files and directories on the local filesystem.
+pub struct File;
+
+impl Source for File {
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>> {
+        resolve(globals, value, existing)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>> {
+        fetch(globals, value, version).map_err(Error::from).boxed()
+    }
+}
+
+/// Hashes the files, which is cheap enough for filters and (unlike the
+/// modification time) doesn't change when git checks out the same contents.
+async fn resolve(
+    globals: &Globals,
+    value: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let cache_key = format!("file:{value}");
+    if globals.signing_keys.contains_key(&cache_key) {
+        bail!("only releases can be signed, not local files");
+    }
+
+    let watermark = logging::spawn_blocking({
+        let (globals, source, path) = (globals.clone(), cache_key.clone(), PathBuf::from(value));
+        move || {
+            let files = local_files(&globals, &source, &path)?;
+            let mut writer = HashingWriter::new(io::sink());
+            for (path, name) in &files {
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(&[0])?;
+                io::copy(&mut std::fs::File::open(path)?, &mut writer)?;
+            }
+            Ok::<_, color_eyre::Report>(format!("sha256:{}", writer.finish().1))
+        }
+    })
+    .await??;
+    debug!("{cache_key} has watermark {watermark}");
+
+    if existing == Some(&watermark) {
+        return Ok(None);
+    }
+    Ok(Some(VersionInfo {
+        zipball_url: value.to_string(),
+        watermark,
+        body: None,
+        files: Vec::new(),
+        checksums_url: None,
+        signature_url: None,
+    }))
+}
+
+/// Copies the files into their targets, all of them or none.
+async fn fetch(globals: &Globals, value: &str, _version: &VersionInfo) -> Result<Vec<PathBuf>> {
+    let cache_key = format!("file:{value}");
+    globals.progress.emit(Event::Extracting {
+        source: cache_key.clone(),
+    });
+
+    let files = logging::spawn_blocking({
+        let (globals, path) = (globals.clone(), PathBuf::from(value));
+        move || copy_files(&globals, &cache_key, &path)
+    })
+    .await??;
+    info!("updated file:{value}");
+
+    Ok(files)
+}
+
+fn copy_files(globals: &Globals, source: &str, path: &Path) -> Result<Vec<PathBuf>> {
+    let pipeline = globals.pipelines.get(source).cloned().unwrap_or_default();
+    let mut used_filenames: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut transaction = install::Transaction::new(&globals.game_directory);
+    let mut files = Vec::new();
+
+    for (path, name) in local_files(globals, source, path)? {
+        if globals.cancel.is_cancelled() {
+            bail!(Error::Cancelled);
+        }
+        let Some(target) = globals.targets.iter().find(|v| v.matches(&name)) else {
+            continue;
+        };
+        let Some(filename) = install::sanitize_filename(&name) else {
+            warn!("skipping {path:?}, its name can't be used in the game directory");
+            continue;
+        };
+
+        let used = used_filenames.entry(target.directory.clone()).or_default();
+        let filename = install::unique_filename(pipeline.rename(&filename), used);
+        let full_path = install::destination(&target.directory, Path::new(&filename))?;
+        if !target.directory.exists() {
+            std::fs::create_dir_all(&target.directory)?;
+        }
+
+        info!("copying {path:?}");
+        globals.progress.emit(Event::Writing {
+            source: source.to_string(),
+            path: full_path.clone(),
+        });
+        transaction.write_file_in(&target.directory, &full_path, |dest| {
+            let data = std::fs::read(&path)?;
+            // Other files (like sounds) are installed as they are.
+            if Path::new(&name).extension().is_none_or(|v| v != "filter") {
+                dest.write_all(&data)?;
+                return Ok(());
+            }
+            let data = text::to_utf8(&name, data);
+            dest.write_all(&pipeline.process(source, &name, data, &globals.cancel)?)?;
+            Ok(())
+        })?;
+        files.push(full_path);
+    }
+
+    if globals.cancel.is_cancelled() {
+        bail!(Error::Cancelled);
+    }
+    transaction.commit()?;
+    Ok(files)
+}
+
+/// The selected files at a path and their names, sorted so that they are
+/// hashed in the same order every time. Directories are searched
+/// recursively, skipping hidden ones like `.git`.
+fn local_files(globals: &Globals, source: &str, path: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let pipeline = globals.pipelines.get(source).cloned().unwrap_or_default();
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            bail!(Error::InvalidSourceSpec(format!("{path:?} does not exist")))
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut files = Vec::new();
+    if metadata.is_file() {
+        files.push(path.to_path_buf());
+    } else {
+        let mut directories = vec![path.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                match entry.file_type()? {
+                    v if v.is_dir() => directories.push(entry.path()),
+                    v if v.is_file() => files.push(entry.path()),
+                    _ => debug!(
+                        "skipping {:?}, it is a symlink or special file",
+                        entry.path()
+                    ),
+                }
+            }
+        }
+        files.sort();
+    }
+
+    Ok(files
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.to_string();
+            let selected =
+                globals.targets.iter().any(|v| v.matches(&name)) && pipeline.selects(&name);
+            selected.then_some((path, name))
+        })
+        .collect())
+}
@@ -0,0 +1,129 @@
+//! Filters customized on [FilterBlade](https://www.filterblade.xyz), from
+//! the download link of a saved export (`filterblade:<link>`). Profile names
+//! are rejected, FilterBlade has no public API to look them up.
+//!
+//! FilterBlade doesn't publish versions, so like `url:` sources the
+//! watermark is the `ETag` or `Last-Modified` date of the export, or else a
+//! hash of it.
+
+use std::path::PathBuf;
+
+use color_eyre::{eyre::bail, Result};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use tokio::fs;
+
+use crate::{cache, pipeline, text, url, Error, Globals, Source, VersionInfo};
+
+/// The name exports are installed as, unless the link names the file.
+const DEFAULT_NAME: &str = "FilterBlade.filter";
+
+pub struct FilterBlade;
+
+impl Source for FilterBlade {
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>> {
+        resolve(globals, value, existing)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>> {
+        fetch(globals, value, version).map_err(Error::from).boxed()
+    }
+}
+
+async fn resolve(
+    globals: &Globals,
+    value: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let link = link(value)?;
+    let cache_key = format!("filterblade:{value}");
+    if globals.offline {
+        return cache::cached_version(globals, &cache_key, existing).await;
+    }
+
+    url::latest(globals, &cache_key, link, existing).await
+}
+
+/// The download link of a source. Only links are supported: FilterBlade has
+/// no public API to look up the export of a profile by its name.
+fn link(value: &str) -> Result<reqwest::Url, Error> {
+    let Ok(link) = reqwest::Url::parse(value) else {
+        return Err(Error::InvalidSourceSpec(format!(
+            "filterblade:{value} is not a link, FilterBlade profiles can only be used through the \
+            download link of their saved export (https://www.filterblade.xyz/...)"
+        )));
+    };
+    let filterblade = link
+        .host_str()
+        .is_some_and(|host| host == "filterblade.xyz" || host.ends_with(".filterblade.xyz"));
+    if link.scheme() != "https" || !filterblade {
+        return Err(Error::InvalidSourceSpec(format!(
+            "filterblade:{value} must be a https://www.filterblade.xyz download link"
+        )));
+    }
+    Ok(link)
+}
+
+async fn fetch(globals: &Globals, value: &str, version: &VersionInfo) -> Result<Vec<PathBuf>> {
+    let cache_key = format!("filterblade:{value}");
+    let named = reqwest::Url::parse(value)
+        .ok()
+        .and_then(|v| v.path_segments()?.next_back().map(str::to_lowercase))
+        .is_some_and(|v| v.ends_with(".filter"));
+    let name = match named {
+        true => url::filename(value),
+        false => DEFAULT_NAME.to_string(),
+    };
+
+    // Expired or mistyped links are answered with a web page, which would
+    // otherwise replace the filter.
+    let path = url::download(globals, &cache_key, version).await?;
+    let data = text::to_utf8(&name, fs::read(&path).await?);
+    if let Err(error) = pipeline::validate(&name, &data) {
+        cache::remove_zipballs(&cache_key, None).await;
+        bail!("{error}, is the FilterBlade link still valid?");
+    }
+
+    url::install(globals, &cache_key, &name, data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::link;
+    use crate::Error;
+
+    #[test]
+    fn accepts_only_filterblade_links() {
+        let valid = "https://www.filterblade.xyz/Filters/Exports/abc/NeverSink.filter";
+        assert_eq!(link(valid).unwrap().as_str(), valid);
+        assert!(link("https://filterblade.xyz/?profile=abc").is_ok());
+
+        for invalid in [
+            "my-profile",
+            "http://www.filterblade.xyz/abc",
+            "https://filterblade.xyz.example.com/abc",
+            "https://notfilterblade.xyz/abc",
+            "file:///home/exile/NeverSink.filter",
+        ] {
+            assert!(
+                matches!(link(invalid), Err(Error::InvalidSourceSpec(_))),
+                "{invalid}"
+            );
+        }
+        let Err(Error::InvalidSourceSpec(message)) = link("my-profile") else {
+            unreachable!();
+        };
+        assert!(message.contains("download link"), "{message}");
+    }
+}
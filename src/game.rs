@@ -0,0 +1,82 @@
+//! The games whose filters can be kept up to date: Path of Exile 2, and the
+//! first one for those who play both.
+
+use std::{env::var_os, fmt, str::FromStr};
+
+use color_eyre::{eyre::bail, Report, Result};
+
+use crate::Options;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Game {
+    Poe1,
+    #[default]
+    Poe2,
+}
+
+impl Game {
+    const ALL: [Game; 2] = [Game::Poe1, Game::Poe2];
+
+    /// The Steam app id.
+    pub fn app_id(self) -> &'static str {
+        match self {
+            Game::Poe1 => "238960",
+            Game::Poe2 => "2694490",
+        }
+    }
+
+    /// The name of the game, which is also its directory in `My Games`, its
+    /// registry key and how it shows up in the installed programs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Game::Poe1 => "Path of Exile",
+            Game::Poe2 => "Path of Exile 2",
+        }
+    }
+
+    /// Public endpoint listing the current leagues, used by the trade site.
+    pub(crate) fn leagues_url(self) -> &'static str {
+        match self {
+            Game::Poe1 => "https://www.pathofexile.com/api/trade/data/leagues",
+            Game::Poe2 => "https://www.pathofexile.com/api/trade2/data/leagues",
+        }
+    }
+
+    /// The game with this Steam app id.
+    fn from_app_id(app_id: &str) -> Option<Game> {
+        Game::ALL.into_iter().find(|v| v.app_id() == app_id)
+    }
+
+    /// The game that Steam is launching, if it is one of them.
+    pub(crate) fn launched() -> Option<Game> {
+        let app_id = var_os("STEAM_COMPAT_APP_ID").or_else(|| var_os("SteamGameId"))?;
+        Game::from_app_id(app_id.to_str()?)
+    }
+}
+
+impl FromStr for Game {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "poe1" | "poe" => Ok(Game::Poe1),
+            "poe2" => Ok(Game::Poe2),
+            _ => bail!("unknown game {value:?}, expected poe1 or poe2"),
+        }
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Game::Poe1 => write!(f, "poe1"),
+            Game::Poe2 => write!(f, "poe2"),
+        }
+    }
+}
+
+/// The game to install filters for: the configured one, otherwise the one
+/// that Steam is launching, and Path of Exile 2 by default.
+pub fn game(options: &Options) -> Game {
+    options.game.or_else(Game::launched).unwrap_or_default()
+}
@@ -1,24 +1,335 @@
 use std::{
-    ffi::OsString,
-    io::{Cursor, Read as _},
+    collections::HashMap,
     path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{split, Globals, VersionInfo};
-use color_eyre::{eyre::bail, Result};
-use log::info;
-use reqwest::header::HeaderValue;
-use serde::Deserialize;
-use tokio::{fs, io::AsyncWriteExt as _};
+use crate::{
+    archive, cache, checksum,
+    http::{self, Validators},
+    split, Error, Globals, Options, Source, VersionInfo,
+};
+use color_eyre::{
+    eyre::{bail, Context, Report},
+    Result,
+};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use log::{debug, info, warn};
+use reqwest::{
+    header::{HeaderValue, RETRY_AFTER},
+    RequestBuilder, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{sync::OnceCell, time::sleep};
 
 static API_VERSION: HeaderValue = HeaderValue::from_static("2022-11-28");
 static API_JSON_TYPE: HeaderValue = HeaderValue::from_static("application/vnd.github+json");
 
+const GRAPHQL_URL: &str = "https://api.github.com/graphql";
+
+/// The longest wait for GitHub's secondary rate limit before giving up.
+const MAX_SECONDARY_WAIT: Duration = Duration::from_secs(120);
+
+/// A GET request, authenticated with the GitHub token if there is one.
+fn request(globals: &Globals, url: &str) -> RequestBuilder {
+    let request = globals.client.get(url);
+    match &globals.github_token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// A GET request to the REST API.
+fn api_request(globals: &Globals, url: &str) -> RequestBuilder {
+    request(globals, url)
+        .header("X-Github-Api-Version", API_VERSION.clone())
+        .header("Accept", API_JSON_TYPE.clone())
+}
+
+/// When the rate limit resets (as a Unix timestamp), once it has been hit.
+pub type RateLimitReset = Arc<AtomicU64>;
+
+/// Responses and downloads of this run, so that sources that point at the same
+/// repository (e.g. an alias and its `github:` form) don't repeat requests.
+/// Concurrent requests for the same URL wait for the first one. Failures
+/// aren't remembered.
+#[derive(Debug, Clone, Default)]
+pub struct RunCache {
+    /// API responses by URL and the validators they were requested with.
+    responses: Arc<Mutex<Responses>>,
+    /// Downloaded archives by URL.
+    archives: Arc<Mutex<HashMap<String, Arc<OnceCell<PathBuf>>>>>,
+}
+
+/// The body of a response and its validators, `None` if it was not modified.
+type CachedResponse = Option<(String, Validators)>;
+type Responses = HashMap<(String, Option<Validators>), Arc<OnceCell<CachedResponse>>>;
+
+impl RunCache {
+    fn response(
+        &self,
+        url: &str,
+        validators: Option<&Validators>,
+    ) -> Arc<OnceCell<CachedResponse>> {
+        let mut responses = self.responses.lock().unwrap_or_else(|v| v.into_inner());
+        responses
+            .entry((url.to_string(), validators.cloned()))
+            .or_default()
+            .clone()
+    }
+
+    pub(crate) fn archive(&self, url: &str) -> Arc<OnceCell<PathBuf>> {
+        let mut archives = self.archives.lock().unwrap_or_else(|v| v.into_inner());
+        archives.entry(url.to_string()).or_default().clone()
+    }
+}
+
+/// Fetches an API response. If it was fetched before and the watermark found
+/// in it is still the `existing` one, the request is made conditional, and
+/// `None` is returned if nothing changed. Such requests don't count against
+/// the rate limit.
+async fn fetch_json<T: DeserializeOwned>(
+    globals: &Globals,
+    url: &str,
+    existing: Option<&String>,
+) -> Result<Option<(T, Validators)>> {
+    let cached = http::cached_validators(globals, url, existing);
+    let cell = globals.run_cache.response(url, cached.as_ref());
+    if cell.initialized() {
+        globals.counters.cache_hit();
+    }
+    let response = cell
+        .get_or_try_init(|| async {
+            let mut request = api_request(globals, url);
+            if let Some(validators) = &cached {
+                request = validators.apply(request);
+            }
+
+            let response = send(globals, request).await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                debug!("{url} was not modified");
+                globals.counters.cache_hit();
+                return Ok::<_, Report>(None);
+            }
+
+            let response = response.error_for_status()?;
+            let validators = Validators::from_response(&response);
+            Ok(Some((response.text().await?, validators)))
+        })
+        .await?;
+
+    match response {
+        Some((body, validators)) => Ok(Some((serde_json::from_str(body)?, validators.clone()))),
+        None => Ok(None),
+    }
+}
+
+/// Sends a request to GitHub, handling both kinds of rate limit. When the
+/// secondary rate limit is hit and nothing is waiting on the update, the
+/// request is retried after the time GitHub asks for, up to `retries` times.
+async fn send(globals: &Globals, request: RequestBuilder) -> Result<Response> {
+    let mut waits = 0;
+    loop {
+        check_rate_limit(globals)?;
+        let Some(attempt) = request.try_clone() else {
+            bail!("could not clone the request");
+        };
+        let response = http::send(&*globals.transport, attempt, globals.retries).await?;
+        match retry_after(&response) {
+            Some(wait)
+                if globals.can_wait && wait <= MAX_SECONDARY_WAIT && waits < globals.retries =>
+            {
+                waits += 1;
+                warn!(
+                    "hit GitHub's secondary rate limit, waiting {} second(s)",
+                    wait.as_secs()
+                );
+                sleep(wait).await;
+            }
+            Some(wait) => bail!(Error::RateLimited {
+                message: secondary_rate_limit_message(wait),
+                reset: Some(unix_time() + wait.as_secs()),
+            }),
+            None => {
+                handle_rate_limit(globals, &response)?;
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Fails right away once the rate limit has been hit, so that the remaining
+/// GitHub sources are skipped instead of each making a failing request.
+fn check_rate_limit(globals: &Globals) -> Result<()> {
+    let reset = globals.rate_limit_reset.load(Ordering::Relaxed);
+    if reset > unix_time() {
+        bail!(Error::RateLimited {
+            message: rate_limit_message(globals, reset),
+            reset: Some(reset),
+        });
+    }
+    Ok(())
+}
+
+/// Looks at the rate limit headers, and turns the opaque 403 (or 429) that
+/// GitHub responds with once the limit is exhausted into a useful error.
+fn handle_rate_limit(globals: &Globals, response: &Response) -> Result<()> {
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+    };
+
+    let remaining = header("x-ratelimit-remaining");
+    if let Some(remaining) = remaining {
+        debug!("{remaining} GitHub requests remaining");
+    }
+
+    let status = response.status();
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && remaining == Some(0));
+    if !limited {
+        return Ok(());
+    }
+
+    let reset = header("x-ratelimit-reset").unwrap_or_else(|| unix_time() + 60);
+    globals.rate_limit_reset.store(reset, Ordering::Relaxed);
+    bail!(Error::RateLimited {
+        message: rate_limit_message(globals, reset),
+        reset: Some(reset),
+    })
+}
+
+fn rate_limit_message(globals: &Globals, reset: u64) -> String {
+    let minutes = reset.saturating_sub(unix_time()).div_ceil(60);
+    let mut message =
+        format!("the GitHub rate limit was exceeded, it resets in {minutes} minute(s)");
+    if globals.github_token.is_none() {
+        message.push_str(
+            ", set GITHUB_TOKEN (or --github-token) to a personal access token to raise the limit",
+        );
+    }
+    message
+}
+
+/// How long GitHub asks to wait when its secondary rate limit (for too many
+/// concurrent or too frequent requests) was hit. These responses are a 403 or
+/// 429 with a `Retry-After` header.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+fn secondary_rate_limit_message(wait: Duration) -> String {
+    format!(
+        "hit GitHub's secondary rate limit (too many requests in a short time), try again in {} second(s)",
+        wait.as_secs()
+    )
+}
+
+/// Prints the remaining GitHub rate limit, for `poe2filter ratelimit`.
+/// Checking it doesn't count against the limit.
+pub async fn print_rate_limit(options: &Options) -> Result<()> {
+    let client = http::client(options)?;
+    let mut request = client
+        .get("https://api.github.com/rate_limit")
+        .header("X-Github-Api-Version", API_VERSION.clone())
+        .header("Accept", API_JSON_TYPE.clone());
+    if let Some(token) = &options.github_token {
+        request = request.bearer_auth(token);
+    }
+
+    let limits: RateLimits = http::send(&client, request, options.retries)
+        .await?
+        .error_for_status()
+        .wrap_err("could not get the rate limit from GitHub")?
+        .json()
+        .await?;
+
+    match &options.github_token {
+        Some(_) => println!("GitHub rate limit (with token):"),
+        None => println!("GitHub rate limit (without token):"),
+    }
+    for (name, limit) in [
+        ("core", &limits.resources.core),
+        ("search", &limits.resources.search),
+        ("graphql", &limits.resources.graphql),
+    ] {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let minutes = limit.reset.saturating_sub(unix_time()).div_ceil(60);
+        println!(
+            "  {name}: {}/{} remaining, resets in {minutes} minute(s)",
+            limit.remaining, limit.limit
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimits {
+    resources: RateLimitResources,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitResources {
+    core: Option<RateLimit>,
+    search: Option<RateLimit>,
+    graphql: Option<RateLimit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimit {
+    limit: u64,
+    remaining: u64,
+    reset: u64,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Downloads a (small) release asset.
+async fn fetch_asset(globals: &Globals, url: &str) -> Result<String> {
+    let request = request(globals, url).header("Accept", "application/octet-stream");
+    let response = send(globals, request).await?;
+    Ok(response.error_for_status()?.text().await?)
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct ReleaseInfo {
     zipball_url: String,
     tag_name: String,
     body: Option<String>,
+    #[serde(default)]
+    assets: Vec<AssetInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AssetInfo {
+    name: String,
+    /// The API URL of the asset, which (unlike the browser download URL) also
+    /// works for private repositories.
+    url: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -37,79 +348,230 @@ struct CommitMeta {
     message: String,
 }
 
-pub async fn get(
-    globals: &Globals,
-    value: &str,
-    existing: Option<&String>,
-) -> Result<Option<VersionInfo>> {
-    let parts = split(value, '/');
-    let release = match parts.as_slice() {
-        [owner, repo] => get_github_release(globals, owner, repo).await?,
-        [owner, repo, branch] => get_github_branch(globals, owner, repo, branch).await?,
-        _ => bail!("github source must be either github:owner/repo or github:owner/repo/branch"),
-    };
+/// Looks up the latest watermark of several sources (`owner/repo` or
+/// `owner/repo/branch`) with a single GraphQL request, instead of one REST
+/// request each. Only sources that are found are returned, the others are
+/// looked up as usual. GraphQL requires a token, so without one nothing is
+/// returned.
+pub async fn latest_watermarks(globals: &Globals, values: &[&str]) -> HashMap<String, String> {
+    if globals.github_token.is_none() || values.len() < 2 {
+        return HashMap::new();
+    }
 
-    let Some(release) = release else {
-        return Ok(None);
-    };
+    match batch_lookup(globals, values).await {
+        Ok(latest) => latest,
+        Err(error) => {
+            warn!("could not look up the GitHub sources at once: {error:?}");
+            HashMap::new()
+        }
+    }
+}
 
-    info!("found release with watermark: {}", release.watermark);
+async fn batch_lookup(globals: &Globals, values: &[&str]) -> Result<HashMap<String, String>> {
+    let mut declarations = Vec::new();
+    let mut fields = Vec::new();
+    let mut variables = serde_json::Map::new();
+    let mut aliases = HashMap::new();
 
-    if existing == Some(&release.watermark) {
-        return Ok(None);
+    for (index, value) in values.iter().enumerate() {
+        let (owner, repo, branch) = match split(value, '/').as_slice() {
+            [owner, repo] => (*owner, *repo, None),
+            [owner, repo, branch] => (*owner, *repo, Some(*branch)),
+            _ => continue,
+        };
+
+        declarations.push(format!("$o{index}: String!, $r{index}: String!"));
+        variables.insert(format!("o{index}"), owner.into());
+        variables.insert(format!("r{index}"), repo.into());
+        let selection = match branch {
+            Some(branch) => {
+                declarations.push(format!("$b{index}: String!"));
+                variables.insert(format!("b{index}"), format!("refs/heads/{branch}").into());
+                format!("ref(qualifiedName: $b{index}) {{ target {{ oid }} }}")
+            }
+            // The same ordering as the REST endpoint that is used otherwise.
+            None => "releases(first: 1, orderBy: {field: CREATED_AT, direction: DESC}) \
+                     { nodes { tagName } }"
+                .to_string(),
+        };
+        fields.push(format!(
+            "s{index}: repository(owner: $o{index}, name: $r{index}) {{ {selection} }}"
+        ));
+        aliases.insert(format!("s{index}"), value.to_string());
     }
 
-    info!("downloading release zipball");
-    let zipball = globals
-        .client
-        .get(&release.zipball_url)
-        .send()
+    let query = format!(
+        "query({}) {{ {} }}",
+        declarations.join(", "),
+        fields.join(" ")
+    );
+    debug!(
+        "looking up {} GitHub sources with one request",
+        aliases.len()
+    );
+    let request = request(globals, GRAPHQL_URL)
+        .header("X-Github-Api-Version", API_VERSION.clone())
+        .json(&serde_json::json!({ "query": query, "variables": variables }));
+    let response: GraphqlResponse = send(globals, request)
         .await?
         .error_for_status()?
-        .bytes()
-        .await?
-        .to_vec();
+        .json()
+        .await?;
 
-    info!("opening release zipball");
-    let mut zipfile = zip::ZipArchive::new(Cursor::new(zipball))?;
-    let filter = OsString::from("filter");
-    let filenames: Vec<_> = zipfile.file_names().map(|v| v.to_string()).collect();
-    let mut file_data = Vec::new();
+    for error in &response.errors {
+        // A missing repository or branch, reported again by the REST request.
+        debug!("GraphQL error: {}", error.message);
+    }
 
-    for filename in filenames {
-        let path = PathBuf::from(&filename);
-        if Some(filter.as_os_str()) != path.extension() {
+    let mut latest = HashMap::new();
+    for (alias, repository) in response.data.unwrap_or_default() {
+        let (Some(value), Some(repository)) = (aliases.remove(&alias), repository) else {
             continue;
+        };
+        let watermark = match repository {
+            GraphqlRepository::Branch { r#ref } => r#ref.map(|v| v.target.oid),
+            GraphqlRepository::Release { releases } => {
+                releases.nodes.into_iter().next().map(|v| v.tag_name)
+            }
+        };
+        if let Some(watermark) = watermark {
+            latest.insert(value, watermark);
         }
+    }
+    Ok(latest)
+}
 
-        info!("extracting {filename}");
-        let mut file = zipfile.by_name(&filename)?;
-        file_data.clear();
-        file.read_to_end(&mut file_data)?;
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse {
+    data: Option<HashMap<String, Option<GraphqlRepository>>>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
 
-        let Some(filename) = PathBuf::from(&filename)
-            .file_name()
-            .map(|v| v.to_os_string())
-        else {
-            // Not really possible, but avoid panicking
-            continue;
-        };
+#[derive(Debug, Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum GraphqlRepository {
+    // Has to come first, `ref` being optional would match anything.
+    Release { releases: GraphqlReleases },
+    Branch { r#ref: Option<GraphqlRef> },
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlRef {
+    target: GraphqlTarget,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlTarget {
+    oid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlReleases {
+    nodes: Vec<GraphqlRelease>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRelease {
+    tag_name: String,
+}
+
+/// Releases (`github:owner/repo`) and branches (`github:owner/repo/branch`)
+/// of GitHub repositories.
+pub struct GitHub;
+
+impl Source for GitHub {
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>> {
+        resolve(globals, value, existing)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>> {
+        let cache_key = format!("github:{value}");
+        async move { archive::fetch(globals, self, &cache_key, version).await }
+            .map_err(Error::from)
+            .boxed()
+    }
+}
 
-        let full_path = globals.game_directory.join(&filename);
+impl archive::Host for GitHub {
+    fn archive_request(&self, globals: &Globals, url: &str) -> Result<RequestBuilder> {
+        check_rate_limit(globals)?;
+        Ok(request(globals, url))
+    }
+
+    fn check(&self, globals: &Globals, response: &Response) -> Result<()> {
+        match retry_after(response) {
+            Some(wait) => bail!(Error::RateLimited {
+                message: secondary_rate_limit_message(wait),
+                reset: Some(unix_time() + wait.as_secs()),
+            }),
+            None => handle_rate_limit(globals, response),
+        }
+    }
 
-        info!("writing {full_path:?}");
-        let mut dest = fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(full_path)
-            .await?;
+    fn fetch_asset<'a>(
+        &'a self,
+        globals: &'a Globals,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<String>> {
+        fetch_asset(globals, url).boxed()
+    }
+}
+
+async fn resolve(
+    globals: &Globals,
+    value: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let cache_key = format!("github:{value}");
+    if globals.offline {
+        return cache::cached_version(globals, &cache_key, existing).await;
+    }
 
-        dest.write_all(&file_data).await?;
+    if existing.is_some() && existing == globals.latest.get(value) {
+        debug!("{cache_key} is unchanged according to the batched lookup");
+        return Ok(None);
     }
 
-    info!("updated github:{value}");
+    let parts = split(value, '/');
+    let release = match parts.as_slice() {
+        [owner, repo] => latest_release(globals, owner, repo, existing).await?,
+        [_, _, _] if globals.signing_keys.contains_key(&cache_key) => {
+            bail!("only releases can be signed, not branches")
+        }
+        [owner, repo, branch] => get_github_branch(globals, owner, repo, branch, existing).await?,
+        _ => bail!(Error::InvalidSourceSpec(
+            "github source must be either github:owner/repo or github:owner/repo/branch".into()
+        )),
+    };
+
+    let Some(release) = release else {
+        return Ok(None);
+    };
+
+    info!("found release with watermark: {}", release.watermark);
 
+    if existing == Some(&release.watermark) {
+        return Ok(None);
+    }
     Ok(Some(release))
 }
 
@@ -118,23 +580,20 @@ async fn get_github_branch(
     owner: &str,
     repo: &str,
     branch: &str,
+    existing: Option<&String>,
 ) -> Result<Option<VersionInfo>> {
     info!("fetching latest commit");
-    let release = globals
-        .client
-        .get(format!(
-            "https://api.github.com/repos/{owner}/{repo}/branches/{branch}"
-        ))
-        .header("X-Github-Api-Version", API_VERSION.clone())
-        .header("Accept", API_JSON_TYPE.clone())
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<BranchInfo>()
-        .await?;
+    let url = format!("https://api.github.com/repos/{owner}/{repo}/branches/{branch}");
+    let Some((release, validators)) = fetch_json::<BranchInfo>(globals, &url, existing).await?
+    else {
+        return Ok(None);
+    };
+    http::remember_validators(globals, url, validators, &release.commit.sha);
 
+    // The API endpoint (rather than github.com/.../archive) also works for
+    // private repositories when a token is set.
     let zipball_url = format!(
-        "https://github.com/{owner}/{repo}/archive/{}.zip",
+        "https://api.github.com/repos/{owner}/{repo}/zipball/{}",
         release.commit.sha
     );
 
@@ -142,35 +601,52 @@ async fn get_github_branch(
         zipball_url,
         watermark: release.commit.sha,
         body: Some(release.commit.commit.message),
+        files: Vec::new(),
+        checksums_url: None,
+        signature_url: None,
     }))
 }
 
-async fn get_github_release(
+/// Fetches the latest release of a repository, `None` if there is none or
+/// it is still the `existing` one.
+pub async fn latest_release(
     globals: &Globals,
     owner: &str,
     repo: &str,
+    existing: Option<&String>,
 ) -> Result<Option<VersionInfo>> {
     info!("fetching latest release");
-    let releases = globals
-        .client
-        .get(format!(
-            "https://api.github.com/repos/{owner}/{repo}/releases?per_page=1&page=0"
-        ))
-        .header("X-Github-Api-Version", API_VERSION.clone())
-        .header("Accept", API_JSON_TYPE.clone())
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<Vec<ReleaseInfo>>()
-        .await?;
+    let url = format!("https://api.github.com/repos/{owner}/{repo}/releases?per_page=1&page=0");
+    let Some((releases, validators)) =
+        fetch_json::<Vec<ReleaseInfo>>(globals, &url, existing).await?
+    else {
+        return Ok(None);
+    };
 
     let Some(release) = releases.into_iter().next() else {
         return Ok(None);
     };
+    http::remember_validators(globals, url, validators, &release.tag_name);
+
+    let checksums = release
+        .assets
+        .iter()
+        .find(|asset| checksum::CHECKSUM_ASSETS.contains(&asset.name.as_str()));
+    let signature_url = checksums.and_then(|checksums| {
+        let name = format!("{}.minisig", checksums.name);
+        release.assets.iter().find(|asset| asset.name == name)
+    });
+    let (checksums_url, signature_url) = (
+        checksums.map(|v| v.url.clone()),
+        signature_url.map(|v| v.url.clone()),
+    );
 
     Ok(Some(VersionInfo {
         zipball_url: release.zipball_url,
         watermark: release.tag_name,
         body: release.body,
+        files: Vec::new(),
+        checksums_url,
+        signature_url,
     }))
 }
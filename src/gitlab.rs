@@ -0,0 +1,271 @@
+//! Releases (`gitlab:owner/repo`) and branches (`gitlab:owner/repo/branch`)
+//! of GitLab repositories, through the GitLab API.
+
+use std::path::PathBuf;
+
+use color_eyre::{eyre::bail, Result};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use log::{debug, info};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    archive, cache, checksum,
+    http::{self, Validators},
+    split, Error, Globals, Source, VersionInfo,
+};
+
+const API_URL: &str = "https://gitlab.com/api/v4";
+
+/// A GET request, authenticated with the GitLab token if there is one and the
+/// URL is on GitLab itself: release links can point anywhere. The token is
+/// sent as a bearer token, which (unlike `PRIVATE-TOKEN`) is dropped when a
+/// request is redirected to another host.
+fn request(globals: &Globals, url: &str) -> RequestBuilder {
+    let request = globals.client.get(url);
+    match &globals.gitlab_token {
+        Some(token) if is_api_url(url) => request.bearer_auth(token),
+        _ => request,
+    }
+}
+
+/// Whether the URL is on the same host as the GitLab API.
+fn is_api_url(url: &str) -> bool {
+    let (Ok(url), Ok(api)) = (Url::parse(url), Url::parse(API_URL)) else {
+        return false;
+    };
+    url.origin() == api.origin()
+}
+
+/// The API URL of a project, which is addressed by its encoded path.
+fn project_url(owner: &str, repo: &str) -> String {
+    let path = format!("{owner}/{repo}");
+    format!(
+        "{API_URL}/projects/{}",
+        utf8_percent_encode(&path, NON_ALPHANUMERIC)
+    )
+}
+
+/// Fetches an API response, conditionally if it was fetched before and the
+/// watermark found in it is still the `existing` one. `None` is returned if
+/// nothing changed.
+async fn fetch_json<T: DeserializeOwned>(
+    globals: &Globals,
+    url: &str,
+    existing: Option<&String>,
+) -> Result<Option<(T, Validators)>> {
+    let mut request = request(globals, url);
+    if let Some(validators) = http::cached_validators(globals, url, existing) {
+        request = validators.apply(request);
+    }
+
+    let response = http::send(&*globals.transport, request, globals.retries).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        debug!("{url} was not modified");
+        globals.counters.cache_hit();
+        return Ok(None);
+    }
+
+    let response = response.error_for_status()?;
+    let validators = Validators::from_response(&response);
+    Ok(Some((response.json().await?, validators)))
+}
+
+/// Downloads a (small) release asset.
+async fn fetch_asset(globals: &Globals, url: &str) -> Result<String> {
+    let response = http::send(&*globals.transport, request(globals, url), globals.retries).await?;
+    Ok(response.error_for_status()?.text().await?)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReleaseInfo {
+    tag_name: String,
+    description: Option<String>,
+    #[serde(default)]
+    assets: ReleaseAssets,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ReleaseAssets {
+    #[serde(default)]
+    links: Vec<AssetLink>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AssetLink {
+    name: String,
+    url: String,
+    /// The permanent URL of the asset, when it was uploaded to the release.
+    direct_asset_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BranchInfo {
+    commit: CommitInfo,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CommitInfo {
+    id: String,
+    message: String,
+}
+
+pub struct GitLab;
+
+impl Source for GitLab {
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>> {
+        resolve(globals, value, existing)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>> {
+        let cache_key = format!("gitlab:{value}");
+        async move { archive::fetch(globals, self, &cache_key, version).await }
+            .map_err(Error::from)
+            .boxed()
+    }
+}
+
+impl archive::Host for GitLab {
+    fn archive_request(&self, globals: &Globals, url: &str) -> Result<RequestBuilder> {
+        Ok(request(globals, url))
+    }
+
+    fn check(&self, _globals: &Globals, _response: &Response) -> Result<()> {
+        Ok(())
+    }
+
+    fn fetch_asset<'a>(
+        &'a self,
+        globals: &'a Globals,
+        url: &'a str,
+    ) -> BoxFuture<'a, Result<String>> {
+        fetch_asset(globals, url).boxed()
+    }
+}
+
+async fn resolve(
+    globals: &Globals,
+    value: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let cache_key = format!("gitlab:{value}");
+    if globals.offline {
+        return cache::cached_version(globals, &cache_key, existing).await;
+    }
+
+    let parts = split(value, '/');
+    let version = match parts.as_slice() {
+        [owner, repo] => latest_release(globals, owner, repo, existing).await?,
+        [_, _, _] if globals.signing_keys.contains_key(&cache_key) => {
+            bail!("only releases can be signed, not branches")
+        }
+        [owner, repo, branch] => latest_commit(globals, owner, repo, branch, existing).await?,
+        _ => bail!(Error::InvalidSourceSpec(
+            "gitlab source must be either gitlab:owner/repo or gitlab:owner/repo/branch".into()
+        )),
+    };
+
+    let Some(version) = version else {
+        return Ok(None);
+    };
+    info!("found release with watermark: {}", version.watermark);
+
+    if existing == Some(&version.watermark) {
+        return Ok(None);
+    }
+    Ok(Some(version))
+}
+
+/// Fetches the latest release of a project, `None` if there is none or it is
+/// still the `existing` one.
+async fn latest_release(
+    globals: &Globals,
+    owner: &str,
+    repo: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    info!("fetching latest release");
+    let project = project_url(owner, repo);
+    let url = format!("{project}/releases?per_page=1");
+    let Some((releases, validators)) =
+        fetch_json::<Vec<ReleaseInfo>>(globals, &url, existing).await?
+    else {
+        return Ok(None);
+    };
+
+    let Some(release) = releases.into_iter().next() else {
+        return Ok(None);
+    };
+    http::remember_validators(globals, url, validators, &release.tag_name);
+
+    let links = &release.assets.links;
+    let checksums = links
+        .iter()
+        .find(|link| checksum::CHECKSUM_ASSETS.contains(&link.name.as_str()));
+    let signature = checksums.and_then(|checksums| {
+        let name = format!("{}.minisig", checksums.name);
+        links.iter().find(|link| link.name == name)
+    });
+    let asset_url = |link: &AssetLink| link.direct_asset_url.clone().unwrap_or(link.url.clone());
+
+    Ok(Some(VersionInfo {
+        zipball_url: archive_url(&project, &release.tag_name),
+        watermark: release.tag_name.clone(),
+        body: release.description,
+        files: Vec::new(),
+        checksums_url: checksums.map(asset_url),
+        signature_url: signature.map(asset_url),
+    }))
+}
+
+/// Fetches the latest commit on a branch.
+async fn latest_commit(
+    globals: &Globals,
+    owner: &str,
+    repo: &str,
+    branch: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    info!("fetching latest commit");
+    let project = project_url(owner, repo);
+    let url = format!(
+        "{project}/repository/branches/{}",
+        utf8_percent_encode(branch, NON_ALPHANUMERIC)
+    );
+    let Some((branch, validators)) = fetch_json::<BranchInfo>(globals, &url, existing).await?
+    else {
+        return Ok(None);
+    };
+    http::remember_validators(globals, url, validators, &branch.commit.id);
+
+    Ok(Some(VersionInfo {
+        zipball_url: archive_url(&project, &branch.commit.id),
+        watermark: branch.commit.id,
+        body: Some(branch.commit.message),
+        files: Vec::new(),
+        checksums_url: None,
+        signature_url: None,
+    }))
+}
+
+/// The repository archive API, which (unlike the web URL) also works for
+/// private projects when a token is set.
+fn archive_url(project: &str, sha: &str) -> String {
+    format!(
+        "{project}/repository/archive.zip?sha={}",
+        utf8_percent_encode(sha, NON_ALPHANUMERIC)
+    )
+}
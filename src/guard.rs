@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+#[cfg(target_os = "linux")]
+use color_eyre::{eyre::Context, Result};
+#[cfg(target_os = "linux")]
+use log::{debug, info, warn};
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+/// The guarded files by path and their last known contents, `None` unless
+/// the guardian runs.
+type Guarded = Option<HashMap<PathBuf, Vec<u8>>>;
+
+static GUARDED: Mutex<Guarded> = Mutex::new(None);
+
+/// Keeps the guardian from acting while the installer puts files in place or
+/// removes them, which would otherwise look like they were deleted behind
+/// our back. The installer tells it what changed before letting go.
+pub(crate) struct Pause(MutexGuard<'static, Guarded>);
+
+pub(crate) fn pause() -> Pause {
+    Pause(GUARDED.lock().unwrap_or_else(|v| v.into_inner()))
+}
+
+impl Pause {
+    /// A file that was put in place, and is guarded from now on.
+    pub(crate) fn installed(&mut self, path: &Path) {
+        if let Some(files) = &mut *self.0 {
+            if let Ok(data) = fs::read(path) {
+                files.insert(path.to_path_buf(), data);
+            }
+        }
+    }
+
+    /// A file that is being removed, and no longer guarded.
+    pub(crate) fn removed(&mut self, path: &Path) {
+        if let Some(files) = &mut *self.0 {
+            files.remove(path);
+        }
+    }
+}
+
+/// Watches the installed filters and restores the ones that get deleted or
+/// truncated behind our back, e.g. by an overzealous cleanup or cloud sync
+/// tool.
+///
+/// Only the files that are recorded as installed are guarded. They are
+/// remembered when the guardian starts and whenever they are written to
+/// afterwards, which includes our own updates.
+#[cfg(target_os = "linux")]
+pub fn spawn(game_directory: PathBuf) -> Result<()> {
+    let inotify =
+        Inotify::init(InitFlags::IN_CLOEXEC).wrap_err_with(|| "could not init inotify")?;
+
+    let store = crate::block_on(crate::store::read(&game_directory));
+    let mut files = HashMap::new();
+    for file in store.into_values().flat_map(|v| v.files) {
+        let path = game_directory.join(file);
+        if let Ok(data) = fs::read(&path) {
+            files.insert(path, data);
+        }
+    }
+
+    let mut directories = HashMap::new();
+    for directory in files.keys().filter_map(|v| v.parent()) {
+        if directories.values().any(|v| v == directory) {
+            continue;
+        }
+        let descriptor = inotify
+            .add_watch(
+                directory,
+                AddWatchFlags::IN_CLOSE_WRITE
+                    | AddWatchFlags::IN_MOVED_TO
+                    | AddWatchFlags::IN_MOVED_FROM
+                    | AddWatchFlags::IN_DELETE,
+            )
+            .wrap_err_with(|| format!("could not watch {directory:?}"))?;
+        directories.insert(descriptor, directory.to_path_buf());
+    }
+
+    info!("guarding {} filter(s)", files.len());
+    *pause().0 = Some(files);
+    std::thread::Builder::new()
+        .name("guard".to_string())
+        .spawn(move || guard(inotify, &game_directory, directories))?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn guard(inotify: Inotify, game_directory: &Path, directories: HashMap<WatchDescriptor, PathBuf>) {
+    loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(error) => {
+                warn!("stopped guarding filters: {error}");
+                return;
+            }
+        };
+
+        for event in events {
+            let (Some(directory), Some(name)) = (directories.get(&event.wd), event.name) else {
+                continue;
+            };
+            let path = directory.join(name);
+
+            // Whatever happened, the file is checked as it is now: an update
+            // moves the previous version away before the new one is put in
+            // place, and is done by the time the pause is over.
+            let mut guarded = pause();
+            let Some(files) = &mut *guarded.0 else {
+                return;
+            };
+            let Some(remembered) = files.get_mut(&path) else {
+                continue;
+            };
+
+            match fs::read(&path) {
+                Ok(data) if !data.is_empty() => {
+                    debug!("remembering new contents of {path:?}");
+                    *remembered = data;
+                }
+                current => {
+                    if remembered.is_empty() {
+                        continue;
+                    }
+
+                    let what = if current.is_ok() {
+                        "truncated"
+                    } else {
+                        "deleted"
+                    };
+                    warn!("{path:?} was {what}, restoring it");
+                    let directory = match path.starts_with(game_directory) {
+                        true => game_directory,
+                        false => directory,
+                    };
+                    let restored =
+                        crate::block_on(crate::install::write_file(directory, &path, remembered));
+                    if let Err(error) = restored {
+                        warn!("could not restore {path:?}: {error:#}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{collections::HashMap, fs, thread::sleep, time::Duration};
+
+    use crate::{block_on, install::Transaction, releases_file, store};
+
+    #[test]
+    fn only_restores_what_the_installer_did_not_change() {
+        let directory =
+            std::env::temp_dir().join(format!("poe2filter-guard-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (updated, orphan, unmanaged) = (
+            directory.join("updated.filter"),
+            directory.join("orphan.filter"),
+            directory.join("unmanaged.filter"),
+        );
+        fs::write(&updated, "Show # old").unwrap();
+        fs::write(&orphan, "Show # orphan").unwrap();
+        fs::write(&unmanaged, "Show # unmanaged").unwrap();
+        fs::write(
+            releases_file(&directory),
+            r#"{"a": {"watermark": "1", "files": ["updated.filter", "orphan.filter"]}}"#,
+        )
+        .unwrap();
+
+        super::spawn(directory.clone()).unwrap();
+
+        let mut transaction = Transaction::new(&directory);
+        transaction
+            .write_file_with(&updated, |dest| Ok(dest.write_all(b"Show # new")?))
+            .unwrap();
+        transaction.commit().unwrap();
+        block_on(store::remove_files(
+            &directory,
+            &["orphan.filter".into()],
+            &HashMap::new(),
+        ));
+        fs::remove_file(&unmanaged).unwrap();
+        sleep(Duration::from_millis(500));
+
+        assert_eq!(fs::read_to_string(&updated).unwrap(), "Show # new");
+        assert!(!orphan.exists());
+        assert!(!unmanaged.exists());
+
+        fs::remove_file(&updated).unwrap();
+        sleep(Duration::from_millis(500));
+        assert_eq!(fs::read_to_string(&updated).unwrap(), "Show # new");
+
+        // Otherwise cleaning up restores the file again.
+        *super::pause().0 = None;
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
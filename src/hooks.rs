@@ -0,0 +1,114 @@
+use std::{env::var, time::Duration};
+
+use log::{info, warn};
+use tokio::{process::Command, time::timeout};
+
+use crate::Summary;
+
+/// User-provided shell commands that are run at various points.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    /// Run once the game directory is known, before any source is updated.
+    pub pre_update: Option<String>,
+    /// Run after all sources have been updated and the watermarks saved.
+    pub post_update: Option<String>,
+    /// Run right before the command is executed.
+    pub pre_launch: Option<String>,
+    /// Run after the command exits, only with `--wait`.
+    pub post_game: Option<String>,
+}
+
+impl Hooks {
+    pub fn from_env() -> Self {
+        Hooks {
+            pre_update: var("POE2FILTER_PRE_UPDATE").ok(),
+            post_update: var("POE2FILTER_POST_UPDATE").ok(),
+            pre_launch: var("POE2FILTER_PRE_LAUNCH").ok(),
+            post_game: var("POE2FILTER_POST_GAME").ok(),
+        }
+    }
+
+    /// These hooks, with the ones that aren't set taken from `other`.
+    pub fn or(self, other: &Hooks) -> Self {
+        Hooks {
+            pre_update: self.pre_update.or(other.pre_update.clone()),
+            post_update: self.post_update.or(other.post_update.clone()),
+            pre_launch: self.pre_launch.or(other.pre_launch.clone()),
+            post_game: self.post_game.or(other.post_game.clone()),
+        }
+    }
+}
+
+/// The environment given to `post_update`, every list is newline separated.
+pub fn summary_env(game_directory: &str, summary: &Summary) -> Vec<(&'static str, String)> {
+    let updated: Vec<_> = summary
+        .updated
+        .iter()
+        .map(|update| format!("{}={}", update.source, update.watermark))
+        .collect();
+    let files: Vec<_> = summary
+        .updated
+        .iter()
+        .flat_map(|update| update.files.iter())
+        .map(|file| file.to_string_lossy())
+        .collect();
+    let failed: Vec<_> = summary
+        .failed
+        .iter()
+        .map(|(source, _)| source.as_str())
+        .collect();
+
+    vec![
+        ("POE2FILTER_GAME_DIR", game_directory.to_string()),
+        ("POE2FILTER_UPDATED", updated.join("\n")),
+        ("POE2FILTER_FILES", files.join("\n")),
+        ("POE2FILTER_FAILED", failed.join("\n")),
+    ]
+}
+
+/// How long a hook may run before it is killed, so that a hanging hook can't
+/// hold up the update or the game forever.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A command that runs `command` with the system shell: `sh -c`, or `cmd /C`
+/// on Windows.
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Runs a hook with the system shell. Hooks are best-effort, a failing hook
+/// is logged but never stops the update or the launch. A hook that takes
+/// longer than a minute is killed, as is one that is still running when this
+/// future is dropped.
+pub async fn run(name: &str, command: Option<&str>, env: &[(&str, String)]) {
+    let Some(command) = command else {
+        return;
+    };
+
+    info!("running {name} hook: {command}");
+    let child = shell(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(error) => return warn!("could not run {name} hook: {error}"),
+    };
+
+    match timeout(HOOK_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => warn!("{name} hook failed with {status}"),
+        Ok(Err(error)) => warn!("could not run {name} hook: {error}"),
+        Err(_) => warn!("{name} hook took longer than {HOOK_TIMEOUT:?}, killed it"),
+    }
+}
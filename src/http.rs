@@ -0,0 +1,738 @@
+//! The HTTP client, and sending requests with retries.
+
+use std::{
+    collections::HashMap,
+    env::var_os,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Report, Result,
+};
+use futures_util::{future::BoxFuture, FutureExt as _};
+use log::{info, warn};
+use rand::Rng as _;
+use reqwest::{
+    header::{
+        HeaderName, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+        RANGE,
+    },
+    Certificate, Client, ClientBuilder, NoProxy, Proxy, Request, RequestBuilder, Response,
+    StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt as _, time::sleep};
+
+use crate::{Globals, Options};
+
+pub const MIB: u64 = 1024 * 1024;
+
+/// Log target of every request and its response, enabled with
+/// `--trace-http`.
+pub const TRACE_TARGET: &str = "poe2filter::trace_http";
+
+/// Response headers that are logged when tracing requests.
+const TRACED_HEADERS: [&str; 9] = [
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "x-ratelimit-resource",
+    "retry-after",
+    "etag",
+    "last-modified",
+    "cache-control",
+    "content-length",
+];
+
+/// The largest download that is accepted, unless overridden.
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 512 * MIB;
+
+/// Idle connections kept per host, enough for every concurrent update.
+const POOL_MAX_IDLE_PER_HOST: usize = 8;
+
+/// How long idle connections are kept, long enough to last a whole run.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// How far back the download speed is averaged when throttling.
+const THROTTLE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How many times a failed request is retried, unless overridden.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for every further one.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound for the delay between retries.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Randomizes each delay by up to this fraction, so that retries from many
+/// users don't line up.
+const JITTER: f64 = 0.25;
+
+/// Limits on how long requests may take, `None` disables a limit.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    /// Establishing the connection.
+    pub connect: Option<Duration>,
+    /// Waiting for the next data while reading a response.
+    pub read: Option<Duration>,
+    /// The whole request, including downloading the body.
+    pub total: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(Duration::from_secs(10)),
+            read: Some(Duration::from_secs(30)),
+            total: Some(Duration::from_secs(300)),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn apply(&self, mut client: ClientBuilder) -> ClientBuilder {
+        if let Some(timeout) = self.connect {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read {
+            client = client.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.total {
+            client = client.timeout(timeout);
+        }
+        client
+    }
+}
+
+/// The User-Agent sent with every request: `poe2filter/<version>`, with the
+/// contact details if there are any, so that API operators know who to reach
+/// out to.
+fn user_agent(options: &Options) -> String {
+    if let Some(user_agent) = &options.user_agent {
+        return user_agent.clone();
+    }
+
+    let user_agent = concat!("poe2filter/", env!("CARGO_PKG_VERSION"));
+    match &options.contact {
+        Some(contact) => format!("{user_agent} (+{contact})"),
+        None => user_agent.to_string(),
+    }
+}
+
+/// Builds the client used for all requests. HTTP/2 is negotiated where the
+/// server supports it (GitHub does), so that concurrent updates share one
+/// connection per host, and connections are kept open, so that the burst of
+/// requests to api.github.com and codeload.github.com when several sources are
+/// updated reuses them instead of making a new TLS handshake each time.
+pub fn client(options: &Options) -> Result<Client> {
+    let mut client = options
+        .timeouts
+        .apply(ClientBuilder::new().user_agent(user_agent(options)))
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        // Grows the flow control window to the bandwidth, large archives would
+        // otherwise download slower than over HTTP/1.1.
+        .http2_adaptive_window(true)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .tcp_nodelay(true);
+    if let Some(proxy) = &options.proxy {
+        client = client.proxy(proxy_from_url(proxy)?);
+    } else if options.no_system_proxy {
+        client = client.no_proxy();
+    }
+
+    if let Some(path) = &options.ca_bundle {
+        // TLS-intercepting proxies sign with their own CA, which has to be
+        // trusted in addition to the usual ones.
+        let pem = std::fs::read(path).wrap_err_with(|| format!("could not read {path:?}"))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .wrap_err_with(|| format!("{path:?} is not a PEM certificate bundle"))?;
+        if certificates.is_empty() {
+            bail!("{path:?} does not contain any certificates");
+        }
+        for certificate in certificates {
+            client = client.add_root_certificate(certificate);
+        }
+    }
+
+    client
+        .build()
+        .wrap_err_with(|| "could not create an HTTP client")
+}
+
+/// Builds a proxy for all requests from a URL such as `http://host:3128` or
+/// `socks5h://host:1080` (`socks5h` resolves names through the proxy).
+/// Hosts in `NO_PROXY` still bypass it.
+fn proxy_from_url(url: &str) -> Result<Proxy> {
+    Ok(Proxy::all(url)
+        .wrap_err_with(|| format!("invalid proxy {url:?}"))?
+        .no_proxy(NoProxy::from_env()))
+}
+
+/// Whether a proxy is configured through the environment.
+pub fn proxy_from_env() -> bool {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .any(|name| var_os(name).is_some_and(|v| !v.is_empty()))
+}
+
+/// Sends the request, retrying up to `retries` times with exponential backoff
+/// when it times out, can't connect, or the server reports a temporary
+/// problem. Other failures are returned right away.
+pub async fn send(
+    transport: &dyn Transport,
+    request: RequestBuilder,
+    retries: u32,
+) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        // Requests with a streaming body can't be sent twice.
+        let Some(next) = request.try_clone() else {
+            return execute(transport, request).await;
+        };
+
+        let result = execute(transport, next).await;
+        let transient = match &result {
+            Ok(response) => is_transient(response.status()),
+            Err(error) => error.is_timeout() || error.is_connect(),
+        };
+        if !transient || attempt >= retries {
+            return result;
+        }
+
+        attempt += 1;
+        let delay = backoff(attempt);
+        match &result {
+            Ok(response) => warn!(
+                "{} responded with {}, retrying in {delay:?} ({attempt}/{retries})",
+                response.url(),
+                response.status()
+            ),
+            Err(error) => warn!("{error}, retrying in {delay:?} ({attempt}/{retries})"),
+        }
+        sleep(delay).await;
+    }
+}
+
+async fn execute(transport: &dyn Transport, request: RequestBuilder) -> reqwest::Result<Response> {
+    let (_, request) = request.build_split();
+    let request = request?;
+    let (method, url) = (request.method().clone(), redact_url(request.url()));
+    let started = Instant::now();
+    let result = transport.execute(request).await;
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    match &result {
+        Ok(response) => {
+            let headers: Vec<_> = TRACED_HEADERS
+                .iter()
+                .filter_map(|name| {
+                    let value = response.headers().get(*name)?.to_str().ok()?;
+                    Some(format!("{name}: {value}"))
+                })
+                .collect();
+            let version = format!("{:?}", response.version());
+            info!(
+                target: TRACE_TARGET,
+                method:% = method, url:% = url, status = response.status().as_u16(), version:% = version, elapsed_ms;
+                "{method} {url}: {} over {version} in {elapsed_ms}ms [{}]", response.status(), headers.join(", ")
+            );
+        }
+        Err(error) => {
+            // The error itself includes the unredacted URL.
+            let reason = std::error::Error::source(error)
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "request failed".to_string());
+            info!(
+                target: TRACE_TARGET,
+                method:% = method, url:% = url, elapsed_ms;
+                "{method} {url}: {reason} after {elapsed_ms}ms"
+            );
+        }
+    }
+    result
+}
+
+/// Hides the parts of a URL that are secrets: passwords, tokens in the query
+/// and the token of Discord webhooks.
+pub fn redact_url(url: &Url) -> String {
+    const SECRET_PARAMETERS: [&str; 5] = ["token", "key", "secret", "sig", "password"];
+
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("REDACTED"));
+    }
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| {
+            let secret = SECRET_PARAMETERS
+                .iter()
+                .any(|v| key.to_lowercase().contains(v));
+            match secret {
+                true => (key.into_owned(), "REDACTED".to_string()),
+                false => (key.into_owned(), value.into_owned()),
+            }
+        })
+        .collect();
+    if !query.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    // https://discord.com/api/webhooks/<id>/<token>
+    let segments: Option<Vec<_>> = url
+        .path_segments()
+        .map(|v| v.map(|v| v.to_string()).collect());
+    if let Some(mut segments) = segments {
+        if let Some(index) = segments.iter().position(|v| v == "webhooks") {
+            if segments.len() > index + 2 {
+                segments[index + 2] = "REDACTED".to_string();
+                url.set_path(&segments.join("/"));
+            }
+        }
+    }
+
+    url.to_string()
+}
+
+/// The `ETag` and `Last-Modified` date of a response, to make the next
+/// request for the same URL conditional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn from_response(response: &Response) -> Self {
+        let header = |name: HeaderName| {
+            let value = response.headers().get(name)?.to_str().ok()?;
+            Some(value.to_string())
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Adds `If-None-Match` and `If-Modified-Since` to a request. Servers
+    /// only look at the date if there is no ETag.
+    pub(crate) fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// The validators of an API response, and the watermark that was found in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Etag {
+    #[serde(flatten)]
+    validators: Validators,
+    watermark: String,
+}
+
+/// ETags by request URL, shared between concurrent updates.
+pub type Etags = Arc<Mutex<HashMap<String, Etag>>>;
+
+/// The validators of an earlier response for the URL, if the watermark that
+/// was found in it is still the `existing` one.
+pub(crate) fn cached_validators(
+    globals: &Globals,
+    url: &str,
+    existing: Option<&String>,
+) -> Option<Validators> {
+    let existing = existing?;
+    let etags = globals.etags.lock().ok()?;
+    let cached = etags.get(url)?;
+    (cached.watermark == *existing).then(|| cached.validators.clone())
+}
+
+/// Remembers the validators of a response for the next run, along with the
+/// watermark that was found in it.
+pub(crate) fn remember_validators(
+    globals: &Globals,
+    url: String,
+    validators: Validators,
+    watermark: &str,
+) {
+    if validators.is_empty() {
+        return;
+    }
+    let Ok(mut etags) = globals.etags.lock() else {
+        return;
+    };
+    etags.insert(
+        url,
+        Etag {
+            validators,
+            watermark: watermark.to_string(),
+        },
+    );
+}
+
+/// Sends requests. Everything goes through the one in [`Globals`], so that
+/// tools embedding poe2filter can supply their own, e.g. to add
+/// authentication, record requests or serve responses from fixtures.
+/// Responses can be made from an `http::Response` with `Response::from`.
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>>;
+}
+
+impl Transport for Client {
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>> {
+        Client::execute(self, request).boxed()
+    }
+}
+
+/// Downloads the response body to `path`. The data is first written to a
+/// `.part` file next to it. If the download is interrupted (here, or in an
+/// earlier run), it continues where it left off when the server supports
+/// range requests and the file is still the same one, which is checked with
+/// the `ETag` or `Last-Modified` date kept in a `.part.validator` file.
+/// `progress` is given the downloaded and total size as the download goes
+/// on.
+pub async fn download(
+    globals: &Globals,
+    request: RequestBuilder,
+    path: &Path,
+    check: impl Fn(&Response) -> Result<()>,
+    progress: impl Fn(u64, Option<u64>),
+) -> Result<()> {
+    let part = with_suffix(path, ".part");
+    let validator = with_suffix(path, ".part.validator");
+
+    let retries = globals.retries;
+    let mut attempt = 0;
+    loop {
+        match download_part(globals, &request, &part, &validator, &check, &progress).await {
+            Ok(true) => break,
+            Ok(false) if attempt < retries => attempt += 1,
+            Ok(false) => bail!("the server rejected resuming the download"),
+            Err(error) if attempt < retries && is_interrupted(&error) => {
+                attempt += 1;
+                warn!("download interrupted, resuming ({attempt}/{retries}): {error}");
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    fs::rename(&part, path).await?;
+    let _ = fs::remove_file(&validator).await;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+async fn download_part(
+    globals: &Globals,
+    request: &RequestBuilder,
+    part: &Path,
+    validator: &Path,
+    check: &impl Fn(&Response) -> Result<()>,
+    progress: &impl Fn(u64, Option<u64>),
+) -> Result<bool> {
+    let max_size = globals.max_download_size;
+    let mut offset = fs::metadata(part).await.map(|v| v.len()).unwrap_or(0);
+    let mut request = request
+        .try_clone()
+        .ok_or_else(|| eyre!("the request can't be repeated"))?;
+    if offset > 0 {
+        // Without a validator the file may have changed since, and the parts
+        // of two different files would be joined together.
+        match fs::read_to_string(validator).await {
+            Ok(value) if !value.is_empty() => {
+                request = request
+                    .header(RANGE, format!("bytes={offset}-"))
+                    .header(IF_RANGE, value);
+            }
+            _ => {
+                info!("can't tell whether {part:?} is still current, starting over");
+                offset = 0;
+            }
+        }
+    }
+
+    let response = send(&*globals.transport, request, globals.retries).await?;
+    check(&response)?;
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        warn!("could not resume the download, starting over");
+        fs::remove_file(part).await?;
+        return Ok(false);
+    }
+
+    let mut response = response.error_for_status()?;
+    let (mut file, mut size) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        if !resumes_at(&response, offset) {
+            warn!("the server resumed the download at the wrong offset, starting over");
+            fs::remove_file(part).await?;
+            return Ok(false);
+        }
+        info!("resuming download at {offset} bytes");
+        let file = fs::OpenOptions::new().append(true).open(part).await?;
+        (file, offset)
+    } else {
+        // A full response, because the file changed or the server doesn't
+        // support ranges: the new validator goes with the new part.
+        match range_validator(&response) {
+            Some(value) => fs::write(validator, value).await?,
+            None => {
+                let _ = fs::remove_file(validator).await;
+            }
+        }
+        (fs::File::create(part).await?, 0)
+    };
+
+    let total = response.content_length().map(|v| size + v);
+    check_size(max_size, total.unwrap_or(size), part).await?;
+    progress(size, total);
+
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len() as u64;
+        check_size(max_size, size, part).await?;
+        file.write_all(&chunk).await?;
+        globals.counters.downloaded(chunk.len() as u64);
+        progress(size, total);
+        if let Some(throttle) = &globals.throttle {
+            throttle.consume(chunk.len() as u64).await;
+        }
+    }
+    file.sync_all().await?;
+    Ok(true)
+}
+
+/// The validator to send in `If-Range` when resuming: a strong `ETag`, or
+/// else the `Last-Modified` date. Weak `ETag`s can't be used for ranges.
+fn range_validator(response: &Response) -> Option<&str> {
+    let header = |name| response.headers().get(name)?.to_str().ok();
+    header(ETAG)
+        .filter(|v| !v.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+}
+
+/// Whether a partial response starts where the part ends.
+fn resumes_at(response: &Response, offset: u64) -> bool {
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split_once('-'))
+        .is_some_and(|(start, _)| start.parse() == Ok(offset))
+}
+
+/// Limits the combined speed of all downloads.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// When the limit started being tracked, and how much was downloaded
+    /// since.
+    state: Arc<Mutex<(Instant, u64)>>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Throttle {
+            bytes_per_second,
+            state: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    /// Accounts for downloaded data, and waits until downloading it would
+    /// have taken at the limited speed.
+    async fn consume(&self, bytes: u64) {
+        let ahead = {
+            let Ok(mut state) = self.state.lock() else {
+                return;
+            };
+            // Forget about idle time, so that it can't be used up in a burst.
+            if state.0.elapsed() > THROTTLE_WINDOW {
+                *state = (Instant::now(), 0);
+            }
+            state.1 += bytes;
+
+            let due = Duration::from_secs_f64(state.1 as f64 / self.bytes_per_second as f64);
+            due.saturating_sub(state.0.elapsed())
+        };
+
+        if !ahead.is_zero() {
+            sleep(ahead).await;
+        }
+    }
+}
+
+/// Gives up on (and removes) downloads that are larger than allowed.
+async fn check_size(max_size: Option<u64>, size: u64, part: &Path) -> Result<()> {
+    let Some(max_size) = max_size.filter(|max_size| size > *max_size) else {
+        return Ok(());
+    };
+
+    let _ = fs::remove_file(part).await;
+    bail!(
+        "the download is larger than the limit of {} MiB (--max-download-size)",
+        max_size / MIB
+    )
+}
+
+/// Whether the download failed halfway through in a way that is worth
+/// resuming.
+fn is_interrupted(error: &Report) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|v| v.is_body() || v.is_timeout() || v.is_connect() || v.is_decode())
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// The delay before retry `attempt` (counting from 1): exponential, capped
+/// and randomized.
+pub(crate) fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    let jitter = rand::thread_rng().gen_range(-JITTER..=JITTER);
+    delay.mul_f64(1.0 + jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use futures_util::{future::BoxFuture, FutureExt as _};
+    use reqwest::{
+        header::{IF_RANGE, RANGE},
+        Request, Response,
+    };
+
+    use super::{download, Transport};
+    use crate::{block_on, Globals, Options};
+
+    const CONTENT: &str = "Show\n    BaseType \"Divine Orb\"\n";
+    const ETAG: &str = "\"current\"";
+
+    /// The `Range` and `If-Range` of a request.
+    type Headers = (Option<String>, Option<String>);
+
+    /// Serves `CONTENT`, and ranges of it while `If-Range` matches its
+    /// `ETag`. Remembers the `Range` and `If-Range` of every request.
+    #[derive(Debug, Default)]
+    struct Server {
+        requests: Mutex<Vec<Headers>>,
+    }
+
+    impl Transport for Server {
+        fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>> {
+            let header = |name| Some(request.headers().get(name)?.to_str().ok()?.to_string());
+            let (range, if_range) = (header(RANGE), header(IF_RANGE));
+            self.requests
+                .lock()
+                .unwrap()
+                .push((range.clone(), if_range.clone()));
+
+            let offset = range
+                .filter(|_| if_range.as_deref() == Some(ETAG))
+                .and_then(|v| v.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok());
+            let response = match offset {
+                Some(offset) => ::http::Response::builder()
+                    .status(206)
+                    .header(
+                        "content-range",
+                        format!("bytes {offset}-{}/{}", CONTENT.len() - 1, CONTENT.len()),
+                    )
+                    .body(CONTENT[offset..].to_string()),
+                None => ::http::Response::builder()
+                    .header("etag", ETAG)
+                    .body(CONTENT.to_string()),
+            };
+            async move { Ok(Response::from(response.unwrap())) }.boxed()
+        }
+    }
+
+    /// Downloads `CONTENT` over an interrupted `part`, and returns the result
+    /// and the requests that were made.
+    fn resume(name: &str, part: &str, validator: Option<&str>) -> (String, Vec<Headers>) {
+        let directory =
+            std::env::temp_dir().join(format!("poe2filter-http-{name}-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("archive.zip");
+        fs::write(directory.join("archive.zip.part"), part).unwrap();
+        if let Some(validator) = validator {
+            fs::write(directory.join("archive.zip.part.validator"), validator).unwrap();
+        }
+
+        let server = std::sync::Arc::new(Server::default());
+        let result = block_on(async {
+            let options = Options {
+                game_directory: Some(directory.clone()),
+                ..Default::default()
+            };
+            let mut globals = Globals::new(&options).await.unwrap();
+            globals.transport = server.clone();
+            let request = globals.client.get("https://example.com/archive.zip");
+            download(&globals, request, &path, |_| Ok(()), |_, _| {}).await
+        });
+        result.unwrap();
+
+        let downloaded = fs::read_to_string(&path).unwrap();
+        assert!(!directory.join("archive.zip.part.validator").exists());
+        fs::remove_dir_all(&directory).unwrap();
+        let requests = server.requests.lock().unwrap().clone();
+        (downloaded, requests)
+    }
+
+    #[test]
+    fn resumes_the_same_file() {
+        let (downloaded, requests) = resume("same", &CONTENT[..5], Some(ETAG));
+        assert_eq!(downloaded, CONTENT);
+        assert_eq!(
+            requests,
+            [(Some("bytes=5-".to_string()), Some(ETAG.to_string()))]
+        );
+    }
+
+    #[test]
+    fn starts_over_when_the_file_changed() {
+        let (downloaded, requests) = resume("changed", "Hide # the old file", Some("\"old\""));
+        assert_eq!(downloaded, CONTENT);
+        assert_eq!(
+            requests,
+            [(Some("bytes=19-".to_string()), Some("\"old\"".to_string()))]
+        );
+    }
+
+    #[test]
+    fn starts_over_without_a_validator() {
+        let (downloaded, requests) = resume("unvalidated", "Hide # the old file", None);
+        assert_eq!(downloaded, CONTENT);
+        assert_eq!(requests, [(None, None)]);
+    }
+}
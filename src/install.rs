@@ -0,0 +1,664 @@
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use color_eyre::{eyre::bail, Report, Result};
+use icu_normalizer::ComposingNormalizer;
+use log::{debug, info, warn};
+use tokio::{fs, io::AsyncWriteExt as _};
+
+use crate::{
+    checksum::{self, HashingWriter},
+    guard, pipeline,
+};
+
+/// Mode given to every installed file, the game only needs to read them.
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o644;
+
+/// Characters that can't appear in filenames on Windows filesystems.
+const INVALID_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names reserved by Windows, regardless of extension.
+const RESERVED_NAMES: [&str; 24] = [
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// How many times a write is attempted before giving up on a file that does
+/// not read back correctly.
+const WRITE_ATTEMPTS: usize = 3;
+
+/// Where the files of a release whose name matches `pattern` (with `*` and
+/// `?` wildcards) are installed. Files that no target matches are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub pattern: String,
+    /// Relative to the game directory, unless it is absolute.
+    pub directory: PathBuf,
+}
+
+impl Target {
+    /// Filters go into the game directory, which is always a target.
+    pub fn filters() -> Self {
+        Target {
+            pattern: "*.filter".to_string(),
+            directory: PathBuf::new(),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        pipeline::matches(&self.pattern, name)
+    }
+}
+
+impl FromStr for Target {
+    type Err = Report;
+
+    /// Parses `pattern=directory`.
+    fn from_str(value: &str) -> Result<Self> {
+        match value.split_once('=') {
+            Some((pattern, directory)) if !pattern.is_empty() && !directory.is_empty() => {
+                Ok(Target {
+                    pattern: pattern.to_string(),
+                    directory: PathBuf::from(directory),
+                })
+            }
+            _ => bail!("a target must be in the form pattern=directory"),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.pattern, self.directory.display())
+    }
+}
+
+/// Suffix of files that are still being written.
+const TEMP_SUFFIX: &str = ".poe2filter-tmp";
+
+/// Suffix of the previous version of a file while a [`Transaction`] puts the
+/// new ones in place.
+const BACKUP_SUFFIX: &str = ".poe2filter-old";
+
+/// A file that is being written, removed again unless it was put in place.
+/// This makes sure that cancelled or failed writes don't leave partial files
+/// around.
+struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            debug!("removing {:?}", self.path);
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    hidden_path(path, TEMP_SUFFIX)
+}
+
+fn hidden_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Writes a file into the game directory, verifies it and normalizes its
+/// permissions. The data is written to a temporary file first, which is then
+/// renamed over the destination, so the game never sees a partial file.
+pub async fn write_file(directory: &Path, path: &Path, data: &[u8]) -> Result<()> {
+    ensure_inside(directory, path)?;
+
+    let mut temp = TempFile {
+        path: temp_path(path),
+        keep: false,
+    };
+
+    for attempt in 1..=WRITE_ATTEMPTS {
+        info!("writing {path:?}");
+        let mut dest = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&temp.path)
+            .await?;
+
+        dest.write_all(data).await?;
+        dest.sync_all().await?;
+        drop(dest);
+
+        if verify_file(&temp.path, data).await {
+            normalize_permissions(directory, &temp.path);
+            fs::rename(&temp.path, path).await?;
+            temp.keep = true;
+            sync_directory(path.parent().unwrap_or(directory));
+            return Ok(());
+        }
+
+        warn!("{path:?} does not match what was written (attempt {attempt}/{WRITE_ATTEMPTS})");
+    }
+
+    bail!("could not write {path:?}, the data on disk does not match what was downloaded")
+}
+
+/// Like [`write_file`], but the data is written by `write`, which is called
+/// again for every attempt. This blocks, so it has to run on a blocking
+/// thread.
+pub fn write_file_with(
+    directory: &Path,
+    path: &Path,
+    write: impl FnMut(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let mut temp = write_temp_file_with(directory, path, write)?;
+    std::fs::rename(&temp.path, path)?;
+    temp.keep = true;
+    sync_directory(path.parent().unwrap_or(directory));
+    Ok(())
+}
+
+/// Makes renames into the directory durable, so that after a crash or power
+/// loss the watermarks (which are written afterwards) never claim a version
+/// whose files are missing. Not every filesystem supports this, which is
+/// fine.
+fn sync_directory(directory: &Path) {
+    if let Err(error) = std::fs::File::open(directory).and_then(|v| v.sync_all()) {
+        debug!("could not sync {directory:?}: {error}");
+    }
+}
+
+/// Writes and verifies the temporary file for `path`, without putting it in
+/// place yet.
+fn write_temp_file_with(
+    directory: &Path,
+    path: &Path,
+    mut write: impl FnMut(&mut dyn Write) -> Result<()>,
+) -> Result<TempFile> {
+    ensure_inside(directory, path)?;
+
+    let temp = TempFile {
+        path: temp_path(path),
+        keep: false,
+    };
+
+    for attempt in 1..=WRITE_ATTEMPTS {
+        info!("writing {path:?}");
+        let mut dest = HashingWriter::new(BufWriter::new(std::fs::File::create(&temp.path)?));
+        write(&mut dest)?;
+        let (dest, digest) = dest.finish();
+        dest.into_inner()
+            .map_err(|error| error.into_error())?
+            .sync_all()?;
+
+        match checksum::sha256_file(&temp.path) {
+            Ok(actual) if actual == digest => {
+                normalize_permissions(directory, &temp.path);
+                return Ok(temp);
+            }
+            Ok(_) => {}
+            Err(error) => warn!("could not read back {path:?}: {error}"),
+        }
+
+        warn!("{path:?} does not match what was written (attempt {attempt}/{WRITE_ATTEMPTS})");
+    }
+
+    bail!("could not write {path:?}, the data on disk does not match what was downloaded")
+}
+
+/// The files of one source, which are either all updated or not at all.
+/// They are written next to their destination first, and only put in place
+/// together by [`Transaction::commit`]. Dropping the transaction instead
+/// removes them again, leaving the previous files untouched.
+pub struct Transaction {
+    directory: PathBuf,
+    staged: Vec<(TempFile, PathBuf)>,
+}
+
+impl Transaction {
+    pub fn new(directory: &Path) -> Self {
+        Transaction {
+            directory: directory.to_path_buf(),
+            staged: Vec::new(),
+        }
+    }
+
+    /// Like [`write_file_with`], but the file is only put in place when the
+    /// transaction is committed. This blocks.
+    pub fn write_file_with(
+        &mut self,
+        path: &Path,
+        write: impl FnMut(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let directory = self.directory.clone();
+        self.write_file_in(&directory, path, write)
+    }
+
+    /// Like [`Transaction::write_file_with`], for a file in another directory
+    /// than the one of the transaction.
+    pub fn write_file_in(
+        &mut self,
+        directory: &Path,
+        path: &Path,
+        write: impl FnMut(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let temp = write_temp_file_with(directory, path, write)?;
+        self.staged.push((temp, path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Puts every file in place. The previous versions are moved aside first,
+    /// so that they can be restored if one of the renames fails. This blocks.
+    pub fn commit(mut self) -> Result<()> {
+        let mut pause = guard::pause();
+        let mut done: Vec<(PathBuf, Option<PathBuf>)> = Vec::new();
+        for (temp, path) in &mut self.staged {
+            let backup = hidden_path(path, BACKUP_SUFFIX);
+            let result = (|| {
+                let backup = match path.exists() {
+                    true => std::fs::rename(&*path, &backup).map(|_| Some(backup))?,
+                    false => None,
+                };
+                done.push((path.clone(), backup));
+                std::fs::rename(&temp.path, &*path)?;
+                temp.keep = true;
+                Ok::<_, std::io::Error>(())
+            })();
+
+            if let Err(error) = result {
+                warn!("could not put {path:?} in place, restoring the previous files: {error}");
+                for (path, backup) in done.iter().rev() {
+                    let restored = match backup {
+                        Some(backup) => std::fs::rename(backup, path),
+                        None => std::fs::remove_file(path),
+                    };
+                    if let Err(error) = restored {
+                        warn!("could not restore {path:?}: {error}");
+                    }
+                }
+                bail!("could not write {path:?}: {error}");
+            }
+        }
+
+        let directories: HashSet<_> = done.iter().filter_map(|(path, _)| path.parent()).collect();
+        for directory in directories {
+            sync_directory(directory);
+        }
+
+        for (path, _) in &done {
+            pause.installed(path);
+        }
+        for backup in done.into_iter().filter_map(|(_, backup)| backup) {
+            if let Err(error) = std::fs::remove_file(&backup) {
+                warn!("could not remove {backup:?}: {error}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes temporary files left behind by a previous run that was killed,
+/// and restores previous versions that a [`Transaction`] was replacing. Files
+/// can be written into subdirectories (e.g. by a rename), so those are
+/// searched too, without following symlinks.
+pub async fn remove_temp_files(directory: &Path) {
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let Ok(mut entries) = fs::read_dir(&directory).await else {
+            continue;
+        };
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_type().await.is_ok_and(|v| v.is_dir()) {
+                directories.push(entry.path());
+                continue;
+            }
+
+            let name = entry.file_name();
+            let name = name.as_encoded_bytes();
+            if name.ends_with(TEMP_SUFFIX.as_bytes()) {
+                info!("removing leftover {:?}", entry.path());
+                if let Err(error) = fs::remove_file(entry.path()).await {
+                    warn!("could not remove {:?}: {error}", entry.path());
+                }
+            } else if let Some(original) = name
+                .strip_suffix(BACKUP_SUFFIX.as_bytes())
+                .and_then(|v| v.strip_prefix(b"."))
+            {
+                // SAFETY: only ASCII was stripped from the name, which leaves
+                // it valid.
+                let original = unsafe { OsStr::from_encoded_bytes_unchecked(original) };
+                // The new version may be incomplete, the old one is known to
+                // be good.
+                let path = directory.join(original);
+                info!("restoring {path:?} from an interrupted update");
+                if let Err(error) = fs::rename(entry.path(), &path).await {
+                    warn!("could not restore {path:?}: {error}");
+                }
+            }
+        }
+    }
+}
+
+/// Normalizes an archive entry filename to NFC and makes it valid on Windows,
+/// which the prefix (and the game) would otherwise mangle:
+///
+/// - invalid characters are removed,
+/// - trailing dots and spaces are removed,
+/// - reserved device names get an underscore appended (`CON.filter` becomes `CON_.filter`).
+///
+/// Returns `None` if nothing usable is left.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let normalized = ComposingNormalizer::new_nfc().normalize(name);
+    let mut sanitized: String = normalized
+        .chars()
+        .filter(|c| !c.is_control() && !INVALID_CHARACTERS.contains(c))
+        .collect();
+
+    sanitized.truncate(sanitized.trim_end_matches(['.', ' ']).len());
+
+    let (stem, extension) = match sanitized.find('.') {
+        Some(index) => sanitized.split_at(index),
+        None => (sanitized.as_str(), ""),
+    };
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.trim_end().eq_ignore_ascii_case(reserved))
+    {
+        sanitized = format!("{stem}_{extension}");
+    }
+
+    if sanitized != name {
+        debug!("sanitized filename {name:?} to {sanitized:?}");
+    }
+
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Turns the name of an archive entry into a relative path that can't leave
+/// the directory it is extracted to, with every component sanitized. Entries
+/// with absolute paths, drive letters or `..` components are rejected rather
+/// than fixed up, since no legitimate archive contains them. Any other colon
+/// is just an invalid character, and removed by [`sanitize_filename`].
+pub fn entry_path(name: &str) -> Option<PathBuf> {
+    if name.starts_with(['/', '\\']) || has_drive_prefix(name) {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            _ => path.push(sanitize_filename(component)?),
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Whether the name starts with a Windows drive (`C:`), which makes it
+/// absolute or relative to the current directory of that drive.
+fn has_drive_prefix(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic()
+    )
+}
+
+/// Checks the Unix mode of an archive entry (if the archive has one) for a
+/// regular file. Symlinks could point anywhere, and devices, fifos and the
+/// like have no business in a filter archive.
+pub fn is_regular_file(unix_mode: Option<u32>) -> bool {
+    const TYPE_MASK: u32 = 0o170000;
+    const REGULAR: u32 = 0o100000;
+
+    // Archives made on Windows often don't record a type.
+    unix_mode.is_none_or(|mode| matches!(mode & TYPE_MASK, 0 | REGULAR))
+}
+
+/// Joins a relative path from [`entry_path`] (or similar) to the directory,
+/// making sure that the result is inside of it.
+pub fn destination(directory: &Path, relative: &Path) -> Result<PathBuf> {
+    if !relative
+        .components()
+        .all(|v| matches!(v, Component::Normal(_)))
+    {
+        bail!("{relative:?} would be written outside of {directory:?}");
+    }
+
+    Ok(directory.join(relative))
+}
+
+/// Makes sure that the file would end up inside the directory, even when a
+/// subdirectory is a symlink that points elsewhere.
+fn ensure_inside(directory: &Path, path: &Path) -> Result<()> {
+    let parent = path.parent().unwrap_or(path);
+    let (Ok(directory), Ok(parent)) = (directory.canonicalize(), parent.canonicalize()) else {
+        // Writing will fail anyway.
+        return Ok(());
+    };
+
+    if !parent.starts_with(&directory) {
+        bail!("{path:?} would be written outside of {directory:?}");
+    }
+    Ok(())
+}
+
+/// Picks a filename that hasn't been used yet by appending a counter to the
+/// stem, for when several archive entries sanitize to the same name. The
+/// result only depends on the order of the entries, so it's stable between
+/// runs.
+pub fn unique_filename(name: String, used: &mut HashSet<String>) -> String {
+    if used.insert(name.to_lowercase()) {
+        return name;
+    }
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) if index > 0 => name.split_at(index),
+        _ => (name.as_str(), ""),
+    };
+
+    (2..)
+        .map(|counter| format!("{stem} ({counter}){extension}"))
+        .find(|candidate| used.insert(candidate.to_lowercase()))
+        .expect("there is always an unused name")
+}
+
+/// Reads a file back and checks that it contains exactly the expected bytes,
+/// flaky storage (like SD cards) can silently corrupt writes.
+async fn verify_file(path: &Path, expected: &[u8]) -> bool {
+    match fs::read(path).await {
+        Ok(actual) => actual == expected,
+        Err(error) => {
+            warn!("could not read back {path:?}: {error}");
+            false
+        }
+    }
+}
+
+/// Makes sure that the file is readable by the game, and owned by the same
+/// user as the game directory. This matters when running as a different user
+/// (e.g. via sudo or a system service), where files would otherwise end up
+/// owned by root or with a restrictive umask.
+#[cfg(unix)]
+pub fn normalize_permissions(directory: &Path, path: &Path) {
+    use std::{
+        fs::Permissions,
+        os::unix::fs::{chown, MetadataExt as _, PermissionsExt as _},
+    };
+
+    if let Err(error) = std::fs::set_permissions(path, Permissions::from_mode(FILE_MODE)) {
+        warn!("could not set permissions of {path:?}: {error}");
+    }
+
+    let (Ok(dir_meta), Ok(file_meta)) = (directory.metadata(), path.metadata()) else {
+        return;
+    };
+
+    if dir_meta.uid() == file_meta.uid() && dir_meta.gid() == file_meta.gid() {
+        return;
+    }
+
+    debug!(
+        "changing owner of {path:?} to {}:{}",
+        dir_meta.uid(),
+        dir_meta.gid()
+    );
+    if let Err(error) = chown(path, Some(dir_meta.uid()), Some(dir_meta.gid())) {
+        warn!("could not change owner of {path:?}, the game may not be able to read it: {error}");
+    }
+}
+
+/// Files on Windows inherit the permissions of the directory.
+#[cfg(not(unix))]
+pub fn normalize_permissions(_directory: &Path, _path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{
+        entry_path, hidden_path, remove_temp_files, sanitize_filename, temp_path, BACKUP_SUFFIX,
+    };
+    use crate::block_on;
+
+    #[test]
+    fn entry_path_keeps_relative_paths() {
+        assert_eq!(
+            entry_path("NeverSink/filters/Strict.filter"),
+            Some(PathBuf::from("NeverSink/filters/Strict.filter"))
+        );
+        assert_eq!(
+            entry_path("./a\\b.filter"),
+            Some(PathBuf::from("a/b.filter"))
+        );
+    }
+
+    #[test]
+    fn entry_path_rejects_parent_components() {
+        assert_eq!(entry_path("../evil.filter"), None);
+        assert_eq!(entry_path("../../evil.filter"), None);
+        assert_eq!(entry_path("filters/../../evil.filter"), None);
+        assert_eq!(entry_path("filters\\..\\evil.filter"), None);
+    }
+
+    #[test]
+    fn entry_path_rejects_absolute_paths() {
+        assert_eq!(entry_path("/etc/evil.filter"), None);
+        assert_eq!(entry_path("\\evil.filter"), None);
+        assert_eq!(entry_path("\\\\server\\share\\evil.filter"), None);
+    }
+
+    #[test]
+    fn entry_path_rejects_drive_prefixes() {
+        assert_eq!(entry_path("C:\\evil.filter"), None);
+        assert_eq!(entry_path("c:/evil.filter"), None);
+        assert_eq!(entry_path("C:evil.filter"), None);
+    }
+
+    #[test]
+    fn entry_path_sanitizes_colons() {
+        assert_eq!(
+            entry_path("Foo: Strict.filter"),
+            Some(PathBuf::from("Foo Strict.filter"))
+        );
+        assert_eq!(
+            entry_path("filters/C:Strict.filter"),
+            Some(PathBuf::from("filters/CStrict.filter"))
+        );
+        assert_eq!(
+            entry_path("Strict.filter:stream"),
+            Some(PathBuf::from("Strict.filterstream"))
+        );
+    }
+
+    #[test]
+    fn entry_path_rejects_empty_names() {
+        assert_eq!(entry_path(""), None);
+        assert_eq!(entry_path("./"), None);
+        assert_eq!(entry_path("..."), None);
+    }
+
+    #[test]
+    fn sanitize_filename_renames_reserved_names() {
+        assert_eq!(
+            sanitize_filename("CON.filter").as_deref(),
+            Some("CON_.filter")
+        );
+        assert_eq!(sanitize_filename("nul").as_deref(), Some("nul_"));
+        assert_eq!(
+            sanitize_filename("COM0.filter").as_deref(),
+            Some("COM0_.filter")
+        );
+        assert_eq!(
+            sanitize_filename("LPT0.filter").as_deref(),
+            Some("LPT0_.filter")
+        );
+        assert_eq!(
+            sanitize_filename("LPT9.x.filter").as_deref(),
+            Some("LPT9_.x.filter")
+        );
+        assert_eq!(
+            sanitize_filename("CONSOLE.filter").as_deref(),
+            Some("CONSOLE.filter")
+        );
+    }
+
+    #[test]
+    fn sanitize_filename_removes_invalid_characters() {
+        assert_eq!(
+            sanitize_filename("a<b>c|d?e*.filter").as_deref(),
+            Some("abcde.filter")
+        );
+        assert_eq!(sanitize_filename("Strict. . ").as_deref(), Some("Strict"));
+        assert_eq!(sanitize_filename("???"), None);
+    }
+
+    #[test]
+    fn remove_temp_files_searches_subdirectories() {
+        let directory =
+            std::env::temp_dir().join(format!("poe2filter-install-{}", std::process::id()));
+        let nested = directory.join("sounds/alerts");
+        fs::create_dir_all(&nested).unwrap();
+        let (filter, sound) = (directory.join("A.filter"), nested.join("alert.mp3"));
+        fs::write(&filter, "Show # partial").unwrap();
+        fs::write(hidden_path(&filter, BACKUP_SUFFIX), "Show # good").unwrap();
+        fs::write(temp_path(&filter), "Show").unwrap();
+        fs::write(hidden_path(&sound, BACKUP_SUFFIX), "good").unwrap();
+        fs::write(temp_path(&nested.join("other.mp3")), "partial").unwrap();
+
+        block_on(remove_temp_files(&directory));
+
+        assert_eq!(fs::read_to_string(&filter).unwrap(), "Show # good");
+        assert_eq!(fs::read_to_string(&sound).unwrap(), "good");
+        let mut left: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .chain(fs::read_dir(&nested).unwrap())
+            .map(|v| v.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["A.filter", "alert.mp3", "sounds"]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Installing filters from other programs, with progress reporting.
+
+use std::{
+    ffi::OsString,
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+};
+
+use crate::{
+    cancel,
+    http::Transport,
+    progress::{Event, Progress},
+    update_with, Error, Options, Summary,
+};
+
+/// Updates sources the same way the command line does, reporting progress
+/// along the way. Options that only affect launching the game (such as
+/// `pause` or `environment`) have no effect.
+#[derive(Debug, Clone)]
+pub struct Installer {
+    options: Options,
+    progress: Progress,
+    transport: Option<Arc<dyn Transport>>,
+    cancel: cancel::Token,
+}
+
+impl Installer {
+    pub fn new(options: Options) -> Self {
+        Installer {
+            options,
+            progress: Progress::default(),
+            transport: None,
+            cancel: cancel::Token::default(),
+        }
+    }
+
+    /// Calls `callback` with every [`Event`].
+    pub fn on_progress(mut self, callback: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        self.progress = Progress::new(callback);
+        self
+    }
+
+    /// Sends every [`Event`] to the returned channel instead, e.g. to
+    /// receive them on a UI thread.
+    pub fn events(self) -> (Self, Receiver<Event>) {
+        let (sender, receiver) = channel();
+        let installer = self.on_progress(move |event| {
+            // Nobody listening anymore is fine.
+            let _ = sender.send(event);
+        });
+        (installer, receiver)
+    }
+
+    /// Sends all requests with `transport` instead of the built-in client.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Stops updating once `token` is cancelled. Sources that were already
+    /// updated are kept, the one being installed is left as it was.
+    pub fn with_cancellation(mut self, token: cancel::Token) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Updates the sources (`kind:value` or a builtin name).
+    pub async fn install(&self, sources: Vec<OsString>) -> Result<Summary, Error> {
+        Ok(update_with(sources, &self.options, |globals| {
+            globals.progress = self.progress.clone();
+            globals.cancel = self.cancel.clone();
+            if let Some(transport) = &self.transport {
+                globals.transport = transport.clone();
+            }
+        })
+        .await?)
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::Result;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{cache, install, Globals};
+
+#[derive(Debug, Clone, Deserialize)]
+struct Leagues {
+    result: Vec<League>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct League {
+    id: String,
+}
+
+fn leagues_file(path: &Path) -> PathBuf {
+    path.join("filter_leagues.json")
+}
+
+async fn fetch(globals: &Globals) -> Result<BTreeSet<String>> {
+    let leagues: Leagues =
+        serde_json::from_str(&cache::fetch(globals, globals.game.leagues_url()).await?)?;
+
+    Ok(leagues.result.into_iter().map(|v| v.id).collect())
+}
+
+/// Checks whether a league started since the last run, in which case filters
+/// should be re-installed since authors release league-start versions.
+pub async fn league_started(globals: &Globals) -> bool {
+    let current = match fetch(globals).await {
+        Ok(current) => current,
+        Err(error) => {
+            warn!("could not check for new leagues: {error}");
+            return false;
+        }
+    };
+
+    let path = leagues_file(&globals.game_directory);
+    let previous: Option<BTreeSet<String>> = fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|v| serde_json::from_str(&v).ok());
+
+    if previous.as_ref() == Some(&current) {
+        return false;
+    }
+
+    debug!("leagues changed from {previous:?} to {current:?}");
+    if let Ok(s) = serde_json::to_string_pretty(&current) {
+        if let Err(error) = install::write_file(&globals.game_directory, &path, s.as_bytes()).await
+        {
+            warn!("could not save the league list: {error}");
+        }
+    }
+
+    // A first run has nothing to compare against, and leagues ending doesn't
+    // need new filters.
+    let Some(previous) = previous else {
+        return false;
+    };
+    let started: Vec<_> = current.difference(&previous).collect();
+    if started.is_empty() {
+        return false;
+    }
+
+    info!("new league(s) started: {started:?}");
+    true
+}
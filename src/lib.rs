@@ -0,0 +1,1075 @@
+//! Keeps Path of Exile 2 item filters up to date. The `poe2filter` binary is a
+//! thin command line interface over this crate, which can also be used by
+//! other tools to install filters without shelling out.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env::var_os,
+    ffi::{OsStr, OsString},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+use futures_util::{stream, StreamExt as _};
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use tokio::{
+    fs,
+    net::TcpStream,
+    time::{error::Elapsed, sleep, timeout, timeout_at, Instant},
+};
+
+use crate::{
+    events::EventLog,
+    game::game,
+    progress::{Event, Progress},
+};
+
+mod archive;
+mod cache;
+pub mod cancel;
+pub mod checksum;
+pub mod client_log;
+pub mod config;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+mod dirs;
+mod error;
+mod events;
+pub mod file;
+pub mod filterblade;
+pub mod game;
+pub mod github;
+pub mod gitlab;
+mod guard;
+pub mod hooks;
+pub mod http;
+pub mod install;
+mod installer;
+mod league;
+pub mod logging;
+pub mod manage;
+pub mod metrics;
+pub mod minisign;
+pub mod notify;
+pub mod pipeline;
+pub mod preview;
+pub mod process;
+pub mod progress;
+pub mod report;
+pub mod rpc;
+pub mod schedule;
+pub mod script;
+pub mod source;
+pub mod steam;
+mod store;
+#[cfg(unix)]
+pub mod systemd;
+mod text;
+pub mod url;
+mod vdf;
+mod version_check;
+pub mod watch;
+mod webhook;
+mod wine;
+
+pub use error::Error;
+pub use game::Game;
+pub use installer::Installer;
+pub use source::Source;
+
+/// How many sources are updated at the same time, unless overridden.
+pub const DEFAULT_JOBS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct Globals {
+    pub game_directory: PathBuf,
+    pub(crate) game: Game,
+    pub versions: HashMap<String, String>,
+    /// The files each source installed, relative to the game directory.
+    pub(crate) installed_files: HashMap<String, Vec<PathBuf>>,
+    /// Used to build requests, which are sent with the `transport`.
+    pub client: Client,
+    pub transport: Arc<dyn http::Transport>,
+    pub(crate) github_token: Option<String>,
+    pub(crate) gitlab_token: Option<String>,
+    pub(crate) etags: http::Etags,
+    pub(crate) rate_limit_reset: github::RateLimitReset,
+    pub(crate) run_cache: github::RunCache,
+    /// How many times failed requests are retried.
+    pub retries: u32,
+    pub max_download_size: Option<u64>,
+    pub throttle: Option<http::Throttle>,
+    pub(crate) signing_keys: HashMap<String, minisign::PublicKey>,
+    pub(crate) pipelines: HashMap<String, pipeline::Pipeline>,
+    /// Where files are installed, the first one that matches is used. The
+    /// directories are absolute.
+    pub targets: Vec<install::Target>,
+    /// The network is unreachable, sources can only be installed from the
+    /// cache.
+    pub offline: bool,
+    /// Nothing is waiting for the update to finish, so it's fine to wait for
+    /// rate limits.
+    pub(crate) can_wait: bool,
+    /// The latest watermark of GitHub sources, when they were looked up
+    /// together up front.
+    pub(crate) latest: HashMap<String, String>,
+    pub progress: Progress,
+    /// Cancelled by signals, or when the deadline passes.
+    pub cancel: cancel::Token,
+    pub(crate) counters: Arc<metrics::Counters>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub clear: bool,
+    /// Used instead of looking for the game directory.
+    pub game_directory: Option<PathBuf>,
+    /// The Wine prefix of the standalone client, searched before anything
+    /// else.
+    pub prefix: Option<PathBuf>,
+    pub mount_timeout: Option<Duration>,
+    /// How long to wait for the network to come up before only installing
+    /// what was already downloaded.
+    pub network_timeout: Option<Duration>,
+    pub ignore_running: bool,
+    /// Detected from the app id Steam launches when not set.
+    pub game: Option<Game>,
+    /// Sources that are for another game than `game`.
+    pub source_games: Vec<(String, Game)>,
+    pub app_id: Option<OsString>,
+    /// Launch the command immediately and update in a detached process.
+    pub background: bool,
+    /// Run the command as a child process instead of replacing this one.
+    pub wait: bool,
+    /// Show a desktop notification with the results, `None` means only when
+    /// launching the game (where stderr can't be seen).
+    pub notify: Option<bool>,
+    /// How often sources are checked in watch mode.
+    pub interval: Duration,
+    /// Restore deleted or truncated filters in watch mode.
+    pub guard: bool,
+    /// How long updates may take before the game is launched anyway.
+    pub deadline: Option<Duration>,
+    pub hooks: hooks::Hooks,
+    /// Re-install all sources when a new league starts.
+    pub league_check: bool,
+    /// Check for new releases of poe2filter once a day.
+    pub version_check: bool,
+    /// How long to wait before launching the game when something changed.
+    pub pause: Option<Duration>,
+    /// Alternative sets of sources, selected based on the game log.
+    pub profiles: Vec<client_log::Profile>,
+    /// Overrides the location of the game's `Client.txt`.
+    pub client_log: Option<PathBuf>,
+    /// Token used for GitHub API requests, which raises the rate limit and
+    /// gives access to private repositories.
+    pub github_token: Option<String>,
+    /// Token used for GitLab API requests, which gives access to private
+    /// projects.
+    pub gitlab_token: Option<String>,
+    /// How many times failed requests are retried.
+    pub retries: u32,
+    pub timeouts: http::Timeouts,
+    /// Don't use the proxy from `HTTPS_PROXY` etc.
+    pub no_system_proxy: bool,
+    /// Additional CA certificates (PEM) to trust.
+    pub ca_bundle: Option<PathBuf>,
+    /// Maximum combined download speed in bytes per second.
+    pub limit_rate: Option<u64>,
+    /// Keys that releases of a source must be signed with, by source.
+    pub signing_keys: Vec<(String, minisign::PublicKey)>,
+    /// Stages the filters of a source go through before they are installed,
+    /// by source and in order.
+    pub stages: Vec<(String, pipeline::Stage)>,
+    /// Where other files than filters are installed (or filters, instead of
+    /// the game directory), checked in order before the default.
+    pub targets: Vec<install::Target>,
+    /// The largest download (and extracted file) that is accepted.
+    pub max_download_size: Option<u64>,
+    /// Proxy used for all requests, instead of the one from `HTTPS_PROXY` etc.
+    pub proxy: Option<String>,
+    /// Replaces the User-Agent sent with every request.
+    pub user_agent: Option<String>,
+    /// Contact details (an email address or URL) added to the User-Agent.
+    pub contact: Option<String>,
+    /// How many sources are updated at the same time.
+    pub jobs: usize,
+    /// URLs that are notified after updates.
+    pub webhooks: Vec<String>,
+    /// Where to write JSON events (`-` for stdout, `fd:N`, or a file).
+    pub events: Option<String>,
+    /// Print what the update did and how long each source took.
+    pub timings: bool,
+    /// Where to write metrics for the node exporter's textfile collector
+    /// after each run.
+    pub metrics_file: Option<PathBuf>,
+    /// Environment variables to set (or unset, if `None`) for the command.
+    pub environment: Vec<(OsString, Option<OsString>)>,
+    /// Whether a command to execute was provided, i.e. poe2filter is being
+    /// used as a launch wrapper.
+    pub wrapping: bool,
+}
+
+/// A source that was updated during this run.
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub source: String,
+    pub watermark: String,
+    pub files: Vec<PathBuf>,
+}
+
+/// The outcome of updating all sources.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub updated: Vec<Update>,
+    pub failed: Vec<(String, Error)>,
+    pub metrics: metrics::Metrics,
+}
+
+impl Summary {
+    fn merge(&mut self, other: Summary) {
+        self.updated.extend(other.updated);
+        self.failed.extend(other.failed);
+        self.metrics.add(other.metrics);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    pub zipball_url: String,
+    pub watermark: String,
+    pub body: Option<String>,
+    /// The files that were installed from this version.
+    pub files: Vec<PathBuf>,
+    /// Where to download the `SHA256SUMS` of this version, if it has one.
+    pub checksums_url: Option<String>,
+    /// Where to download the minisign signature of the `SHA256SUMS`.
+    pub signature_url: Option<String>,
+}
+
+impl Globals {
+    pub async fn new(options: &Options) -> Result<Self> {
+        let game_directory = locate_game_directory(options)
+            .await
+            .wrap_err_with(|| "could not find game directory")?;
+
+        let client = http::client(options)?;
+
+        let targets: Vec<_> = options
+            .targets
+            .iter()
+            .cloned()
+            .chain([install::Target::filters()])
+            .map(|v| install::Target {
+                directory: game_directory.join(&v.directory),
+                ..v
+            })
+            .collect();
+        // Subdirectories are searched too, so targets inside of another one
+        // are covered already.
+        let mut directories = HashSet::new();
+        for target in targets.iter().filter(|v| directories.insert(&v.directory)) {
+            let directory = &target.directory;
+            if !targets
+                .iter()
+                .any(|v| v.directory != *directory && directory.starts_with(&v.directory))
+            {
+                install::remove_temp_files(directory).await;
+            }
+        }
+
+        let store = store::read(&game_directory).await;
+        let versions = store
+            .iter()
+            .filter_map(|(source, v)| Some((source.clone(), v.watermark.clone()?)))
+            .collect();
+        let installed_files = store
+            .into_iter()
+            .map(|(source, v)| (source, v.files))
+            .collect();
+
+        let etags = fs::read_to_string(etags_file(&game_directory))
+            .await
+            .ok()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default();
+
+        Ok(Globals {
+            game_directory,
+            game: game(options),
+            versions,
+            installed_files,
+            transport: Arc::new(client.clone()),
+            client,
+            github_token: options.github_token.clone(),
+            gitlab_token: options.gitlab_token.clone(),
+            etags: Arc::new(Mutex::new(etags)),
+            rate_limit_reset: Default::default(),
+            run_cache: Default::default(),
+            retries: options.retries,
+            max_download_size: options.max_download_size,
+            throttle: options.limit_rate.map(http::Throttle::new),
+            offline: false,
+            can_wait: !options.wrapping || options.background,
+            latest: HashMap::new(),
+            progress: Progress::default(),
+            cancel: cancel::Token::default(),
+            counters: Default::default(),
+            signing_keys: options.signing_keys.iter().cloned().collect(),
+            targets,
+            pipelines: options.stages.iter().fold(
+                HashMap::new(),
+                |mut pipelines: HashMap<_, pipeline::Pipeline>, (source, stage)| {
+                    pipelines
+                        .entry(source.clone())
+                        .or_default()
+                        .stages
+                        .push(stage.clone());
+                    pipelines
+                },
+            ),
+        })
+    }
+}
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("spawn async runtime");
+
+    rt.block_on(future)
+}
+
+pub fn run(sources: Vec<OsString>, options: &Options) -> Result<Summary, Error> {
+    block_on(update(sources, options))
+}
+
+pub async fn update(sources: Vec<OsString>, options: &Options) -> Result<Summary, Error> {
+    Ok(update_with(sources, options, |_| {}).await?)
+}
+
+/// Like [`update`], with `setup` being able to change the [`Globals`] (e.g. to
+/// report progress) before anything is updated.
+///
+/// Sources for the other game are updated afterwards, in its own game
+/// directory, unless the game is being launched.
+async fn update_with(
+    sources: Vec<OsString>,
+    options: &Options,
+    setup: impl FnOnce(&mut Globals),
+) -> Result<Summary> {
+    let game = game(options);
+    let (mut current, mut others) = (Vec::new(), BTreeMap::<Game, Vec<_>>::new());
+    for source in sources {
+        let resolved = resolve_alias(&source.to_string_lossy()).to_string();
+        match options.source_games.iter().find(|(v, _)| *v == resolved) {
+            Some((_, other)) if *other != game => others.entry(*other).or_default().push(source),
+            _ => current.push(source),
+        }
+    }
+
+    let mut summary = match current.is_empty() && !others.is_empty() {
+        true => Summary::default(),
+        false => update_game(current, options, setup).await?,
+    };
+    for (other, sources) in others {
+        if options.wrapping && !options.background {
+            info!(
+                "not updating {} source(s) for {other} while starting {game}",
+                sources.len()
+            );
+            continue;
+        }
+
+        info!("updating {} source(s) for {other}", sources.len());
+        let options = Options {
+            game: Some(other),
+            // These are for the game of the other sources.
+            game_directory: None,
+            prefix: None,
+            app_id: None,
+            ..options.clone()
+        };
+        match update_game(sources.clone(), &options, |_| {}).await {
+            Ok(other) => summary.merge(other),
+            Err(error) => {
+                for source in sources {
+                    let error = eyre!("could not update the {other} filters: {error:#}");
+                    summary
+                        .failed
+                        .push((source.to_string_lossy().into_owned(), error.into()));
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Updates the sources of one game.
+async fn update_game(
+    sources: Vec<OsString>,
+    options: &Options,
+    setup: impl FnOnce(&mut Globals),
+) -> Result<Summary> {
+    let started = Instant::now();
+    // The deadline is only relevant when the game is waiting on us, and
+    // covers everything up to launching it.
+    let deadline = options
+        .deadline
+        .filter(|_| options.wrapping && !options.background);
+    let deadline_at = deadline.map(|v| started + v);
+    if !options.wrapping && !options.ignore_running {
+        if let Some(pid) = process::find_running_game() {
+            warn!("the game is running (pid {pid}), not updating filters while it is in use");
+            warn!("run again after closing the game, or pass --ignore-running to update anyway");
+            return Ok(Summary::default());
+        }
+    }
+
+    let sources = match client_log::select_profile(options).await {
+        Some(profile) => profile.sources.clone(),
+        None => sources,
+    };
+
+    // The network may only be reachable through the proxy.
+    let proxied = options.proxy.is_some() || (!options.no_system_proxy && http::proxy_from_env());
+    let offline = needs_network(&sources) && !proxied && !wait_for_network(options).await;
+    if offline {
+        info!("the network is unreachable, only installing what was already downloaded");
+    }
+
+    let mut globals = Globals::new(options).await?;
+    globals.offline = offline;
+    let events = options.events.as_deref().map(EventLog::open).transpose()?;
+    if let Some(events) = &events {
+        globals.progress = events.progress();
+    }
+    setup(&mut globals);
+    globals.transport = Arc::new(metrics::CountingTransport {
+        inner: globals.transport.clone(),
+        counters: globals.counters.clone(),
+    });
+
+    if options.clear {
+        //  This code isn't going to win any awards
+        globals.versions.clear();
+    }
+
+    if game_was_patched(&globals.game_directory, &app_id(options)).await {
+        info!("the game was patched since the last run, re-installing all sources");
+        globals.versions.clear();
+    }
+
+    if options.league_check
+        && !offline
+        && needs_network(&sources)
+        && before(deadline_at, league::league_started(&globals))
+            .await
+            .unwrap_or_else(|_| {
+                warn!("the league check took too long, skipping it");
+                false
+            })
+    {
+        info!("a new league started since the last run, re-installing all sources");
+        globals.versions.clear();
+    }
+
+    if options.version_check && !offline {
+        let check = version_check::check(&globals, options.notify.unwrap_or(options.wrapping));
+        if before(deadline_at, check).await.is_err() {
+            debug!("the version check took too long, skipping it");
+        }
+    }
+
+    let game_directory = globals.game_directory.to_string_lossy().into_owned();
+    let source_list: Vec<_> = sources.iter().map(|v| v.to_string_lossy()).collect();
+    let env = [
+        ("POE2FILTER_GAME_DIR", game_directory.clone()),
+        ("POE2FILTER_SOURCES", source_list.join("\n")),
+    ];
+    let pre_update = hooks::run("pre_update", options.hooks.pre_update.as_deref(), &env);
+    if before(deadline_at, pre_update).await.is_err() {
+        warn!("the pre_update hook took too long, killed it");
+    }
+
+    let mut summary = Summary::default();
+    let cancel = globals.cancel.clone();
+    let work = cancel.run(async {
+        if !offline {
+            let github: Vec<_> = sources
+                .iter()
+                .filter_map(|v| v.to_str())
+                .filter_map(|v| resolve_alias(v).strip_prefix("github:"))
+                .collect();
+            globals.latest = github::latest_watermarks(&globals, &github).await;
+        }
+        update_sources(&globals, sources, options.jobs, &mut summary).await
+    });
+
+    match deadline.zip(deadline_at) {
+        Some((deadline, deadline_at)) => match timeout_at(deadline_at, work).await {
+            Ok(Some(())) => {}
+            Ok(None) => warn!("cancelled, keeping the sources that were already updated"),
+            Err(_) => {
+                // Stops extractions that are still running on other threads.
+                cancel.cancel();
+                warn!("updates took longer than {deadline:?}, starting the game with the existing filters");
+            }
+        },
+        None => {
+            if work.await.is_none() {
+                warn!("cancelled, keeping the sources that were already updated");
+            }
+        }
+    }
+
+    for update in &summary.updated {
+        globals
+            .versions
+            .insert(update.source.clone(), update.watermark.clone());
+
+        // Files that were renamed or dropped upstream would stay around
+        // forever otherwise.
+        let files = update
+            .files
+            .iter()
+            .map(|v| store::relative(&globals.game_directory, v))
+            .collect();
+        let previous = globals
+            .installed_files
+            .insert(update.source.clone(), files)
+            .unwrap_or_default();
+        store::remove_files(&globals.game_directory, &previous, &globals.installed_files).await;
+    }
+
+    for (source, error) in &summary.failed {
+        eprintln!("# {source}: failed");
+        eprintln!("{error:#}");
+        eprintln!();
+    }
+
+    summary.metrics.collect(&globals.counters);
+    summary.metrics.total = started.elapsed();
+    if options.timings {
+        eprintln!("# timings");
+        eprintln!("{}", summary.metrics);
+    }
+    if let Some(path) = &options.metrics_file {
+        if let Err(error) = metrics::write_textfile(path, &summary).await {
+            warn!("could not write the metrics to {path:?}: {error}");
+        }
+    }
+
+    info!("saving watermark");
+    let installed = store::merge(&globals.versions, &globals.installed_files);
+    store::write(&globals.game_directory, &installed).await?;
+
+    info!("saved watermark");
+
+    // Losing the ETags only means that the next run makes full requests.
+    let etags = match globals.etags.lock() {
+        Ok(etags) => serde_json::to_string_pretty(&*etags)?,
+        Err(_) => String::new(),
+    };
+    if !etags.is_empty() {
+        let path = etags_file(&globals.game_directory);
+        if let Err(error) =
+            install::write_file(&globals.game_directory, &path, etags.as_bytes()).await
+        {
+            warn!("could not save {path:?}: {error}");
+        }
+    }
+
+    hooks::run(
+        "post_update",
+        options.hooks.post_update.as_deref(),
+        &hooks::summary_env(&game_directory, &summary),
+    )
+    .await;
+    webhook::send(&globals, &options.webhooks, &summary).await;
+    if let Some(events) = &events {
+        events.summary(&summary);
+    }
+
+    Ok(summary)
+}
+
+/// Expands the builtin source names.
+pub fn resolve_alias(source: &str) -> &str {
+    match source {
+        "neversink-lite" => "github:NeverSinkDev/NeverSink-PoE2litefilter",
+        "neversink-lite/main" => "github:NeverSinkDev/NeverSink-PoE2litefilter/main",
+        "cdrg" => "github:cdrg/cdr-poe2filter",
+        "cdrg/main" => "github:cdrg/cdr-poe2filter/main",
+        other => other,
+    }
+}
+
+/// Whether the argument has the form of a source (`kind:value`, or a builtin).
+pub fn is_source(arg: &OsStr) -> bool {
+    arg.to_str()
+        .map(resolve_alias)
+        .and_then(|v| v.split_once(':'))
+        .is_some_and(|(kind, _)| !kind.is_empty())
+}
+
+/// Updates every source, up to `jobs` at a time. A failing source doesn't
+/// prevent the others from being updated.
+async fn update_sources(
+    globals: &Globals,
+    sources: Vec<OsString>,
+    jobs: usize,
+    summary: &mut Summary,
+) {
+    let mut results = stream::iter(sources)
+        .map(|source| async move {
+            let started = Instant::now();
+            let resolved = resolve_alias(&source.to_string_lossy()).to_string();
+            let result = logging::with_source(resolved, update_source(globals, &source)).await;
+            (source, result, started.elapsed())
+        })
+        .buffer_unordered(jobs.max(1));
+
+    while let Some((source, result, duration)) = results.next().await {
+        summary
+            .metrics
+            .sources
+            .push((source.to_string_lossy().into_owned(), duration));
+        match result {
+            Ok(Some(update)) => summary.updated.push(update),
+            Ok(None) => {}
+            Err(error) => {
+                let source = source.to_string_lossy().into_owned();
+                error!(
+                    source = source.as_str(), outcome = "failed", error:% = format!("{error:#}");
+                    "failed to update {source}: {error:?}"
+                );
+                globals.progress.emit(Event::Failed {
+                    source: resolve_alias(&source).to_string(),
+                    error: format!("{error:#}"),
+                });
+                summary.failed.push((source, error.into()));
+            }
+        }
+    }
+}
+
+async fn update_source(globals: &Globals, source: &OsStr) -> Result<Option<Update>> {
+    let source = source
+        .to_str()
+        .ok_or_else(|| Error::InvalidSourceSpec("all arguments must be valid UTF-8".into()))?;
+
+    let source = resolve_alias(source);
+    let (source_name, value) = source::split(source)?;
+
+    let current_version = globals.versions.get(source);
+    info!(
+        source;
+        "updating {source} which has watermark {}...",
+        current_version.map(|v| v.as_str()).unwrap_or("none")
+    );
+    let provider = source::provider(source_name)?;
+    globals.progress.emit(Event::Resolving {
+        source: source.to_string(),
+    });
+    let next_version = match provider.resolve(globals, value, current_version).await? {
+        Some(mut version) => {
+            version.files = provider.fetch(globals, value, &version).await?;
+            Some(version)
+        }
+        None => None,
+    };
+
+    globals.progress.emit(Event::Done {
+        source: source.to_string(),
+        watermark: next_version.as_ref().map(|v| v.watermark.clone()),
+    });
+
+    if let Some(tag) = next_version {
+        eprintln!("# {source}: {}", &tag.watermark);
+        if let Some(body) = tag.body.as_ref() {
+            eprintln!("{body}");
+        }
+        eprintln!();
+
+        info!(
+            source, outcome = "updated", watermark = tag.watermark.as_str();
+            "watermark for {source} updated to {}", tag.watermark
+        );
+        Ok(Some(Update {
+            source: source.to_string(),
+            watermark: tag.watermark,
+            files: tag.files,
+        }))
+    } else {
+        info!(source, outcome = "unchanged"; "{source} is already up to date");
+        Ok(None)
+    }
+}
+
+/// Host used to check whether the network is up before doing any real work.
+const PROBE_HOST: (&str, u16) = ("api.github.com", 443);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Quickly checks whether the network is reachable, so that being offline
+/// results in a single message instead of a pile of HTTP errors.
+async fn is_online() -> bool {
+    match timeout(PROBE_TIMEOUT, TcpStream::connect(PROBE_HOST)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(error)) => {
+            debug!("connectivity probe failed: {error}");
+            false
+        }
+        Err(_) => {
+            debug!("connectivity probe timed out");
+            false
+        }
+    }
+}
+
+/// Runs `future` unless `deadline` passes first.
+async fn before<T>(
+    deadline: Option<Instant>,
+    future: impl Future<Output = T>,
+) -> Result<T, Elapsed> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await,
+        None => Ok(future.await),
+    }
+}
+
+/// Whether any of the sources is downloaded, rather than e.g. only `file:`
+/// sources that are read from disk.
+fn needs_network(sources: &[OsString]) -> bool {
+    sources.iter().any(|source| {
+        let source = source.to_str().map(resolve_alias);
+        !source.is_some_and(|v| v.starts_with("file:"))
+    })
+}
+
+/// Probes the network until it is reachable or `--wait-for-network` passes,
+/// for launches that happen before e.g. Wi-Fi has connected. When launching
+/// the game, it never waits longer than the deadline.
+async fn wait_for_network(options: &Options) -> bool {
+    if is_online().await {
+        return true;
+    }
+    let mut timeout = options.network_timeout.unwrap_or_default();
+    if let Some(deadline) = options
+        .deadline
+        .filter(|_| options.wrapping && !options.background)
+    {
+        timeout = timeout.min(deadline);
+    }
+    if timeout.is_zero() {
+        return false;
+    }
+
+    info!("the network is unreachable, waiting up to {timeout:?} for it to come up...");
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    while Instant::now() < deadline && !cancel::is_cancelled() {
+        attempt += 1;
+        sleep(http::backoff(attempt).min(deadline - Instant::now())).await;
+        if is_online().await {
+            info!("the network is now reachable");
+            return true;
+        }
+    }
+    false
+}
+
+fn split_paths(raw: OsString) -> Vec<PathBuf> {
+    if raw.is_empty() {
+        return Default::default();
+    }
+
+    let mut bytes = raw.as_encoded_bytes();
+    let mut result = Vec::new();
+
+    while !bytes.is_empty() {
+        let index = bytes
+            .iter()
+            .cloned()
+            .enumerate()
+            .find_map(|(idx, v)| if v == b':' { Some(idx) } else { None })
+            .unwrap_or(bytes.len());
+
+        let (current, next) = bytes.split_at(index);
+        if next.is_empty() {
+            break;
+        }
+        bytes = &next[1..]; // Remove the :
+
+        // SAFETY: split at an ASCII character, which leaves valid parts.
+        result.push(PathBuf::from(unsafe {
+            OsStr::from_encoded_bytes_unchecked(current)
+        }));
+    }
+
+    result
+}
+
+/// Removable media (SD cards, USB drives) is mounted under one of these, the
+/// Steam Deck in particular uses `/run/media`.
+const REMOVABLE_MOUNT_ROOTS: [&str; 3] = ["/run/media/", "/media/", "/mnt/"];
+
+async fn wait_for_mount(path: &Path, timeout: Duration) {
+    if !REMOVABLE_MOUNT_ROOTS
+        .iter()
+        .any(|root| path.starts_with(root))
+    {
+        return;
+    }
+
+    if let Ok(true) = fs::try_exists(path).await {
+        return;
+    }
+
+    info!("{path:?} is on removable media, waiting up to {timeout:?} for it to be mounted...");
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        sleep(Duration::from_millis(500)).await;
+        if let Ok(true) = fs::try_exists(path).await {
+            info!("{path:?} is now available");
+            return;
+        }
+    }
+
+    warn!("gave up waiting for {path:?} to be mounted");
+}
+
+/// The Steam app id of the game, preferring explicit configuration over what
+/// Steam provides.
+fn app_id(options: &Options) -> OsString {
+    options
+        .app_id
+        .clone()
+        .or_else(|| var_os("POE2FILTER_APP_ID"))
+        .or_else(|| options.game.is_none().then(launched_app_id).flatten())
+        .unwrap_or_else(|| OsString::from(game(options).app_id()))
+}
+
+/// The app id of the game Steam is launching.
+fn launched_app_id() -> Option<OsString> {
+    var_os("STEAM_COMPAT_APP_ID").or_else(|| var_os("SteamGameId"))
+}
+
+pub async fn locate_game_directory(options: &Options) -> Result<PathBuf, Error> {
+    if let Some(path) = &options.game_directory {
+        if let Some(timeout) = options.mount_timeout {
+            wait_for_mount(path, timeout).await;
+        }
+        info!("using the configured game directory {path:?}");
+        return match fs::try_exists(path).await {
+            Ok(true) => Ok(path.clone()),
+            _ => Err(Error::GameDirNotFound),
+        };
+    }
+
+    let game = game(options);
+    if let Some(prefix) = &options.prefix {
+        info!("checking the configured wine prefix {prefix:?}...");
+        return match wine::game_directory(prefix, game, true).await {
+            Some(path) if create_game_directory(&path).await => Ok(path),
+            _ => Err(Error::GameDirNotFound),
+        };
+    }
+
+    if cfg!(windows) {
+        return windows_game_directory(game).await;
+    }
+
+    let mut paths = Vec::new();
+    let game_id = app_id(options);
+
+    // The prefix Steam provides is the one of the game being launched, which
+    // may be the other game.
+    let launched = launched_app_id();
+    if let Some(compat_path) =
+        var_os("STEAM_COMPAT_DATA_PATH").filter(|_| launched.as_ref().is_none_or(|v| *v == game_id))
+    {
+        let compat_path = PathBuf::from(compat_path);
+        if let Some(timeout) = options.mount_timeout {
+            wait_for_mount(&compat_path, timeout).await;
+        }
+        paths.push(compat_path);
+    }
+
+    if let Some(compat_paths) = var_os("STEAM_COMPAT_LIBRARY_PATHS") {
+        for path in split_paths(compat_paths) {
+            if let Some(timeout) = options.mount_timeout {
+                wait_for_mount(&path, timeout).await;
+            }
+            paths.push(path.join("compatdata").join(&game_id));
+        }
+    }
+
+    if let Some(base_path) = var_os("STEAM_BASE_FOLDER") {
+        let base_path = PathBuf::from(base_path);
+        paths.push(base_path.join("steamapps/compatdata").join(&game_id));
+    }
+
+    if let Some(data_dirs) = var_os("XDG_DATA_DIRS") {
+        for path in split_paths(data_dirs) {
+            paths.push(path.join("Steam/steamapps/compatdata").join(&game_id));
+        }
+    }
+
+    // The prefix is in the library the game is installed in, which can be on
+    // another drive.
+    for library in steam::library_folders(&game_id) {
+        paths.push(library.join("steamapps/compatdata").join(&game_id));
+    }
+
+    // Including Steam installed as a Flatpak or Snap.
+    for (layout, root) in steam::steam_installs() {
+        info!("found {layout} Steam at {root:?}");
+        paths.push(root.join("steamapps/compatdata").join(&game_id));
+    }
+
+    let mut checked_paths = HashSet::new();
+    for path in paths
+        .into_iter()
+        .filter(|v| checked_paths.insert(v.clone()))
+    {
+        let path = path.join("pfx/drive_c/users/steamuser/My Documents/My Games");
+        info!("checking {path:?}...");
+        if let Ok(true) = fs::try_exists(&path).await {
+            let path = path.join(game.name());
+            if create_game_directory(&path).await {
+                return Ok(path);
+            }
+        }
+    }
+
+    let mut prefixes = Vec::new();
+    if let Some(prefix) = var_os("WINEPREFIX") {
+        prefixes.push(PathBuf::from(prefix));
+    }
+    if let Some(home) = var_os("HOME") {
+        prefixes.push(PathBuf::from(home).join(".wine"));
+    }
+
+    for prefix in prefixes {
+        info!("checking wine prefix {prefix:?}...");
+        let Some(path) = wine::game_directory(&prefix, game, false).await else {
+            continue;
+        };
+        if create_game_directory(&path).await {
+            return Ok(path);
+        }
+    }
+
+    Err(Error::GameDirNotFound)
+}
+
+/// When running natively on Windows, the game directory is in the Documents
+/// folder, which OneDrive moves into its own folder when it backs it up.
+async fn windows_game_directory(game: Game) -> Result<PathBuf, Error> {
+    for variable in ["OneDrive", "USERPROFILE"] {
+        let Some(root) = var_os(variable) else {
+            continue;
+        };
+        let path = PathBuf::from(root).join("Documents").join("My Games");
+        info!("checking {path:?}...");
+        if let Ok(true) = fs::try_exists(&path).await {
+            let path = path.join(game.name());
+            if create_game_directory(&path).await {
+                return Ok(path);
+            }
+        }
+    }
+
+    Err(Error::GameDirNotFound)
+}
+
+/// Creates the game directory if the game hasn't yet.
+async fn create_game_directory(path: &Path) -> bool {
+    info!("attempting to create game data directory at {path:?}");
+    let created = fs::create_dir_all(path)
+        .await
+        .inspect_err(|error| warn!("failed to create directory: {error:?}"))
+        .is_ok();
+    if created {
+        info!("found game directory");
+    }
+    created
+}
+
+/// The watermark of every installed source.
+pub(crate) async fn installed_versions(options: &Options) -> BTreeMap<String, String> {
+    let Ok(directory) = locate_game_directory(options).await else {
+        return BTreeMap::new();
+    };
+    store::read(&directory)
+        .await
+        .into_iter()
+        .filter_map(|(source, v)| Some((source, v.watermark?)))
+        .collect()
+}
+
+fn releases_file(path: &Path) -> PathBuf {
+    path.join("filter_watermarks.json")
+}
+
+fn etags_file(path: &Path) -> PathBuf {
+    path.join("filter_etags.json")
+}
+
+fn game_build_file(path: &Path) -> PathBuf {
+    path.join("filter_game_build.txt")
+}
+
+/// Compares the Steam build id of the game with the one from the last run, and
+/// remembers the current one. Filter authors usually ship compatibility fixes
+/// right after a patch.
+async fn game_was_patched(game_directory: &Path, app_id: &OsStr) -> bool {
+    let Some(build) = steam::game_build(app_id).await else {
+        return false;
+    };
+
+    let path = game_build_file(game_directory);
+    let previous = fs::read_to_string(&path).await.ok();
+    if previous.as_deref().map(str::trim) == Some(build.as_str()) {
+        return false;
+    }
+
+    debug!("game build changed from {previous:?} to {build}");
+    if let Err(error) = install::write_file(game_directory, &path, build.as_bytes()).await {
+        warn!("could not save the game build: {error}");
+    }
+
+    // A first run has nothing to compare against.
+    previous.is_some()
+}
+
+fn split(mut v: &str, c: char) -> Vec<&str> {
+    let mut result = Vec::new();
+
+    while !v.is_empty() {
+        let index = v.find(c).unwrap_or(v.len());
+        let (item, next) = v.split_at(index);
+
+        if !item.is_empty() {
+            result.push(item);
+        }
+
+        if next.is_empty() {
+            break;
+        }
+
+        v = &next[1..]; // Remove delimeter
+    }
+
+    result
+}
@@ -0,0 +1,405 @@
+//! Logging to stderr and, optionally, to a file: the output of a game's
+//! launch wrapper is lost when Steam starts it, which makes a filter that
+//! didn't update impossible to debug after the fact.
+
+use std::{
+    cell::RefCell,
+    env::var,
+    fmt::Arguments,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::{eyre::bail, Report};
+use env_logger::fmt::Formatter;
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    warn, LevelFilter, Log, Metadata, Record,
+};
+use serde_json::{json, Map};
+
+use crate::{dirs, http};
+
+/// The log is rotated once it grows beyond this.
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated logs are kept.
+const KEEP: usize = 5;
+
+/// Rotated logs older than this are removed, however many there are.
+const MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+const LOG_NAME: &str = "poe2filter.log";
+
+tokio::task_local! {
+    /// The source being updated by the current task.
+    static SOURCE: String;
+}
+
+thread_local! {
+    /// The source being updated on a blocking thread, see [`spawn_blocking`].
+    static BLOCKING_SOURCE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs the update of a source, tagging everything it logs with the source.
+/// Sources are updated concurrently, and their records would be impossible
+/// to tell apart otherwise.
+pub(crate) async fn with_source<F: Future>(source: String, future: F) -> F::Output {
+    SOURCE.scope(source, future).await
+}
+
+/// Like [`tokio::task::spawn_blocking`], keeping the source of the task that
+/// spawned it.
+pub(crate) fn spawn_blocking<R: Send + 'static>(
+    f: impl FnOnce() -> R + Send + 'static,
+) -> tokio::task::JoinHandle<R> {
+    let source = current_source();
+    tokio::task::spawn_blocking(move || {
+        // The thread is reused for other work once this is done.
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                BLOCKING_SOURCE.with(|v| v.borrow_mut().take());
+            }
+        }
+
+        BLOCKING_SOURCE.with(|v| *v.borrow_mut() = source);
+        let _reset = Reset;
+        f()
+    })
+}
+
+fn current_source() -> Option<String> {
+    SOURCE
+        .try_with(|v| v.clone())
+        .ok()
+        .or_else(|| BLOCKING_SOURCE.with(|v| v.borrow().clone()))
+}
+
+/// `$XDG_STATE_HOME/poe2filter/logs`.
+pub fn logs_directory() -> Option<PathBuf> {
+    dirs::state_home().map(|v| v.join("poe2filter/logs"))
+}
+
+/// How log records are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// For people.
+    #[default]
+    Text,
+    /// One JSON object per record, with the record's key-values (such as
+    /// `source`, `url` and `outcome`) as fields.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self, Report> {
+        match value {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => bail!("unknown log format {value:?}, expected text or json"),
+        }
+    }
+}
+
+/// How logging is set up. This happens before the arguments are parsed, so
+/// it has its own settings.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Only print errors to stderr, or nothing at all when `quiet`, unless
+    /// `POE2FILTER_LOG` says otherwise.
+    pub quiet: bool,
+    /// Also log everything from info up to a file.
+    pub to_file: bool,
+    /// The file to log to instead of the one in [`logs_directory`], implies
+    /// `to_file`.
+    pub file: Option<PathBuf>,
+    /// What to log, as a level (`debug`) or `POE2FILTER_LOG`-style filters
+    /// (`poe2filter=debug,reqwest=info`), for both stderr and the file.
+    /// Replaces `POE2FILTER_LOG`.
+    pub level: Option<String>,
+    pub format: Format,
+    /// Log every HTTP request, whatever the level.
+    pub trace_http: bool,
+}
+
+/// Sets up logging.
+pub fn init(settings: &Settings) {
+    let mut builder = match settings.format {
+        Format::Text => pretty_env_logger::formatted_builder(),
+        Format::Json => json_builder(),
+    };
+    let filters = settings.filters();
+    match &filters {
+        Some(filters) => builder.parse_filters(filters),
+        None if settings.quiet => builder.filter_level(LevelFilter::Off),
+        None => builder.filter_level(LevelFilter::Error),
+    };
+    if settings.trace_http {
+        builder.filter_module(http::TRACE_TARGET, LevelFilter::Info);
+    }
+    let stderr = builder.build();
+
+    let to_file = settings.to_file || settings.file.is_some();
+    let file = to_file.then(|| file_logger(settings, filters.as_deref()));
+    let (file, error) = match file {
+        Some(Ok(file)) => (Some(file), None),
+        Some(Err(error)) => (None, Some(error)),
+        None => (None, None),
+    };
+
+    let level = stderr
+        .filter()
+        .max(file.as_ref().map_or(LevelFilter::Off, |v| v.filter()));
+    log::set_max_level(level);
+    let logger = Logger {
+        stderr,
+        file,
+        format: settings.format,
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        return;
+    }
+
+    if let Some(error) = error {
+        warn!("could not open the log file: {error}");
+    }
+}
+
+impl Settings {
+    fn filters(&self) -> Option<String> {
+        self.level.clone().or_else(|| var("POE2FILTER_LOG").ok())
+    }
+}
+
+fn file_logger(settings: &Settings, filters: Option<&str>) -> io::Result<env_logger::Logger> {
+    let path = settings
+        .file
+        .clone()
+        .or_else(|| logs_directory().map(|v| v.join(LOG_NAME)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+    let file = RotatingFile::open(&path)?;
+
+    let mut builder = match settings.format {
+        Format::Text => {
+            let mut builder = env_logger::Builder::new();
+            builder.format_timestamp_secs();
+            builder
+        }
+        Format::Json => json_builder(),
+    };
+    match filters {
+        Some(filters) => builder.parse_filters(filters),
+        None => builder.filter_level(LevelFilter::Info),
+    };
+    if settings.trace_http {
+        builder.filter_module(http::TRACE_TARGET, LevelFilter::Info);
+    }
+    Ok(builder
+        .write_style(env_logger::WriteStyle::Never)
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .build())
+}
+
+fn json_builder() -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    builder.format(write_json);
+    builder
+}
+
+/// Writes a record as a line of JSON, e.g.
+/// `{"time":"...","level":"INFO","target":"poe2filter","message":"...","source":"github:..."}`.
+fn write_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut fields = Map::new();
+    fields.insert("time".into(), json!(buf.timestamp_millis().to_string()));
+    fields.insert("level".into(), json!(record.level().as_str()));
+    fields.insert("target".into(), json!(record.target()));
+    fields.insert("message".into(), json!(record.args().to_string()));
+    // Fields that can't be collected are left out rather than losing the
+    // record.
+    let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+    writeln!(buf, "{}", serde_json::Value::Object(fields))
+}
+
+struct JsonFields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_bool() {
+            json!(v)
+        } else if let Some(v) = value.to_u64() {
+            json!(v)
+        } else if let Some(v) = value.to_i64() {
+            json!(v)
+        } else if let Some(v) = value.to_f64() {
+            json!(v)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+/// Sends every record to stderr and the log file, each with its own filter.
+struct Logger {
+    stderr: env_logger::Logger,
+    file: Option<env_logger::Logger>,
+    format: Format,
+}
+
+impl Logger {
+    fn write(&self, record: &Record) {
+        self.stderr.log(record);
+        if let Some(file) = &self.file {
+            file.log(record);
+        }
+    }
+}
+
+/// The key-values of a record, plus the source it was logged for.
+struct WithSource<'a> {
+    inner: &'a dyn kv::Source,
+    source: &'a str,
+}
+
+impl kv::Source for WithSource<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        self.inner.visit(visitor)?;
+        visitor.visit_pair(Key::from_str("source"), Value::from(self.source))
+    }
+}
+
+/// A copy of the record with other arguments and key-values.
+fn rebuild<'a>(record: &Record<'a>, args: Arguments<'a>, kvs: &'a dyn kv::Source) -> Record<'a> {
+    Record::builder()
+        .args(args)
+        .level(record.level())
+        .target(record.target())
+        .module_path(record.module_path())
+        .file(record.file())
+        .line(record.line())
+        .key_values(kvs)
+        .build()
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || self.file.as_ref().is_some_and(|v| v.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        let tagged = record.key_values().get(Key::from_str("source")).is_some();
+        let Some(source) = current_source().filter(|_| !tagged) else {
+            return self.write(record);
+        };
+
+        let kvs = WithSource {
+            inner: record.key_values(),
+            source: &source,
+        };
+        match self.format {
+            // The text format doesn't show key-values.
+            Format::Text => self.write(&rebuild(
+                record,
+                format_args!("[{source}] {}", record.args()),
+                &kvs,
+            )),
+            Format::Json => self.write(&rebuild(record, *record.args(), &kvs)),
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(file) = &self.file {
+            file.flush();
+        }
+    }
+}
+
+/// The log file, which is moved aside as `poe2filter.log.1` (and so on)
+/// whenever it gets too large.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        remove_old_logs(path);
+
+        if fs::metadata(path).is_ok_and(|v| v.len() >= MAX_SIZE) {
+            rotate(path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+        })
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size >= MAX_SIZE {
+            // Another process may have rotated it already, in which case this
+            // only rotates its fresh log early.
+            rotate(&self.path)?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = 0;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// Moves `poe2filter.log` to `poe2filter.log.1`, `.1` to `.2` and so on,
+/// dropping the oldest.
+fn rotate(path: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(rotated_path(path, KEEP));
+    for index in (1..KEEP).rev() {
+        let _ = fs::rename(rotated_path(path, index), rotated_path(path, index + 1));
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+fn remove_old_logs(path: &Path) {
+    let now = SystemTime::now();
+    for index in 1..=KEEP {
+        let path = rotated_path(path, index);
+        let age = fs::metadata(&path)
+            .and_then(|v| v.modified())
+            .ok()
+            .and_then(|v| now.duration_since(v).ok());
+        if age.is_some_and(|v| v > MAX_AGE) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
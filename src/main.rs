@@ -1,9 +1,12 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    env::{args_os, var_os},
-    ffi::{CString, OsStr, OsString},
-    os::unix::prelude::OsStrExt,
+    collections::VecDeque,
+    env::{args_os, var, var_os},
+    ffi::{OsStr, OsString},
+    io::Write as _,
     path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+    time::Duration,
 };
 
 use color_eyre::{
@@ -11,278 +14,876 @@ use color_eyre::{
     Result,
 };
 use log::{debug, error, info, warn};
-use reqwest::{Client, ClientBuilder};
-use tokio::{fs, io::AsyncWriteExt};
-
-mod github;
-
-#[derive(Debug, Clone)]
-struct Globals {
-    game_directory: PathBuf,
-    versions: HashMap<String, String>,
-    client: Client,
-}
-
-#[derive(Debug, Clone)]
-struct VersionInfo {
-    zipball_url: String,
-    watermark: String,
-    body: Option<String>,
-}
-
-impl Globals {
-    async fn new() -> Result<Self> {
-        let game_directory = locate_game_directory()
-            .await
-            .wrap_err_with(|| "could not find game directory")?;
-
-        let client = ClientBuilder::new()
-            .user_agent("poe2filter")
-            .build()
-            .wrap_err_with(|| "could not create an HTTP client")?;
-
-        let mut versions = HashMap::default();
-        if let Ok(store) = fs::read_to_string(releases_file(&game_directory)).await {
-            if let Ok(existing_versions) = serde_json::from_str(&store).inspect_err(|error| {
-                error!("could not read existing files, starting from scratch: {error}")
-            }) {
-                versions = existing_versions;
-            }
-        }
+use poe2filter::{
+    block_on, cancel, client_log, config, github, hooks, http, is_source, locate_game_directory,
+    logging, manage, minisign, notify, pipeline, preview, process, report, resolve_alias, rpc, run,
+    schedule, steam, watch, Options, DEFAULT_JOBS,
+};
 
-        Ok(Globals {
-            game_directory,
-            versions,
-            client,
-        })
-    }
-}
+/// How long the game launch may be delayed by updates, unless overridden.
+const DEFAULT_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Subcommands whose arguments can be absolute paths, which are not the
+/// start of a command to run.
+const PATH_SUBCOMMANDS: [&str; 2] = ["preview", "bundle-report"];
+
+/// Subcommands that use the sources of the configuration when none are
+/// given.
+const SOURCE_SUBCOMMANDS: [&str; 6] = ["update", "run", "check", "list", "watch", "serve"];
+
+const USAGE: &str = "\
+Keeps Path of Exile 2 item filters up to date.
+
+Usage:
+  poe2filter [options] [sources...] -- <game command>  update, then start the game
+  poe2filter run [options] [sources...] -- <command>   the same
+  poe2filter update [options] [sources...]             only update
+  poe2filter check [sources...]                        show which sources have updates
+  poe2filter list [sources...]                         show the installed sources
+  poe2filter remove <sources...>                       uninstall sources
+  poe2filter watch [sources...]                        keep updating while the game runs
+  poe2filter serve [--stdio] [sources...]              serve DBus (or JSON-RPC on stdio)
+  poe2filter install-steam <sources...>                add poe2filter to the launch options
+  poe2filter systemd|schedule ...                      update on a timer
+  poe2filter preview <filter> [--match <text>] [--png <path>]
+  poe2filter bundle-report [path]                      collect what's needed for a bug report
+  poe2filter ratelimit                                 show the GitHub rate limit
+
+Sources:
+  github:<owner>/<repo>[/<branch>], gitlab:<owner>/<repo>[/<branch>], url:<URL>,
+  file:<path>, filterblade:<link>, or neversink-lite, cdrg (with /main for the branch)
+
+Common options:
+  --game-dir <path>     use this game directory instead of looking for it
+  --prefix <path>       look for it in this Wine prefix (standalone client)
+  --game <poe1|poe2>    install filters for Path of Exile 1 instead
+  --force, --clear      re-install every source
+  --config <path>       read the configuration from this file
+  --log-level <level>   log more (info, debug), or set POE2FILTER_LOG
+  --stage <source>=<stage>, --target <pattern>=<directory>, --deadline <seconds>, ...
+
+Sources can also be given in ~/.config/poe2filter/config.toml. See the README for
+every option: https://github.com/jcdickinson/poe2filter
+";
 
 fn main() -> Result<()> {
-    pretty_env_logger::init_custom_env("POE2FILTER_LOG");
-
     let sep = OsString::from("--");
-    let clear_flag = OsString::from("--clear");
     let mut args: VecDeque<_> = args_os().collect();
 
+    // Logging (and the configuration, which can set the log level) has to be
+    // set up before the arguments are parsed.
+    let flag = |name: &str| args.iter().take_while(|v| **v != sep).any(|v| v == name);
+    let value = |name: &str| {
+        let mut args = args.iter().take_while(|v| **v != sep).map(|v| v.to_str());
+        while let Some(arg) = args.next() {
+            if arg == Some(name) {
+                return args.next().flatten();
+            }
+            let value = arg.and_then(|v| v.strip_prefix(name)?.strip_prefix('='));
+            if value.is_some() {
+                return value;
+            }
+        }
+        None
+    };
+    let (config, config_error) = match config::load(value("--config").map(Path::new)) {
+        Ok(config) => (config, None),
+        Err(error) => (config::Config::default(), Some(error)),
+    };
+    logging::init(&logging::Settings {
+        quiet: flag("--quiet-unless-changed"),
+        to_file: flag("--log-to-file") || var_os("POE2FILTER_LOG_TO_FILE").is_some(),
+        file: value("--log-file")
+            .map(PathBuf::from)
+            .or_else(|| var_os("POE2FILTER_LOG_FILE").map(PathBuf::from))
+            .filter(|v| !v.as_os_str().is_empty()),
+        level: value("--log-level")
+            .map(String::from)
+            .or_else(|| var("POE2FILTER_LOG").ok())
+            .or(config.log_level.clone()),
+        format: value("--log-format")
+            .map(String::from)
+            .or_else(|| var("POE2FILTER_LOG_FORMAT").ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default(),
+        trace_http: flag("--trace-http") || var_os("POE2FILTER_TRACE_HTTP").is_some(),
+    });
+
+    if let Some(error) = config_error {
+        // Never keep the game from starting because of a typo.
+        error!("ignoring the configuration: {error:#}");
+    }
     debug!("args are {args:?}");
     args.pop_front(); // Remove "poe2filter"
 
-    let mut clear = false;
-    let mut sources = Vec::new();
-    while let Some(front) = args.pop_front() {
-        if front == sep {
-            break;
+    let mut options = Options {
+        deadline: Some(DEFAULT_DEADLINE),
+        interval: watch::DEFAULT_INTERVAL,
+        league_check: true,
+        version_check: true,
+        jobs: DEFAULT_JOBS,
+        retries: config.retries,
+        timeouts: config.timeouts.clone(),
+        max_download_size: config.max_download_size,
+        proxy: var("POE2FILTER_PROXY")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(config.proxy.clone()),
+        user_agent: var("POE2FILTER_USER_AGENT").ok().filter(|v| !v.is_empty()),
+        contact: var("POE2FILTER_CONTACT").ok().filter(|v| !v.is_empty()),
+        ca_bundle: var_os("POE2FILTER_CA_BUNDLE")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from),
+        github_token: var("POE2FILTER_GITHUB_TOKEN")
+            .or_else(|_| var("GITHUB_TOKEN"))
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(config.github_token.clone()),
+        gitlab_token: var("POE2FILTER_GITLAB_TOKEN")
+            .or_else(|_| var("GITLAB_TOKEN"))
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(config.gitlab_token.clone()),
+        app_id: config.app_id.clone(),
+        hooks: hooks::Hooks::from_env().or(&config.hooks),
+        metrics_file: var_os("POE2FILTER_METRICS_FILE")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from),
+        webhooks: var("POE2FILTER_WEBHOOKS")
+            .map(|v| v.split_whitespace().map(|v| v.to_string()).collect())
+            .unwrap_or_default(),
+        game_directory: config.game_directory.clone(),
+        prefix: config.prefix.clone(),
+        game: config.game,
+        source_games: config.source_games.clone(),
+        stages: config.stages.clone(),
+        environment: config.environment.clone(),
+        ..Default::default()
+    };
+    let CommandLine {
+        mut sources,
+        command: args,
+        help,
+    } = parse_args(args, &mut options)?;
+    if help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    // Sources from the configuration are used when none are given, also by
+    // the subcommands that update sources.
+    let configured = config.sources.iter().map(OsString::from);
+    match sources.first().and_then(|v| v.to_str()) {
+        None => sources.extend(configured),
+        Some(command)
+            if SOURCE_SUBCOMMANDS.contains(&command)
+                && sources[1..].iter().all(|v| v == "--stdio") =>
+        {
+            sources.extend(configured)
         }
-        if front == clear_flag {
-            clear = true;
-            continue;
+        _ => {}
+    }
+
+    if sources.first().is_some_and(|v| v == "help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    if sources.first().is_some_and(|v| v == "update") {
+        sources.remove(0);
+        if !args.is_empty() {
+            bail!("update doesn't start a command, use run instead");
         }
+    }
 
-        sources.push(front);
+    if sources.first().is_some_and(|v| v == "run") {
+        sources.remove(0);
+        if args.is_empty() {
+            bail!("run needs the command to start, e.g. poe2filter run <sources> -- %command%");
+        }
     }
 
-    {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("spawn async runtime");
+    options.wrapping = !args.is_empty();
+    let notify = options.notify.unwrap_or(options.wrapping);
+    cancel::install_handlers();
 
-        rt.block_on(async_main(sources, clear))?;
+    #[cfg(unix)]
+    if sources.first().is_some_and(|v| v == "systemd") {
+        sources.remove(0);
+        return block_on(poe2filter::systemd::command(sources, &options));
     }
 
-    let Some(path) = args.front().cloned() else {
-        info!("nothing to execute provided");
-        return Ok(());
-    };
+    if sources.first().is_some_and(|v| v == "schedule") {
+        sources.remove(0);
+        return block_on(schedule::command(sources, &options));
+    }
+
+    if sources.first().is_some_and(|v| v == "install-steam") {
+        sources.remove(0);
+        return block_on(steam::install_launch_options(sources, &options));
+    }
 
-    let args: Vec<_> = args.iter().map(|v| to_cstr(v.as_os_str())).collect();
+    if sources.first().is_some_and(|v| v == "list") {
+        sources.remove(0);
+        return block_on(manage::list(sources, &options));
+    }
 
-    info!("starting {path:?} {args:?}");
-    nix::unistd::execv(&to_cstr(&path), &args)?;
+    if sources.first().is_some_and(|v| v == "check") {
+        sources.remove(0);
+        return block_on(manage::check(sources, &options));
+    }
 
-    Ok(())
-}
+    if sources.first().is_some_and(|v| v == "remove") {
+        sources.remove(0);
+        return block_on(manage::remove(sources, &options));
+    }
 
-async fn async_main(sources: Vec<OsString>, clear: bool) -> Result<()> {
-    let mut globals = Globals::new().await?;
+    if sources.first().is_some_and(|v| v == "ratelimit") {
+        return block_on(github::print_rate_limit(&options));
+    }
 
-    if clear {
-        //  This code isn't going to win any awards
-        globals.versions.clear();
+    if sources.first().is_some_and(|v| v == "preview") {
+        sources.remove(0);
+        return block_on(preview::command(sources, &options));
     }
 
-    for source in sources {
-        let source = source
-            .to_str()
-            .ok_or_else(|| eyre!("all arguments must be valid UTF-8"))?;
+    if sources.first().is_some_and(|v| v == "bundle-report") {
+        sources.remove(0);
+        return block_on(report::command(sources, &options));
+    }
 
-        let source = match source {
-            "neversink-lite" => "github:NeverSinkDev/NeverSink-PoE2litefilter",
-            "neversink-lite/main" => "github:NeverSinkDev/NeverSink-PoE2litefilter/main",
-            "cdrg" => "github:cdrg/cdr-poe2filter",
-            "cdrg/main" => "github:cdrg/cdr-poe2filter/main",
-            other => other,
-        };
+    if sources.first().is_some_and(|v| v == "watch") {
+        sources.remove(0);
+        // The whole point is to update filters while playing.
+        options.ignore_running = true;
+        return watch::watch(sources, &options);
+    }
 
-        let index = source
-            .find(':')
-            .ok_or_else(|| eyre!("all arguments must be in the form source:arg"))?;
-        let (source_name, value) = source.split_at(index);
+    if sources.first().is_some_and(|v| v == "serve") {
+        sources.remove(0);
+        // Updates are requested while playing, like in watch mode.
+        options.ignore_running = true;
+        if let Some(index) = sources.iter().position(|v| v == "--stdio") {
+            sources.remove(index);
+            return rpc::serve(sources, &options);
+        }
+        #[cfg(target_os = "linux")]
+        return poe2filter::dbus::serve(sources, &options);
+        #[cfg(not(target_os = "linux"))]
+        bail!("serve needs D-Bus, use serve --stdio instead");
+    }
 
-        let current_version = globals.versions.get(source);
-        info!(
-            "updating {source} which has watermark {}...",
-            current_version.map(|v| v.as_str()).unwrap_or("none")
+    // If the game has never been started, its prefix doesn't exist yet. Start
+    // the game so that it gets created, and install filters once it has been.
+    let mut wait_for_prefix = false;
+    if options.wrapping && !options.background && block_on(locate_game_directory(&options)).is_err()
+    {
+        warn!(
+            "could not find the game directory, starting the game and retrying in the background"
         );
-        let next_version = match source_name {
-            "github" => github::get(&globals, &value[1..], current_version).await?,
-            _ => bail!("source type must be github"),
-        };
+        options.background = true;
+        wait_for_prefix = true;
+    }
+
+    if options.background && options.wrapping {
+        return background(args, sources, &options, wait_for_prefix);
+    }
+
+    match run(sources, &options) {
+        // Never keep the game from starting because of filters.
+        Err(error) if options.wrapping => error!("could not update filters: {error}"),
+        Err(error) => return Err(error.into()),
+        Ok(summary) => {
+            if notify {
+                notify::summary(&summary);
+            }
 
-        if let Some(tag) = next_version {
-            eprintln!("# {source}: {}", &tag.watermark);
-            if let Some(body) = tag.body.as_ref() {
-                eprintln!("{body}");
+            if !options.wrapping && !summary.failed.is_empty() {
+                bail!("{} source(s) failed to update", summary.failed.len())
             }
-            eprintln!();
 
-            info!("watermark for {source} updated to {}", tag.watermark);
-            globals.versions.insert(source.to_string(), tag.watermark);
-        } else {
-            info!("{source} is already up to date");
+            let changed = !summary.updated.is_empty() || !summary.failed.is_empty();
+            if let (true, true, Some(pause)) = (options.wrapping, changed, options.pause) {
+                countdown(pause);
+            }
         }
     }
 
-    info!("saving watermark");
-    let s = serde_json::to_string_pretty(&globals.versions)?;
-    let mut o = fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(&releases_file(&globals.game_directory))
-        .await?;
-    o.write_all(s.as_bytes()).await?;
+    exec(args, &options)
+}
 
-    info!("saved watermark");
-    Ok(())
+/// Starts the game right away and updates filters in a process of their own,
+/// which reports the results as a notification.
+#[cfg(unix)]
+fn background(
+    args: VecDeque<OsString>,
+    sources: Vec<OsString>,
+    options: &Options,
+    wait_for_prefix: bool,
+) -> Result<()> {
+    use nix::unistd::{fork, setsid, ForkResult};
+
+    // SAFETY: the async runtime hasn't been started yet, so this process
+    // is still single-threaded.
+    match unsafe { fork() }.wrap_err_with(|| "could not fork")? {
+        ForkResult::Parent { child } => {
+            info!("updating filters in the background (pid {child})");
+            exec(args, options)
+        }
+        ForkResult::Child => {
+            // Detach from the game's session so that Steam doesn't wait on us.
+            if let Err(error) = setsid() {
+                warn!("could not detach from the session: {error}");
+            }
+            update_in_background(sources, options, wait_for_prefix);
+            Ok(())
+        }
+    }
 }
 
-fn split_paths(raw: OsString) -> Vec<PathBuf> {
-    if raw.is_empty() {
-        return Default::default();
+/// Without fork, the game is started on another thread and filters are
+/// updated while it runs. The process exits with the game.
+#[cfg(not(unix))]
+fn background(
+    args: VecDeque<OsString>,
+    sources: Vec<OsString>,
+    options: &Options,
+    wait_for_prefix: bool,
+) -> Result<()> {
+    info!("updating filters in the background");
+    let game = std::thread::spawn({
+        let options = options.clone();
+        move || launch(args, &options)
+    });
+    update_in_background(sources, options, wait_for_prefix);
+
+    // Only exit once both are done, the update would be cut short otherwise.
+    let code = game
+        .join()
+        .map_err(|_| eyre!("could not wait for the game"))??;
+    if let Some(code) = code {
+        exit(code);
     }
+    Ok(())
+}
 
-    let mut bytes = raw.as_bytes();
-    let mut result = Vec::new();
+fn update_in_background(sources: Vec<OsString>, options: &Options, wait_for_prefix: bool) {
+    if wait_for_prefix && !wait_for_game_directory(options) {
+        notify::failure(&eyre!(
+            "the game directory did not appear, filters were not installed"
+        ));
+        return;
+    }
 
-    while !bytes.is_empty() {
-        let index = bytes
-            .iter()
-            .cloned()
-            .enumerate()
-            .find_map(|(idx, v)| if v == b':' { Some(idx) } else { None })
-            .unwrap_or(bytes.len());
+    match run(sources, options) {
+        Ok(summary) => notify::summary(&summary),
+        Err(error) => notify::failure(&error),
+    }
+}
 
-        let (current, next) = bytes.split_at(index);
-        if next.is_empty() {
-            break;
+/// How long to wait for the game to create its prefix on the first launch.
+const PREFIX_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Polls until the game directory can be located, which happens once the
+/// game has created its prefix and Documents tree.
+fn wait_for_game_directory(options: &Options) -> bool {
+    let deadline = std::time::Instant::now() + PREFIX_TIMEOUT;
+    while std::time::Instant::now() < deadline && !cancel::is_cancelled() {
+        std::thread::sleep(Duration::from_secs(5));
+        if block_on(locate_game_directory(options)).is_ok() {
+            info!("the game directory now exists");
+            return true;
         }
-        bytes = &next[1..]; // Remove the :
-        result.push(PathBuf::from(OsStr::from_bytes(current)));
     }
 
-    result
+    false
 }
 
-async fn locate_game_directory() -> Result<PathBuf> {
-    let mut paths = Vec::new();
-
-    if let Some(compat_path) = var_os("STEAM_COMPAT_DATA_PATH") {
-        paths.push(PathBuf::from(compat_path));
+/// Gives the user some time to read the summary before the game starts.
+fn countdown(pause: Duration) {
+    let mut stderr = std::io::stderr();
+    for remaining in (1..=pause.as_secs()).rev() {
+        let _ = write!(stderr, "\rstarting the game in {remaining}s... ");
+        let _ = stderr.flush();
+        std::thread::sleep(Duration::from_secs(1));
     }
+    let _ = writeln!(stderr);
+}
 
-    let game_id = var_os("STEAM_COMPAT_APP_ID")
-        .or_else(|| var_os("SteamGameId"))
-        .unwrap_or_else(|| OsString::from("2694490"));
+/// Runs the command that poe2filter wraps (the game), between the
+/// `pre_launch` and `post_game` hooks, and exits with its exit code.
+fn exec(args: VecDeque<OsString>, options: &Options) -> Result<()> {
+    if let Some(code) = launch(args, options)? {
+        exit(code);
+    }
+    Ok(())
+}
 
-    if let Some(compat_paths) = var_os("STEAM_COMPAT_LIBRARY_PATHS") {
-        for path in split_paths(compat_paths) {
-            paths.push(path.join("compatdata").join(&game_id));
-        }
+/// Runs the command that poe2filter wraps, either by replacing this process
+/// or as a child. Returns the exit code of the child, `None` if there was
+/// nothing to run.
+fn launch(args: VecDeque<OsString>, options: &Options) -> Result<Option<i32>> {
+    if args.is_empty() {
+        info!("nothing to execute provided");
+        return Ok(None);
     }
 
-    if let Some(base_path) = var_os("STEAM_BASE_FOLDER") {
-        let base_path = PathBuf::from(base_path);
-        paths.push(base_path.join("steamapps/compatdata").join(&game_id));
+    let command: Vec<_> = args.iter().map(|v| v.to_string_lossy()).collect();
+    block_on(hooks::run(
+        "pre_launch",
+        options.hooks.pre_launch.as_deref(),
+        &[("POE2FILTER_COMMAND", command.join(" "))],
+    ));
+
+    if args.iter().any(|v| v == "%command%") {
+        bail!("%command% was not replaced, it only works in the Steam launch options");
     }
 
-    if let Some(data_dirs) = var_os("XDG_DATA_DIRS") {
-        for path in split_paths(data_dirs) {
-            paths.push(path.join("Steam/steamapps/compatdata").join(&game_id));
+    // Windows can't replace the process, the game is run as a child instead.
+    if options.wait || cfg!(not(unix)) {
+        let log_position = block_on(client_log::position(options));
+        let code = process::spawn_and_wait(&args, &options.environment)?;
+
+        if let Some((log, from)) = log_position {
+            if let Ok(directory) = block_on(locate_game_directory(options)) {
+                block_on(client_log::report_filter_errors(
+                    &log,
+                    from,
+                    &directory,
+                    options.notify.unwrap_or(options.wrapping),
+                ));
+            }
         }
-    }
 
-    if let Some(home) = var_os("HOME") {
-        let home = PathBuf::from(home);
-        paths.push(
-            home.join(".local/share/Steam/steamapps/compatdata")
-                .join(&game_id),
-        );
+        block_on(hooks::run(
+            "post_game",
+            options.hooks.post_game.as_deref(),
+            &[("POE2FILTER_EXIT_CODE", code.to_string())],
+        ));
+        return Ok(Some(code));
     }
 
-    let mut checked_paths = HashSet::new();
-    for path in paths
-        .into_iter()
-        .filter(|v| checked_paths.insert(v.clone()))
+    #[cfg(unix)]
     {
-        let path = path.join("pfx/drive_c/users/steamuser/My Documents/My Games");
-        info!("checking {path:?}...");
-        if let Ok(true) = fs::try_exists(&path).await {
-            let path = path.join("Path of Exile 2");
-
-            info!("attempting to create game data directory at {path:?}");
-            if fs::create_dir_all(&path)
-                .await
-                .inspect_err(|error| warn!("failed to create directory: {error:?}"))
-                .is_ok()
-            {
-                info!("found game directory");
-                return Ok(path);
+        // The process is replaced right after, so nothing else reads the
+        // environment anymore.
+        for (key, value) in &options.environment {
+            match value {
+                Some(value) => {
+                    debug!("setting {key:?}={value:?}");
+                    std::env::set_var(key, value);
+                }
+                None => {
+                    debug!("unsetting {key:?}");
+                    std::env::remove_var(key);
+                }
+            }
+        }
+
+        let path = to_cstr(&args[0]);
+        let args: Vec<_> = args.iter().map(|v| to_cstr(v.as_os_str())).collect();
+
+        // The command can be another wrapper (e.g. mangohud or gamemoderun), so
+        // search PATH like a shell would.
+        info!("starting {path:?} {args:?}");
+        nix::unistd::execvp(&path, &args)?;
+    }
+
+    Ok(None)
+}
+
+/// The sources (and subcommand) and the command to run, from the command
+/// line. Options are parsed into [`Options`] along the way.
+#[derive(Debug, Default)]
+struct CommandLine {
+    sources: Vec<OsString>,
+    command: VecDeque<OsString>,
+    help: bool,
+}
+
+fn parse_args(
+    args: impl IntoIterator<Item = OsString>,
+    options: &mut Options,
+) -> Result<CommandLine> {
+    use lexopt::Arg::{Long, Short, Value};
+
+    let mut parser = lexopt::Parser::from_args(args);
+    let mut parsed = CommandLine::default();
+    let sources = &mut parsed.sources;
+    loop {
+        let mut raw = parser.raw_args()?;
+        if raw.next_if(|v| v == "--").is_some() {
+            parsed.command.extend(raw);
+            break;
+        }
+
+        // Without %command% Steam appends the command to the launch options,
+        // and other wrappers may have consumed the --, so anything that looks
+        // like a path is the start of the command.
+        let takes_paths = sources
+            .first()
+            .is_some_and(|v| PATH_SUBCOMMANDS.iter().any(|s| v == s));
+        let path = raw
+            .peek()
+            .filter(|v| Path::new(v).is_absolute() && !takes_paths)
+            .map(OsStr::to_os_string);
+        if let Some(path) = path {
+            warn!("found {path:?} without a preceding --, treating it as the command to run");
+            parsed.command.extend(raw);
+
+            // Wrappers in front of the game (`gamemoderun`, `mangohud`, ...)
+            // are part of the command too, they are never valid sources.
+            while let Some(wrapper) = sources.pop_if(|v: &mut OsString| !is_source(v)) {
+                warn!("treating {wrapper:?} as part of the command to run");
+                parsed.command.push_front(wrapper);
+            }
+            break;
+        }
+
+        let Some(arg) = parser.next()? else {
+            break;
+        };
+        match arg {
+            Long("exec") => {
+                parsed.command.extend(parser.raw_args()?);
+                break;
+            }
+            Long("help") | Short('h') => {
+                parsed.help = true;
+                return Ok(parsed);
+            }
+            Long("clear" | "force") => options.clear = true,
+            // Either replaces the other, also when it is in the configuration.
+            Long("game-dir") => {
+                options.game_directory = Some(flag_value(&mut parser, "game-dir")?);
+                options.prefix = None;
+            }
+            Long("game") => options.game = Some(flag_value(&mut parser, "game")?),
+            Long("prefix") => {
+                options.prefix = Some(flag_value(&mut parser, "prefix")?);
+                options.game_directory = None;
+            }
+            Long("ignore-running") => options.ignore_running = true,
+            Long("quiet-unless-changed") => {} // Handled by logging::init
+            Long("log-to-file") => {}          // Handled by logging::init
+            Long("trace-http") => {}           // Handled by logging::init
+            Long("config") => {
+                let _: PathBuf = flag_value(&mut parser, "config")?; // Handled before logging::init
+            }
+            Long("timings") => options.timings = true,
+            Long("metrics-file") => {
+                options.metrics_file = Some(flag_value(&mut parser, "metrics-file")?)
+            }
+            Long(flag @ ("log-file" | "log-level")) => {
+                let flag = flag.to_string();
+                let _: String = flag_value(&mut parser, &flag)?; // Handled by logging::init
+            }
+            Long("log-format") => {
+                // Handled by logging::init, but it can't report a bad value.
+                let _: logging::Format = flag_value(&mut parser, "log-format")?;
+            }
+            Long("no-league-check") => options.league_check = false,
+            Long("no-version-check") => options.version_check = false,
+            Long("background") => options.background = true,
+            Long("wait") => options.wait = true,
+            Long("notify") => options.notify = Some(true),
+            Long("no-notify") => options.notify = Some(false),
+            Long("guard") => options.guard = true,
+            Long("interval") => {
+                let seconds: u64 = flag_value(&mut parser, "interval")?;
+                options.interval = Duration::from_secs(seconds.max(60));
+            }
+            Long("app-id") => {
+                let app_id: u32 = flag_value(&mut parser, "app-id")?;
+                options.app_id = Some(OsString::from(app_id.to_string()));
+            }
+            Long(flag @ ("deadline" | "timeout")) => {
+                let flag = flag.to_string();
+                let seconds = flag_value(&mut parser, &flag)?;
+                options.deadline = Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero());
+            }
+            Long("pre-update") => {
+                options.hooks.pre_update = Some(flag_value(&mut parser, "pre-update")?)
+            }
+            Long("post-update") => {
+                options.hooks.post_update = Some(flag_value(&mut parser, "post-update")?)
+            }
+            Long("post-game") => {
+                options.hooks.post_game = Some(flag_value(&mut parser, "post-game")?)
+            }
+            Long("pre-launch") => {
+                options.hooks.pre_launch = Some(flag_value(&mut parser, "pre-launch")?)
+            }
+            Long("pause") => {
+                let seconds = flag_value(&mut parser, "pause")?;
+                options.pause = Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero());
+            }
+            Long("profile") => {
+                let value: String = flag_value(&mut parser, "profile")?;
+                options.profiles.push(client_log::Profile::parse(&value)?);
+            }
+            Long("client-log") => options.client_log = Some(flag_value(&mut parser, "client-log")?),
+            Long("github-token") => {
+                options.github_token = Some(flag_value(&mut parser, "github-token")?)
             }
+            Long("gitlab-token") => {
+                options.gitlab_token = Some(flag_value(&mut parser, "gitlab-token")?)
+            }
+            Long("retries") => options.retries = flag_value(&mut parser, "retries")?,
+            Long("proxy") => options.proxy = Some(flag_value(&mut parser, "proxy")?),
+            Long("user-agent") => options.user_agent = Some(flag_value(&mut parser, "user-agent")?),
+            Long("contact") => options.contact = Some(flag_value(&mut parser, "contact")?),
+            Long("connect-timeout") => {
+                let seconds = flag_value(&mut parser, "connect-timeout")?;
+                options.timeouts.connect =
+                    Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero());
+            }
+            Long("read-timeout") => {
+                let seconds = flag_value(&mut parser, "read-timeout")?;
+                options.timeouts.read = Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero());
+            }
+            Long("request-timeout") => {
+                let seconds = flag_value(&mut parser, "request-timeout")?;
+                options.timeouts.total =
+                    Some(Duration::from_secs(seconds)).filter(|v| !v.is_zero());
+            }
+            Long("max-download-size") => {
+                let megabytes: u64 = flag_value(&mut parser, "max-download-size")?;
+                options.max_download_size =
+                    Some(megabytes.saturating_mul(http::MIB)).filter(|v| *v > 0);
+            }
+            Long("minisign-key") => {
+                let value: String = flag_value(&mut parser, "minisign-key")?;
+                let (source, key) = value
+                    .split_once('=')
+                    .ok_or_else(|| eyre!("--minisign-key must be in the form source=key"))?;
+                let key = minisign::PublicKey::parse(key)?;
+                options
+                    .signing_keys
+                    .push((resolve_alias(source).to_string(), key));
+            }
+            Long("transform") => {
+                let value: String = flag_value(&mut parser, "transform")?;
+                let (source, script) = value
+                    .split_once('=')
+                    .ok_or_else(|| eyre!("--transform must be in the form source=script"))?;
+                let stage = pipeline::Stage::Transform(script.to_string());
+                options
+                    .stages
+                    .push((resolve_alias(source).to_string(), stage));
+            }
+            Long("stage") => {
+                let value: String = flag_value(&mut parser, "stage")?;
+                let (source, stage) = value
+                    .split_once('=')
+                    .ok_or_else(|| eyre!("--stage must be in the form source=stage"))?;
+                options
+                    .stages
+                    .push((resolve_alias(source).to_string(), stage.parse()?));
+            }
+            Long("target") => options.targets.push(flag_value(&mut parser, "target")?),
+            Long("no-system-proxy") => options.no_system_proxy = true,
+            Long("ca-bundle") => options.ca_bundle = Some(flag_value(&mut parser, "ca-bundle")?),
+            Long("limit-rate") => {
+                let kibibytes: u64 = flag_value(&mut parser, "limit-rate")?;
+                options.limit_rate = Some(kibibytes.saturating_mul(1024)).filter(|v| *v > 0);
+            }
+            Long("jobs") => options.jobs = flag_value(&mut parser, "jobs")?,
+            Long("webhook") => options.webhooks.push(flag_value(&mut parser, "webhook")?),
+            Long("events") => {
+                let format: String = flag_value(&mut parser, "events")?;
+                if format != "jsonl" {
+                    bail!("--events only supports jsonl");
+                }
+                options.events.get_or_insert_with(|| "-".to_string());
+            }
+            Long("events-file") => options.events = Some(flag_value(&mut parser, "events-file")?),
+            Long("env") => {
+                let value = parser
+                    .value()
+                    .map_err(|_| eyre!("--env requires a value"))?;
+                let index = value
+                    .as_encoded_bytes()
+                    .iter()
+                    .position(|v| *v == b'=')
+                    .ok_or_else(|| eyre!("--env must be in the form KEY=VALUE"))?;
+                let (key, value) = value.as_encoded_bytes().split_at(index);
+                // SAFETY: split at an ASCII character, which leaves valid parts.
+                let (key, value) = unsafe {
+                    (
+                        OsStr::from_encoded_bytes_unchecked(key),
+                        OsStr::from_encoded_bytes_unchecked(&value[1..]),
+                    )
+                };
+                options
+                    .environment
+                    .push((key.to_os_string(), Some(value.to_os_string())));
+            }
+            Long("unset-env") => {
+                let key = parser
+                    .value()
+                    .map_err(|_| eyre!("--unset-env requires a value"))?;
+                options.environment.push((key, None));
+            }
+            Long("wait-for-network") => {
+                let seconds = flag_value(&mut parser, "wait-for-network")?;
+                options.network_timeout = Some(Duration::from_secs(seconds));
+            }
+            Long("wait-for-mount") => {
+                let seconds = flag_value(&mut parser, "wait-for-mount")?;
+                options.mount_timeout = Some(Duration::from_secs(seconds));
+            }
+            Value(value) => sources.push(value),
+            // Options of subcommands, such as `serve --stdio`, are passed on.
+            Long(other) => {
+                sources.push(format!("--{other}").into());
+                sources.extend(parser.optional_value());
+            }
+            Short(other) => bail!("-{other} is not an option, see poe2filter --help"),
         }
     }
 
-    Err(color_eyre::eyre::eyre!("No steam path could be located"))
+    Ok(parsed)
 }
 
-fn releases_file(path: &Path) -> PathBuf {
-    path.join("filter_watermarks.json")
+fn flag_value<T: FromStr>(parser: &mut lexopt::Parser, flag: &str) -> Result<T> {
+    parser
+        .value()
+        .ok()
+        .and_then(|v| v.to_str()?.parse().ok())
+        .ok_or_else(|| eyre!("--{flag} requires a valid value"))
 }
 
-fn to_cstr(os: &OsStr) -> CString {
+#[cfg(unix)]
+fn to_cstr(os: &OsStr) -> std::ffi::CString {
+    use std::os::unix::ffi::OsStrExt as _;
+
     let mut bytes = os.as_bytes().to_vec();
     bytes.push(0);
-    CString::from_vec_with_nul(bytes).unwrap()
+    std::ffi::CString::from_vec_with_nul(bytes).unwrap()
 }
 
-fn split(mut v: &str, c: char) -> Vec<&str> {
-    let mut result = Vec::new();
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf, time::Duration};
 
-    while !v.is_empty() {
-        let index = v.find(c).unwrap_or(v.len());
-        let (item, next) = v.split_at(index);
+    use poe2filter::Options;
 
-        if !item.is_empty() {
-            result.push(item);
-        }
+    use super::{parse_args, CommandLine};
 
-        if next.is_empty() {
-            break;
-        }
+    fn parse(args: &[&str]) -> color_eyre::Result<(CommandLine, Options)> {
+        let mut options = Options::default();
+        let parsed = parse_args(args.iter().map(OsString::from), &mut options)?;
+        Ok((parsed, options))
+    }
 
-        v = &next[1..]; // Remove delimeter
+    fn strings(args: impl IntoIterator<Item = OsString>) -> Vec<String> {
+        args.into_iter().map(|v| v.into_string().unwrap()).collect()
     }
 
-    result
+    #[test]
+    fn parses_options_sources_and_the_command() {
+        let (parsed, options) = parse(&[
+            "--game-dir",
+            "/games/poe2",
+            "neversink-lite",
+            "--jobs=2",
+            "--deadline",
+            "0",
+            "--env",
+            "A=b=c",
+            "github:a/b",
+            "--",
+            "game.exe",
+            "--jobs",
+        ])
+        .unwrap();
+        assert_eq!(strings(parsed.sources), ["neversink-lite", "github:a/b"]);
+        assert_eq!(strings(parsed.command), ["game.exe", "--jobs"]);
+        assert!(!parsed.help);
+        assert_eq!(options.game_directory, Some(PathBuf::from("/games/poe2")));
+        assert_eq!(options.jobs, 2);
+        assert_eq!(options.deadline, None);
+        assert_eq!(
+            options.environment,
+            [("A".into(), Some(OsString::from("b=c")))]
+        );
+    }
+
+    #[test]
+    fn the_last_of_game_dir_and_prefix_wins() {
+        let (_, options) = parse(&["--game-dir", "/a", "--prefix=/b"]).unwrap();
+        assert_eq!(options.game_directory, None);
+        assert_eq!(options.prefix, Some(PathBuf::from("/b")));
+
+        let (_, options) = parse(&["--prefix", "/b", "--game-dir", "/a"]).unwrap();
+        assert_eq!(options.game_directory, Some(PathBuf::from("/a")));
+        assert_eq!(options.prefix, None);
+    }
+
+    #[test]
+    fn finds_the_command_without_a_separator() {
+        let (parsed, _) = parse(&[
+            "cdrg",
+            "gamemoderun",
+            "/steam/proton",
+            "waitforexitandrun",
+            "--wait",
+        ])
+        .unwrap();
+        assert_eq!(strings(parsed.sources), ["cdrg"]);
+        assert_eq!(
+            strings(parsed.command),
+            [
+                "gamemoderun",
+                "/steam/proton",
+                "waitforexitandrun",
+                "--wait"
+            ]
+        );
+
+        let (parsed, options) = parse(&["--wait", "--exec", "game.exe", "--clear"]).unwrap();
+        assert!(options.wait && !options.clear);
+        assert_eq!(strings(parsed.command), ["game.exe", "--clear"]);
+
+        // Unless the subcommand takes a path.
+        let (parsed, _) = parse(&["preview", "/filters/a.filter", "--png=/tmp/a.png"]).unwrap();
+        assert_eq!(
+            strings(parsed.sources),
+            ["preview", "/filters/a.filter", "--png", "/tmp/a.png"]
+        );
+        assert!(parsed.command.is_empty());
+    }
+
+    #[test]
+    fn passes_on_subcommand_options() {
+        let (parsed, options) = parse(&["serve", "--stdio", "--interval", "30"]).unwrap();
+        assert_eq!(strings(parsed.sources), ["serve", "--stdio"]);
+        assert_eq!(options.interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn stops_at_help() {
+        for help in ["-h", "--help"] {
+            let (parsed, _) = parse(&["github:a/b", help, "--jobs"]).unwrap();
+            assert!(parsed.help);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for args in [
+            &["--jobs"][..],
+            &["--jobs", "many"],
+            &["--game", "poe3"],
+            &["--env", "A"],
+            &["--minisign-key", "no-key"],
+            &["--stage", "github:a/b=frobnicate"],
+            &["--events", "xml"],
+            &["-x"],
+            &["--clear=yes"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
 }
@@ -0,0 +1,92 @@
+//! `poe2filter list`, `check` and `remove`: looking at and uninstalling
+//! sources, without installing anything.
+
+use std::{collections::HashMap, ffi::OsString};
+
+use color_eyre::{eyre::bail, Result};
+use log::info;
+
+use crate::{
+    cache, installed_versions, locate_game_directory, resolve_alias, source, store, Globals,
+    Options,
+};
+
+/// Prints the installed sources and their watermarks, and the `sources` that
+/// aren't installed yet.
+pub async fn list(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    let installed = installed_versions(options).await;
+    for (source, watermark) in &installed {
+        println!("{source}: {watermark}");
+    }
+    for source in &sources {
+        let source = resolve_alias(&source.to_string_lossy()).to_string();
+        if !installed.contains_key(&source) {
+            println!("{source}: not installed");
+        }
+    }
+    Ok(())
+}
+
+/// Prints whether a newer version of each source is available. Fails if one
+/// of them could not be checked.
+pub async fn check(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    if sources.is_empty() {
+        bail!("no sources to check, give them as arguments or in the configuration");
+    }
+    let globals = Globals::new(options).await?;
+
+    let mut failed = 0;
+    for source in &sources {
+        let source = resolve_alias(&source.to_string_lossy()).to_string();
+        let installed = globals.versions.get(&source);
+        match source::latest(&globals, &source, installed).await {
+            Ok(Some(latest)) => match installed {
+                Some(installed) => println!("{source}: {installed} -> {latest}"),
+                None => println!("{source}: {latest} (not installed)"),
+            },
+            Ok(None) => match installed {
+                Some(installed) => println!("{source}: {installed} (up to date)"),
+                None => println!("{source}: nothing to install"),
+            },
+            Err(error) => {
+                println!("{source}: failed, {error:#}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} source(s) could not be checked");
+    }
+    Ok(())
+}
+
+/// Uninstalls sources: removes the files they installed (unless another
+/// source installed them too), forgets their watermarks and removes their
+/// cached downloads.
+pub async fn remove(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    if sources.is_empty() {
+        bail!("remove needs the sources to remove");
+    }
+    let directory = locate_game_directory(options).await?;
+    let mut installed = store::read(&directory).await;
+
+    for source in &sources {
+        let source = resolve_alias(&source.to_string_lossy()).to_string();
+        cache::remove_zipballs(&source, None).await;
+        let Some(removed) = installed.remove(&source) else {
+            println!("{source} is not installed");
+            continue;
+        };
+
+        let others: HashMap<_, _> = installed
+            .iter()
+            .map(|(source, v)| (source.clone(), v.files.clone()))
+            .collect();
+        store::remove_files(&directory, &removed.files, &others).await;
+        println!("removed {source} ({} file(s))", removed.files.len());
+    }
+
+    info!("saving watermarks");
+    store::write(&directory, &installed).await
+}
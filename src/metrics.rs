@@ -0,0 +1,229 @@
+//! What a run did and where its time went, to see what slows down a launch.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::{self, Write as _},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Result;
+use futures_util::future::BoxFuture;
+use reqwest::{Request, Response};
+use tokio::fs;
+
+use crate::{
+    http::{Transport, MIB},
+    resolve_alias, Summary,
+};
+
+/// The metric that is carried over from the previous file for sources that
+/// failed, so that it keeps growing older while updates fail.
+const LAST_SUCCESS: &str = "poe2filter_source_last_success_timestamp_seconds";
+
+/// Counted while sources are updated, shared by everything in a run.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    requests: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    cache_hits: AtomicU64,
+}
+
+impl Counters {
+    pub fn downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Something was answered from a cache instead of the network (including
+    /// responses that were not modified).
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The metrics of one run.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// HTTP requests made, including retries.
+    pub requests: u64,
+    pub bytes_downloaded: u64,
+    pub cache_hits: u64,
+    /// How long updating each source took, in the order they finished.
+    pub sources: Vec<(String, Duration)>,
+    /// How long the whole update took.
+    pub total: Duration,
+}
+
+impl Metrics {
+    pub(crate) fn collect(&mut self, counters: &Counters) {
+        self.requests = counters.requests.load(Ordering::Relaxed);
+        self.bytes_downloaded = counters.bytes_downloaded.load(Ordering::Relaxed);
+        self.cache_hits = counters.cache_hits.load(Ordering::Relaxed);
+    }
+
+    /// Adds the metrics of another update, e.g. the one for the other game.
+    pub(crate) fn add(&mut self, other: Metrics) {
+        self.requests += other.requests;
+        self.bytes_downloaded += other.bytes_downloaded;
+        self.cache_hits += other.cache_hits;
+        self.sources.extend(other.sources);
+        self.total += other.total;
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} request(s), {:.1} MiB downloaded, {} cache hit(s), {:.1}s in total",
+            self.requests,
+            self.bytes_downloaded as f64 / MIB as f64,
+            self.cache_hits,
+            self.total.as_secs_f64()
+        )?;
+        for (source, duration) in &self.sources {
+            writeln!(f, "  {source}: {:.1}s", duration.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the outcome of a run for the textfile collector of the Prometheus
+/// node exporter, replacing the metrics of the previous run. Every source of
+/// the run gets the time it last updated successfully (kept from the previous
+/// file while it fails), whether this run succeeded, whether it installed
+/// something new, and how long it took.
+pub async fn write_textfile(path: &Path, summary: &Summary) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let previous = fs::read_to_string(path).await.unwrap_or_default();
+    let mut last_success: BTreeMap<String, String> = previous
+        .lines()
+        .filter_map(|v| v.strip_prefix(LAST_SUCCESS)?.strip_prefix("{source=\""))
+        .filter_map(|v| v.rsplit_once("\"} "))
+        .map(|(source, value)| (source.to_string(), value.to_string()))
+        .collect();
+
+    let metrics = &summary.metrics;
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: Vec<(Option<&str>, String)>| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (source, value) in values {
+            let _ = match source {
+                Some(source) => writeln!(out, "{name}{{source=\"{}\"}} {value}", escape(source)),
+                None => writeln!(out, "{name} {value}"),
+            };
+        }
+    };
+
+    let failed = |source: &str| summary.failed.iter().any(|(v, _)| v == source);
+    let sources = || metrics.sources.iter().map(|(v, _)| v.as_str());
+    // Sources that are no longer updated would otherwise look like they
+    // stopped updating.
+    last_success.retain(|k, _| sources().any(|v| escape(resolve_alias(v)) == *k));
+    for source in sources().filter(|v| !failed(v)) {
+        last_success.insert(escape(resolve_alias(source)), now.to_string());
+    }
+
+    metric(
+        "poe2filter_last_run_timestamp_seconds",
+        "gauge",
+        "When poe2filter last ran.",
+        vec![(None, now.to_string())],
+    );
+    metric(
+        "poe2filter_run_duration_seconds",
+        "gauge",
+        "How long the last run took.",
+        vec![(None, metrics.total.as_secs_f64().to_string())],
+    );
+    metric(
+        "poe2filter_requests",
+        "gauge",
+        "HTTP requests made by the last run.",
+        vec![(None, metrics.requests.to_string())],
+    );
+    metric(
+        "poe2filter_downloaded_bytes",
+        "gauge",
+        "Bytes downloaded by the last run.",
+        vec![(None, metrics.bytes_downloaded.to_string())],
+    );
+    metric(
+        "poe2filter_source_success",
+        "gauge",
+        "Whether the source was updated (or already up to date) in the last run.",
+        sources()
+            .map(|v| (Some(resolve_alias(v)), u8::from(!failed(v)).to_string()))
+            .collect(),
+    );
+    metric(
+        "poe2filter_source_updated",
+        "gauge",
+        "Whether the last run installed a new version of the source.",
+        sources()
+            .map(|v| {
+                let updated = summary.updated.iter().any(|u| u.source == resolve_alias(v));
+                (Some(resolve_alias(v)), u8::from(updated).to_string())
+            })
+            .collect(),
+    );
+    metric(
+        "poe2filter_source_duration_seconds",
+        "gauge",
+        "How long updating the source took in the last run.",
+        metrics
+            .sources
+            .iter()
+            .map(|(v, duration)| (Some(resolve_alias(v)), duration.as_secs_f64().to_string()))
+            .collect(),
+    );
+    // Already escaped, they come straight from the file.
+    let _ = writeln!(
+        out,
+        "# HELP {LAST_SUCCESS} When the source last updated successfully."
+    );
+    let _ = writeln!(out, "# TYPE {LAST_SUCCESS} gauge");
+    for (source, value) in &last_success {
+        let _ = writeln!(out, "{LAST_SUCCESS}{{source=\"{source}\"}} {value}");
+    }
+
+    // The collector may read the file at any time, so it must never see it
+    // half written.
+    let mut temp = OsString::from(path);
+    temp.push(".tmp");
+    fs::write(&temp, out).await?;
+    fs::rename(&temp, path).await?;
+    Ok(())
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Counts the requests sent through another transport.
+#[derive(Debug)]
+pub(crate) struct CountingTransport {
+    pub inner: Arc<dyn Transport>,
+    pub counters: Arc<Counters>,
+}
+
+impl Transport for CountingTransport {
+    fn execute(&self, request: Request) -> BoxFuture<'_, reqwest::Result<Response>> {
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.execute(request)
+    }
+}
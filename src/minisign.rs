@@ -0,0 +1,282 @@
+//! Verifying [minisign](https://jedisct1.github.io/minisign/) signatures.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+/// Signatures of the data itself, made by older versions of minisign.
+const ALGORITHM_LEGACY: &[u8; 2] = b"Ed";
+/// Signatures of the BLAKE2b-512 hash of the data, the default.
+const ALGORITHM_HASHED: &[u8; 2] = b"ED";
+
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl PublicKey {
+    /// Parses a public key, either just the base64 line (as printed by
+    /// `minisign -G`) or the whole `minisign.pub` file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|v| !v.is_empty() && !v.starts_with("untrusted comment:"))
+            .ok_or_else(|| eyre!("the minisign public key is empty"))?;
+
+        let raw = STANDARD
+            .decode(line)
+            .map_err(|error| eyre!("invalid minisign public key: {error}"))?;
+        if raw.len() != 42 || &raw[..2] != ALGORITHM_LEGACY {
+            bail!("invalid minisign public key, it must be an Ed25519 key");
+        }
+
+        Ok(PublicKey {
+            key_id: raw[2..10].try_into()?,
+            key: raw[10..].try_into()?,
+        })
+    }
+
+    /// Verifies the contents of a `.minisig` file for the data, including the
+    /// signature of its trusted comment.
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<()> {
+        let mut lines = signature.lines().map(str::trim);
+        let mut next = |what: &str| {
+            lines
+                .next()
+                .ok_or_else(|| eyre!("invalid minisign signature, the {what} is missing"))
+        };
+
+        next("untrusted comment")?;
+        let raw = STANDARD
+            .decode(next("signature")?)
+            .map_err(|error| eyre!("invalid minisign signature: {error}"))?;
+        let trusted_comment = next("trusted comment")?
+            .strip_prefix("trusted comment: ")
+            .ok_or_else(|| eyre!("invalid minisign signature, the trusted comment is missing"))?;
+        let global_signature = STANDARD
+            .decode(next("global signature")?)
+            .map_err(|error| eyre!("invalid minisign signature: {error}"))?;
+
+        if raw.len() != 74 {
+            bail!("invalid minisign signature");
+        }
+        let (algorithm, rest) = raw.split_at(2);
+        let (key_id, signature) = rest.split_at(8);
+
+        if key_id != self.key_id {
+            bail!("the signature was made with a different key");
+        }
+
+        let key = UnparsedPublicKey::new(&ED25519, &self.key);
+        let message = match algorithm {
+            a if a == ALGORITHM_LEGACY => data.to_vec(),
+            a if a == ALGORITHM_HASHED => blake2b_512(data).to_vec(),
+            _ => bail!("unsupported minisign signature algorithm"),
+        };
+        key.verify(&message, signature)
+            .map_err(|_| eyre!("the signature does not match"))?;
+
+        let mut global = signature.to_vec();
+        global.extend_from_slice(trusted_comment.as_bytes());
+        key.verify(&global, &global_signature)
+            .map_err(|_| eyre!("the trusted comment of the signature does not match"))?;
+
+        Ok(())
+    }
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+/// BLAKE2b with a 64 byte digest and no key (RFC 7693), which minisign
+/// hashes the data with. `ring` doesn't provide it.
+fn blake2b_512(data: &[u8]) -> [u8; 64] {
+    let mut h = BLAKE2B_IV;
+    h[0] ^= 0x0101_0000 ^ 64;
+
+    let mut counter: u128 = 0;
+    let mut chunks = data.chunks(128).peekable();
+    if chunks.peek().is_none() {
+        compress(&mut h, &[0; 128], 0, true);
+    }
+    while let Some(chunk) = chunks.next() {
+        let mut block = [0; 128];
+        block[..chunk.len()].copy_from_slice(chunk);
+        counter += chunk.len() as u128;
+        compress(&mut h, &block, counter, chunks.peek().is_none());
+    }
+
+    let mut digest = [0; 64];
+    for (output, word) in digest.chunks_mut(8).zip(h) {
+        output.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+fn compress(h: &mut [u64; 8], block: &[u8; 128], counter: u128, last: bool) {
+    let m: Vec<u64> = block
+        .chunks(8)
+        .map(|v| u64::from_le_bytes(v.try_into().expect("chunks are 8 bytes")))
+        .collect();
+
+    let mut v = [0; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= counter as u64;
+    v[13] ^= (counter >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+
+    for s in BLAKE2B_SIGMA {
+        let mut mix = |a: usize, b: usize, c: usize, d: usize, x: u64, y: u64| {
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(32);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(24);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(63);
+        };
+        mix(0, 4, 8, 12, m[s[0]], m[s[1]]);
+        mix(1, 5, 9, 13, m[s[2]], m[s[3]]);
+        mix(2, 6, 10, 14, m[s[4]], m[s[5]]);
+        mix(3, 7, 11, 15, m[s[6]], m[s[7]]);
+        mix(0, 5, 10, 15, m[s[8]], m[s[9]]);
+        mix(1, 6, 11, 12, m[s[10]], m[s[11]]);
+        mix(2, 7, 8, 13, m[s[12]], m[s[13]]);
+        mix(3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blake2b_512, PublicKey};
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|v| format!("{v:02x}")).collect()
+    }
+
+    /// The input of the longer test vectors: `0, 1, ..., 250, 0, 1, ...`.
+    fn counting(length: usize) -> Vec<u8> {
+        (0..length).map(|v| (v % 251) as u8).collect()
+    }
+
+    #[test]
+    fn blake2b_matches_rfc_7693() {
+        // Appendix A.
+        assert_eq!(
+            hex(&blake2b_512(b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(
+            hex(&blake2b_512(b"")),
+            "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419\
+             d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+        );
+    }
+
+    #[test]
+    fn blake2b_handles_block_boundaries() {
+        assert_eq!(
+            hex(&blake2b_512(&counting(128))),
+            "2319e3789c47e2daa5fe807f61bec2a1a6537fa03f19ff32e87eecbfd64b7e0e\
+             8ccff439ac333b040f19b0c4ddd11a61e24ac1fe0f10a039806c5dcc0da3d115"
+        );
+        assert_eq!(
+            hex(&blake2b_512(&counting(129))),
+            "f59711d44a031d5f97a9413c065d1e614c417ede998590325f49bad2fd444d3e\
+             4418be19aec4e11449ac1a57207898bc57d76a1bcf3566292c20c683a5c4648f"
+        );
+        assert_eq!(
+            hex(&blake2b_512(&counting(1000))),
+            "c11e1c0340bd7e5a1b275f1230c962fad215ecb1391486e74e31b960a2f29963\
+             81a5fad092da06841d5f26e38f6ecfeaf441acbcd1c2de61aef121e7927175f5"
+        );
+    }
+
+    const PUBLIC_KEY: &str = "untrusted comment: minisign public key 0123456789ABCDEF
+RWQBI0VniavN7wOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4
+";
+
+    const DATA: &[u8] =
+        b"0f343b0931126a20f133d67c2b018a3b5e1a4b1c9ab7e6f29b04a6dc4e2a5a3c  NeverSink.filter\n";
+
+    /// A signature of the BLAKE2b hash of `DATA`, the default.
+    const HASHED: &str = "untrusted comment: signature from minisign secret key
+RUQBI0VniavN7xV4XxmFDyMtdpofhQx7Q5JUrY+Q9wI0mpLCz/GpYYjsQzc7JB8qkrMbBbi16BjIIMbgifPd7Albtl2M/PwgnAQ=
+trusted comment: timestamp:1735689600\tfile:SHA256SUMS\thashed
+Udahh1pel+A5DQihQEVE6JmPdwRi5dgexorsw4ap/KShT5EykuSD9yYn/PvSlM4znwsgAq/mgUYdL+Q5PJtSCA==
+";
+
+    /// A signature of `DATA` itself, as made with `minisign -l`.
+    const LEGACY: &str = "untrusted comment: signature from minisign secret key
+RWQBI0VniavN7/dc073qvhtegPxTE/PWVWvrgfa1eTBfnuoN3dEyp2OfMc9XH+W5XliXcWL/WWuv6mCQFGy6M5gKKWWzEoCOQAU=
+trusted comment: timestamp:1735689600\tfile:SHA256SUMS
+7qpFHjsN/3Ra6TetMEqLM1mwemDfvH14xMN90hHxR218zAWD0BYWwgcvjeQfcqtA2uSPV3GmnVI1UJwCvcAcDw==
+";
+
+    #[test]
+    fn verifies_signatures() {
+        let key = PublicKey::parse(PUBLIC_KEY).unwrap();
+        key.verify(DATA, HASHED).unwrap();
+        key.verify(DATA, LEGACY).unwrap();
+
+        let line = PUBLIC_KEY.lines().nth(1).unwrap();
+        PublicKey::parse(line)
+            .unwrap()
+            .verify(DATA, HASHED)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_changes() {
+        let key = PublicKey::parse(PUBLIC_KEY).unwrap();
+        let mut data = DATA.to_vec();
+        data[0] = b'1';
+        assert!(key.verify(&data, HASHED).is_err());
+        assert!(key.verify(&data, LEGACY).is_err());
+
+        let comment = HASHED.replace("timestamp:1735689600", "timestamp:1735689601");
+        assert!(key.verify(DATA, &comment).is_err());
+
+        // The same key under another id.
+        let other = PublicKey::parse("RWQBI0VniavN7gOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4");
+        assert!(other.unwrap().verify(DATA, HASHED).is_err());
+        assert!(PublicKey::parse("RWQBI0Vn").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    env::var_os,
+    fmt::Display,
+    io::ErrorKind,
+    process::{Command, Stdio},
+};
+
+use log::{debug, warn};
+
+use crate::{process, Summary};
+
+/// Summarizes the updated and failed sources in a desktop notification.
+pub fn summary(summary: &Summary) {
+    if summary.updated.is_empty() && summary.failed.is_empty() {
+        debug!("nothing was updated, not sending a notification");
+        return;
+    }
+
+    let mut body: Vec<_> = summary
+        .updated
+        .iter()
+        .map(|update| format!("{}: {}", update.source, update.watermark))
+        .collect();
+    body.extend(
+        summary
+            .failed
+            .iter()
+            .map(|(source, error)| format!("{source}: failed: {error}")),
+    );
+
+    if !summary.updated.is_empty() && process::find_running_game().is_some() {
+        body.push("Reload the filter in game to apply the update.".to_string());
+    }
+
+    let title = if summary.failed.is_empty() {
+        "Filters updated"
+    } else {
+        "Some filters failed to update"
+    };
+    send(title, &body.join("\n"));
+}
+
+/// Reports a failed update in a desktop notification.
+pub fn failure(error: &dyn Display) {
+    send("Filter update failed", &format!("{error:#}"));
+}
+
+/// Reports filters that the game failed to load.
+pub fn filter_errors(lines: &[String]) {
+    send("The game could not load a filter", &lines.join("\n"));
+}
+
+pub fn send(summary: &str, body: &str) {
+    if var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+        debug!("no session bus, not sending a notification");
+        return;
+    }
+
+    let result = Command::new("notify-send")
+        .arg("--app-name=poe2filter")
+        .arg(summary)
+        .arg(body)
+        .stdin(Stdio::null())
+        .status();
+
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("notify-send failed with {status}"),
+        Err(error) if error.kind() == ErrorKind::NotFound => send_gdbus(summary, body),
+        Err(error) => warn!("could not run notify-send: {error}"),
+    }
+}
+
+/// Falls back to calling the notification service directly, for systems
+/// without libnotify's `notify-send` (such as SteamOS).
+fn send_gdbus(summary: &str, body: &str) {
+    debug!("notify-send is not available, trying gdbus");
+    let result = Command::new("gdbus")
+        .args([
+            "call",
+            "--session",
+            "--dest=org.freedesktop.Notifications",
+            "--object-path=/org/freedesktop/Notifications",
+            "--method=org.freedesktop.Notifications.Notify",
+            "poe2filter",
+            "0",
+            "",
+        ])
+        .arg(gvariant_string(summary))
+        .arg(gvariant_string(body))
+        .args(["[]", "{}", "-1"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status();
+
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("gdbus failed with {status}"),
+        Err(error) => warn!("could not send a notification, install notify-send or gdbus: {error}"),
+    }
+}
+
+/// Quotes a string as a GVariant text literal.
+fn gvariant_string(v: &str) -> String {
+    let escaped = v
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace('\n', "\\n");
+    format!("'{escaped}'")
+}
@@ -0,0 +1,179 @@
+//! What happens to the filters of a source between being fetched and being
+//! installed, as an ordered list of stages.
+
+use std::{fmt, str::FromStr};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Report, Result,
+};
+
+use crate::{cancel, install, script};
+
+/// A step of a source's pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Only install the filters whose name matches the pattern (`*` matches
+    /// anything, `?` any one character). With several, a filter has to
+    /// match all of them.
+    Select(String),
+    /// Change the filter with a Rhai script, see [`script::transform`].
+    Transform(String),
+    /// Refuse to install a filter that doesn't look like one, e.g. an error
+    /// page or a transform that printed nothing.
+    Validate,
+    /// Install the filters whose name matches the pattern under another
+    /// name. The first matching rename is used.
+    Rename { pattern: String, name: String },
+}
+
+impl FromStr for Stage {
+    type Err = Report;
+
+    /// Parses `select:<pattern>`, `transform:<script>`, `validate` or
+    /// `rename:<pattern>=<name>`.
+    fn from_str(value: &str) -> Result<Self> {
+        let (kind, argument) = match value.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (value, None),
+        };
+        match (kind, argument) {
+            ("select", Some(pattern)) => Ok(Stage::Select(pattern.to_string())),
+            ("transform", Some(script)) => Ok(Stage::Transform(script.to_string())),
+            ("validate", None) => Ok(Stage::Validate),
+            ("rename", Some(argument)) => {
+                let (pattern, name) = argument.split_once('=').ok_or_else(|| {
+                    eyre!("the rename stage must be in the form rename:pattern=name")
+                })?;
+                Ok(Stage::Rename {
+                    pattern: pattern.to_string(),
+                    name: name.to_string(),
+                })
+            }
+            ("select" | "transform" | "rename", None) => {
+                bail!("the {kind} stage needs an argument")
+            }
+            ("validate", Some(_)) => bail!("the validate stage takes no argument"),
+            _ => bail!("unknown stage {kind:?}, expected select, transform, validate or rename"),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Select(pattern) => write!(f, "select:{pattern}"),
+            Stage::Transform(script) => write!(f, "transform:{script}"),
+            Stage::Validate => f.write_str("validate"),
+            Stage::Rename { pattern, name } => write!(f, "rename:{pattern}={name}"),
+        }
+    }
+}
+
+/// The stages of one source, in order. Fetching comes before the first
+/// stage and installing after the last one.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Whether a filter (by its name in the release) passes the select
+    /// stages.
+    pub fn selects(&self, name: &str) -> bool {
+        self.stages.iter().all(|stage| match stage {
+            Stage::Select(pattern) => matches(pattern, name),
+            _ => true,
+        })
+    }
+
+    /// The name a filter (by its name in the release) is installed as.
+    pub fn rename(&self, name: &str) -> String {
+        self.stages
+            .iter()
+            .find_map(|stage| match stage {
+                Stage::Rename { pattern, name: to } if matches(pattern, name) => {
+                    install::sanitize_filename(to)
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Runs the transform and validate stages on a filter, in order. This
+    /// blocks, transforms stop when `cancel` is cancelled.
+    pub fn process(
+        &self,
+        source: &str,
+        name: &str,
+        mut data: Vec<u8>,
+        cancel: &cancel::Token,
+    ) -> Result<Vec<u8>> {
+        for stage in &self.stages {
+            match stage {
+                Stage::Select(_) | Stage::Rename { .. } => {}
+                Stage::Transform(script) => {
+                    data = script::transform(script, source, name, data, cancel)?;
+                }
+                Stage::Validate => validate(name, &data)?,
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Checks that every block starts with `Show`, `Hide` or `Minimal` (or is an
+/// `Import`), and that there is at least one.
+pub(crate) fn validate(name: &str, data: &[u8]) -> Result<()> {
+    let text = std::str::from_utf8(data).map_err(|_| eyre!("{name} is not valid UTF-8"))?;
+    let mut blocks = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        match keyword {
+            "Show" | "Hide" | "Minimal" | "Import" => blocks += 1,
+            _ if blocks == 0 => bail!(
+                "{name} is not a filter, line {} is outside of any block: {line:?}",
+                number + 1
+            ),
+            _ => {}
+        }
+    }
+    if blocks == 0 {
+        bail!("{name} is not a filter, it has no Show or Hide blocks");
+    }
+    Ok(())
+}
+
+/// Matches a name against a pattern with `*` and `?` wildcards.
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<_>, Vec<_>) = (pattern.chars().collect(), name.chars().collect());
+    // The position after the last `*`, and where in the name it resumed.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character.
+                Some((after, resumed)) => {
+                    p = after;
+                    n = resumed + 1;
+                    star = Some((after, resumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,460 @@
+//! `poe2filter preview`: how the rules of a filter will look in game, as a
+//! list or as a PNG to share a theme before trying it.
+//!
+//! Labels are drawn with a small built-in bitmap font, scaled by the font
+//! size of the rule, so the proportions are right but not the game's font.
+
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use flate2::{write::ZlibEncoder, Compression};
+use log::warn;
+use tokio::fs;
+
+use crate::{locate_game_directory, Options};
+
+const USAGE: &str = "usage: poe2filter preview <filter> [--match <text>] [--png <path>]";
+
+/// More labels than this make an image nobody can read.
+const MAX_LABELS: usize = 100;
+
+/// The size of labels that don't set one.
+const DEFAULT_FONT_SIZE: u8 = 32;
+
+const TEXT: Color = Color(200, 200, 200, 255);
+const BACKGROUND: Color = Color(0, 0, 0, 240);
+/// The ground behind the labels.
+const GROUND: Color = Color(24, 22, 20, 255);
+
+const MARGIN: usize = 16;
+const GAP: usize = 8;
+const BORDER: usize = 2;
+
+/// Handles `poe2filter preview <filter> [--match <text>] [--png <path>]`.
+/// The filter is a path, or the name of a filter in the game directory.
+pub async fn command(mut args: Vec<OsString>, options: &Options) -> Result<()> {
+    let mut png = None;
+    let mut patterns = Vec::new();
+    let mut filter = None;
+    while !args.is_empty() {
+        let arg = args.remove(0);
+        match arg.to_str() {
+            Some("--png") if !args.is_empty() => png = Some(PathBuf::from(args.remove(0))),
+            Some("--match") if !args.is_empty() => {
+                patterns.push(args.remove(0).to_string_lossy().to_lowercase())
+            }
+            _ if filter.is_none() => filter = Some(PathBuf::from(arg)),
+            _ => bail!(USAGE),
+        }
+    }
+    let Some(filter) = filter else {
+        bail!(USAGE);
+    };
+
+    let path = find_filter(&filter, options).await;
+    let text = fs::read(&path)
+        .await
+        .wrap_err_with(|| format!("could not read {}", path.display()))?;
+    let mut labels: Vec<_> = parse(&String::from_utf8_lossy(&text))
+        .into_iter()
+        .filter(|label| {
+            patterns
+                .iter()
+                .all(|v| label.rule.to_lowercase().contains(v))
+        })
+        .collect();
+    if labels.is_empty() {
+        bail!("no rules of {} match", path.display());
+    }
+    if labels.len() > MAX_LABELS {
+        warn!(
+            "only previewing the first {MAX_LABELS} of {} rules, select others with --match",
+            labels.len()
+        );
+        labels.truncate(MAX_LABELS);
+    }
+
+    match png {
+        Some(png) => {
+            fs::write(&png, render(&labels)?)
+                .await
+                .wrap_err_with(|| format!("could not write {}", png.display()))?;
+            println!("wrote {} label(s) to {}", labels.len(), png.display());
+        }
+        None => {
+            for label in &labels {
+                println!("{label}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A path as is, or a filter in the game directory with or without its
+/// extension.
+async fn find_filter(filter: &Path, options: &Options) -> PathBuf {
+    if filter.components().count() > 1 || fs::try_exists(filter).await.unwrap_or(false) {
+        return filter.to_path_buf();
+    }
+    let Ok(directory) = locate_game_directory(options).await else {
+        return filter.to_path_buf();
+    };
+    let mut name = filter.as_os_str().to_owned();
+    if filter.extension().is_none_or(|v| v != "filter") {
+        name.push(".filter");
+    }
+    directory.join(name)
+}
+
+/// An RGBA color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u8, pub u8, pub u8, pub u8);
+
+impl Color {
+    /// Parses the arguments of `SetTextColor` and the like, `R G B [A]`.
+    fn parse(arguments: &str) -> Option<Self> {
+        let values: Vec<u8> = arguments
+            .split_whitespace()
+            .map_while(|v| v.parse::<u32>().ok().map(|v| v.min(255) as u8))
+            .collect();
+        match values[..] {
+            [r, g, b] => Some(Color(r, g, b, 255)),
+            [r, g, b, a, ..] => Some(Color(r, g, b, a)),
+            _ => None,
+        }
+    }
+
+    /// This color drawn over `ground`.
+    fn over(self, ground: [u8; 3]) -> [u8; 3] {
+        let alpha = self.3 as u32;
+        let blend = |v: u8, g: u8| ((v as u32 * alpha + g as u32 * (255 - alpha)) / 255) as u8;
+        [
+            blend(self.0, ground[0]),
+            blend(self.1, ground[1]),
+            blend(self.2, ground[2]),
+        ]
+    }
+}
+
+/// How the items of one rule are labelled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// The line the rule starts on.
+    pub line: usize,
+    /// The rule as written, starting with e.g. `Show # $type->currency`.
+    pub rule: String,
+    /// What the label says: the first base type or class of the rule.
+    pub name: String,
+    pub text: Color,
+    pub border: Option<Color>,
+    pub background: Color,
+    pub font_size: u8,
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Color(r, g, b, a) = self.text;
+        write!(
+            f,
+            "line {}: {:?}, size {}, text {r} {g} {b} {a}",
+            self.line, self.name, self.font_size
+        )?;
+        if let Some(Color(r, g, b, a)) = self.border {
+            write!(f, ", border {r} {g} {b} {a}")?;
+        }
+        let Color(r, g, b, a) = self.background;
+        write!(f, ", background {r} {g} {b} {a}")
+    }
+}
+
+/// The labels of the rules that show items. Hidden items get no label.
+pub fn parse(filter: &str) -> Vec<Label> {
+    let mut labels = Vec::new();
+    let mut current: Option<Label> = None;
+    // A base type is a better name than a class, whichever comes first.
+    let mut named_by_base_type = false;
+
+    for (number, line) in filter.lines().enumerate() {
+        let line = line.trim();
+        let content = line.split('#').next().unwrap_or_default().trim();
+        let (keyword, arguments) = content
+            .split_once(char::is_whitespace)
+            .unwrap_or((content, ""));
+        match keyword {
+            "Show" | "Hide" | "Minimal" => {
+                labels.extend(current.take());
+                named_by_base_type = false;
+                current = (keyword != "Hide").then(|| Label {
+                    line: number + 1,
+                    rule: line.to_string(),
+                    name: "Item".to_string(),
+                    text: TEXT,
+                    border: None,
+                    background: BACKGROUND,
+                    font_size: DEFAULT_FONT_SIZE,
+                });
+            }
+            _ => {
+                let Some(label) = &mut current else {
+                    continue;
+                };
+                match keyword {
+                    "SetTextColor" => label.text = Color::parse(arguments).unwrap_or(label.text),
+                    "SetBorderColor" => label.border = Color::parse(arguments),
+                    "SetBackgroundColor" => {
+                        label.background = Color::parse(arguments).unwrap_or(label.background)
+                    }
+                    "SetFontSize" => {
+                        if let Ok(size) = arguments.trim().parse::<u8>() {
+                            label.font_size = size.clamp(1, 45);
+                        }
+                    }
+                    "BaseType" | "Class" if !named_by_base_type => {
+                        if let Some(name) = first_value(arguments) {
+                            label.name = name;
+                            named_by_base_type = keyword == "BaseType";
+                        }
+                    }
+                    _ => {}
+                }
+                label.rule.push('\n');
+                label.rule.push_str(line);
+            }
+        }
+    }
+    labels.extend(current);
+    labels
+}
+
+/// The first value of a condition, e.g. `Divine Orb` of
+/// `BaseType == "Divine Orb" "Mirror of Kalandra"`.
+fn first_value(arguments: &str) -> Option<String> {
+    let arguments = arguments.trim_start_matches(['=', '!', '<', '>', ' ']);
+    match arguments.strip_prefix('"') {
+        Some(rest) => rest.split('"').next().map(|v| v.to_string()),
+        None => arguments.split_whitespace().next().map(|v| v.to_string()),
+    }
+    .filter(|v| !v.is_empty())
+}
+
+/// The scale of the bitmap font for a font size, the game's default size is
+/// drawn three times as large as the font.
+fn scale(font_size: u8) -> usize {
+    ((font_size as usize + 5) / 11).max(1)
+}
+
+fn label_size(label: &Label) -> (usize, usize) {
+    let scale = scale(label.font_size);
+    let characters = label.name.chars().count();
+    let width =
+        (characters * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale + 2 * (3 * scale + BORDER);
+    let height = GLYPH_HEIGHT * scale + 2 * (2 * scale + BORDER);
+    (width, height)
+}
+
+/// Draws the labels below each other and encodes them as a PNG.
+pub fn render(labels: &[Label]) -> Result<Vec<u8>> {
+    let sizes: Vec<_> = labels.iter().map(label_size).collect();
+    let width = sizes.iter().map(|v| v.0).max().unwrap_or_default() + 2 * MARGIN;
+    let height = sizes.iter().map(|v| v.1 + GAP).sum::<usize>() - GAP + 2 * MARGIN;
+    let ground = GROUND.over([0; 3]);
+    let mut image = Image {
+        width,
+        pixels: vec![ground; width * height],
+    };
+
+    let mut y = MARGIN;
+    for (label, (label_width, label_height)) in labels.iter().zip(sizes) {
+        let x = MARGIN;
+        image.fill(x, y, label_width, label_height, label.background);
+        if let Some(border) = label.border {
+            image.fill(x, y, label_width, BORDER, border);
+            image.fill(x, y + label_height - BORDER, label_width, BORDER, border);
+            image.fill(x, y, BORDER, label_height, border);
+            image.fill(x + label_width - BORDER, y, BORDER, label_height, border);
+        }
+
+        let scale = scale(label.font_size);
+        let mut glyph_x = x + BORDER + 3 * scale;
+        let glyph_y = y + BORDER + 2 * scale;
+        for character in label.name.chars() {
+            image.glyph(glyph_x, glyph_y, scale, character, label.text);
+            glyph_x += (GLYPH_WIDTH + 1) * scale;
+        }
+        y += label_height + GAP;
+    }
+
+    image.png()
+}
+
+/// An RGB image.
+struct Image {
+    width: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        for row in y..y + height {
+            for pixel in &mut self.pixels[row * self.width + x..row * self.width + x + width] {
+                *pixel = color.over(*pixel);
+            }
+        }
+    }
+
+    fn glyph(&mut self, x: usize, y: usize, scale: usize, character: char, color: Color) {
+        let index = match character {
+            ' '..='~' => character as usize - ' ' as usize,
+            _ => '?' as usize - ' ' as usize,
+        };
+        for (column, bits) in FONT[index].iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) != 0 {
+                    self.fill(x + column * scale, y + row * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+
+    fn png(&self) -> Result<Vec<u8>> {
+        let height = self.pixels.len() / self.width;
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut header = Vec::new();
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((height as u32).to_be_bytes());
+        // 8 bits per channel, RGB, deflate, no filters, not interlaced.
+        header.extend([8, 2, 0, 0, 0]);
+        chunk(&mut png, b"IHDR", &header);
+
+        let mut data = ZlibEncoder::new(Vec::new(), Compression::default());
+        for row in self.pixels.chunks(self.width) {
+            // Each row starts with its filter type.
+            data.write_all(&[0])?;
+            data.write_all(row.as_flattened())?;
+        }
+        chunk(&mut png, b"IDAT", &data.finish()?);
+        chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// A 5x7 font for the printable ASCII characters, by column, with the top
+/// row in the lowest bit.
+const FONT: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
@@ -0,0 +1,129 @@
+use std::{collections::VecDeque, ffi::OsString, fs, process::Command};
+
+use color_eyre::{eyre::Context, Result};
+use log::{debug, info};
+
+/// Executable names used by the various PoE2 clients (Steam, standalone, 64-bit builds).
+const GAME_EXECUTABLES: [&str; 4] = [
+    "pathofexile.exe",
+    "pathofexile_x64.exe",
+    "pathofexilesteam.exe",
+    "pathofexile_x64steam.exe",
+];
+
+/// Returns the pid of a running game client, if any. Under Proton the game
+/// shows up as a wine process whose command line contains the Windows
+/// executable, so the whole command line is inspected rather than just the
+/// process name.
+pub fn find_running_game() -> Option<u32> {
+    if cfg!(windows) {
+        return find_running_game_with_tasklist();
+    }
+    let entries = fs::read_dir("/proc").ok()?;
+
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|v| v.parse().ok()) else {
+            continue;
+        };
+
+        let Ok(cmdline) = fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+
+        let is_game = cmdline
+            .split(|v| *v == 0)
+            .filter_map(|arg| std::str::from_utf8(arg).ok())
+            .filter_map(|arg| arg.rsplit(['/', '\\']).next())
+            .any(|exe| {
+                GAME_EXECUTABLES
+                    .iter()
+                    .any(|name| exe.eq_ignore_ascii_case(name))
+            });
+
+        if is_game {
+            debug!("found game process {pid}");
+            return Some(pid);
+        }
+    }
+
+    None
+}
+
+/// Windows has no `/proc`, `tasklist` lists the processes by executable name.
+fn find_running_game_with_tasklist() -> Option<u32> {
+    let output = Command::new("tasklist")
+        .args(["/FO", "CSV", "/NH"])
+        .output()
+        .inspect_err(|error| debug!("could not run tasklist: {error}"))
+        .ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(',').map(|v| v.trim_matches('"'));
+            let (exe, pid) = (fields.next()?, fields.next()?);
+            let is_game = GAME_EXECUTABLES
+                .iter()
+                .any(|name| exe.eq_ignore_ascii_case(name));
+            is_game.then(|| pid.parse().ok()).flatten()
+        })
+        .inspect(|pid| debug!("found game process {pid}"))
+}
+
+/// Checks whether a process with the given name (as in `/proc/<pid>/comm`) is
+/// running.
+pub fn is_running(name: &str) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim_end() == name)
+    })
+}
+
+/// Runs the command as a child process and waits for it to exit, returning
+/// the exit code to pass on. Like a shell, a child killed by a signal results
+/// in `128 + signal`. The child's environment is changed by `environment`,
+/// where `None` removes a variable.
+pub fn spawn_and_wait(
+    args: &VecDeque<OsString>,
+    environment: &[(OsString, Option<OsString>)],
+) -> Result<i32> {
+    let args: Vec<_> = args.iter().collect();
+    let Some((path, args)) = args.split_first() else {
+        return Ok(0);
+    };
+
+    let mut command = Command::new(path);
+    command.args(args);
+    for (key, value) in environment {
+        match value {
+            Some(value) => {
+                debug!("setting {key:?}={value:?}");
+                command.env(key, value);
+            }
+            None => {
+                debug!("unsetting {key:?}");
+                command.env_remove(key);
+            }
+        }
+    }
+
+    info!("spawning {path:?} {args:?}");
+    let status = command
+        .status()
+        .wrap_err_with(|| format!("could not start {path:?}"))?;
+
+    info!("{path:?} exited with {status}");
+    #[cfg(unix)]
+    let code = {
+        use std::os::unix::process::ExitStatusExt as _;
+        status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+    };
+    #[cfg(not(unix))]
+    let code = status.code();
+    Ok(code.unwrap_or(1))
+}
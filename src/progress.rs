@@ -0,0 +1,56 @@
+//! Progress of an update, for tools that want to show more than the log.
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+/// Something that happened while updating a source. `source` is the source
+/// with aliases expanded, e.g. `github:NeverSinkDev/NeverSink-PoE2litefilter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// Looking up the latest version.
+    Resolving { source: String },
+    /// Part of the archive was downloaded. `total` is `None` when the server
+    /// doesn't say how large it is.
+    Downloading {
+        source: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// Extracting the filters from the archive.
+    Extracting { source: String },
+    /// Writing a filter into the game directory.
+    Writing { source: String, path: PathBuf },
+    /// The source is up to date, `watermark` is the new version if it was
+    /// updated.
+    Done {
+        source: String,
+        watermark: Option<String>,
+    },
+    /// The source could not be updated.
+    Failed { source: String, error: String },
+}
+
+/// Receives the events of an update. It is called from whichever thread the
+/// work happens on, so it should return quickly.
+#[derive(Clone, Default)]
+pub struct Progress(Option<Arc<dyn Fn(Event) + Send + Sync>>);
+
+impl Progress {
+    pub fn new(callback: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        Progress(Some(Arc::new(callback)))
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(callback) = &self.0 {
+            callback(event);
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Progress")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
@@ -0,0 +1,376 @@
+//! `poe2filter bundle-report`: everything needed to look into a problem, in
+//! one tarball that can be attached to a bug report.
+
+use std::{
+    env::var,
+    ffi::OsString,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use log::warn;
+use reqwest::Url;
+
+use crate::{
+    config, dirs, etags_file, game, http, is_online, locate_game_directory, logging, process,
+    releases_file, Options,
+};
+
+/// The directory everything is put in inside the tarball.
+const ROOT: &str = "poe2filter-report";
+
+/// Environment variables that affect where poe2filter looks and what it does.
+const ENVIRONMENT: [&str; 14] = [
+    "HOME",
+    "XDG_CACHE_HOME",
+    "XDG_CONFIG_HOME",
+    "XDG_STATE_HOME",
+    "XDG_DATA_DIRS",
+    "STEAM_COMPAT_DATA_PATH",
+    "STEAM_COMPAT_LIBRARY_PATHS",
+    "STEAM_BASE_FOLDER",
+    "STEAM_COMPAT_APP_ID",
+    "SteamGameId",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "ALL_PROXY",
+    "NO_PROXY",
+];
+
+/// Handles `poe2filter bundle-report [path]`.
+pub async fn command(mut args: Vec<OsString>, options: &Options) -> Result<()> {
+    let path = match args.pop() {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("{ROOT}-{}.tar.gz", unix_time(SystemTime::now()))),
+    };
+
+    let file =
+        File::create(&path).wrap_err_with(|| format!("could not create {}", path.display()))?;
+    let mut tar = Tar::new(GzEncoder::new(file, Compression::default()));
+    tar.append("doctor.txt", doctor(options).await.as_bytes())?;
+    tar.append("config.txt", config(options).as_bytes())?;
+    tar.append("state.txt", state(options).await.as_bytes())?;
+    for log in logs() {
+        match fs::read(&log) {
+            Ok(data) => {
+                let name = log.file_name().unwrap_or_default().to_string_lossy();
+                tar.append(&format!("logs/{name}"), &data)?;
+            }
+            Err(error) => warn!("could not read {log:?}: {error}"),
+        }
+    }
+    tar.finish()?.finish()?;
+
+    println!("wrote {}", path.display());
+    println!("check that it contains nothing you'd rather not share before attaching it");
+    Ok(())
+}
+
+/// What poe2filter finds when it looks around: the game directory, the
+/// network, and whether the game is running.
+async fn doctor(options: &Options) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "poe2filter {}", env!("CARGO_PKG_VERSION"));
+    if let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") {
+        let _ = writeln!(out, "kernel {}", release.trim());
+    }
+
+    let _ = writeln!(out, "\n# environment");
+    for name in ENVIRONMENT {
+        match var(name) {
+            Ok(value) if name.ends_with("PROXY") && name != "NO_PROXY" => {
+                let _ = writeln!(out, "{name}={}", redact(&value));
+            }
+            Ok(value) => {
+                let _ = writeln!(out, "{name}={value}");
+            }
+            Err(_) => {}
+        }
+    }
+    for (name, value) in std::env::vars().filter(|(v, _)| v.starts_with("POE2FILTER_")) {
+        let _ = writeln!(out, "{name}={}", redact_variable(&name, &value));
+    }
+
+    let _ = writeln!(out, "\n# checks");
+    match locate_game_directory(options).await {
+        Ok(directory) => {
+            let _ = writeln!(out, "game directory: {}", directory.display());
+            let filters = fs::read_dir(&directory)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|v| v.path().extension().is_some_and(|v| v == "filter"));
+            for entry in filters {
+                let _ = writeln!(out, "  {}", describe(&entry.path()));
+            }
+        }
+        Err(error) => {
+            let _ = writeln!(out, "game directory: {error}");
+        }
+    }
+    let _ = writeln!(
+        out,
+        "network: {}",
+        match is_online().await {
+            true => "reachable",
+            false => "unreachable",
+        }
+    );
+    let _ = match process::find_running_game() {
+        Some(pid) => writeln!(out, "game: running (pid {pid})"),
+        None => writeln!(out, "game: not running"),
+    };
+    out
+}
+
+/// The options poe2filter was run with, without secrets.
+fn config(options: &Options) -> String {
+    let mut out = String::new();
+    let set = |v: bool| match v {
+        true => "set",
+        false => "not set",
+    };
+    let _ = writeln!(out, "config file: {:?}", config::path());
+    let _ = writeln!(out, "game directory: {:?}", options.game_directory);
+    let _ = writeln!(out, "github token: {}", set(options.github_token.is_some()));
+    let _ = writeln!(out, "gitlab token: {}", set(options.gitlab_token.is_some()));
+    let _ = writeln!(out, "proxy: {:?}", options.proxy.as_deref().map(redact));
+    let _ = writeln!(out, "no system proxy: {}", options.no_system_proxy);
+    let _ = writeln!(out, "ca bundle: {:?}", options.ca_bundle);
+    let _ = writeln!(out, "user agent: {:?}", options.user_agent);
+    let _ = writeln!(out, "contact: {:?}", options.contact);
+    let _ = writeln!(out, "timeouts: {:?}", options.timeouts);
+    let _ = writeln!(out, "retries: {}", options.retries);
+    let _ = writeln!(out, "jobs: {}", options.jobs);
+    let _ = writeln!(out, "deadline: {:?}", options.deadline);
+    let _ = writeln!(out, "max download size: {:?}", options.max_download_size);
+    let _ = writeln!(out, "limit rate: {:?}", options.limit_rate);
+    let _ = writeln!(out, "game: {}", game::game(options));
+    let _ = writeln!(out, "source games: {:?}", options.source_games);
+    let _ = writeln!(out, "app id: {:?}", options.app_id);
+    let _ = writeln!(out, "client log: {:?}", options.client_log);
+    let _ = writeln!(out, "league check: {}", options.league_check);
+    let _ = writeln!(out, "version check: {}", options.version_check);
+    let _ = writeln!(out, "notify: {:?}", options.notify);
+    let _ = writeln!(out, "hooks: {:?}", options.hooks);
+    for webhook in &options.webhooks {
+        let _ = writeln!(out, "webhook: {}", redact(webhook));
+    }
+    for (source, key) in &options.signing_keys {
+        let _ = writeln!(out, "signing key: {source}={key:?}");
+    }
+    for (source, stage) in &options.stages {
+        let _ = writeln!(out, "stage: {source}={stage}");
+    }
+    for profile in &options.profiles {
+        let _ = writeln!(out, "profile: {profile:?}");
+    }
+    for (name, value) in &options.environment {
+        let name = name.to_string_lossy();
+        let _ = match value {
+            Some(value) => writeln!(
+                out,
+                "env: {name}={}",
+                redact_variable(&name, &value.to_string_lossy())
+            ),
+            None => writeln!(out, "unset env: {name}"),
+        };
+    }
+    out
+}
+
+/// What poe2filter remembers between runs: the installed watermarks, the
+/// cached responses and downloads, and its own state.
+async fn state(options: &Options) -> String {
+    let mut out = String::new();
+    if let Ok(directory) = locate_game_directory(options).await {
+        let _ = writeln!(out, "# watermarks");
+        match fs::read_to_string(releases_file(&directory)) {
+            Ok(watermarks) => out.push_str(&watermarks),
+            Err(error) => {
+                let _ = write!(out, "{error}");
+            }
+        }
+        let etags = fs::read_to_string(etags_file(&directory))
+            .ok()
+            .and_then(|v| serde_json::from_str::<serde_json::Map<_, _>>(&v).ok());
+        let _ = writeln!(
+            out,
+            "\n{} ETag(s) stored",
+            etags.map(|v| v.len()).unwrap_or_default()
+        );
+        for name in ["filter_leagues.json", "filter_game_build.txt"] {
+            let _ = writeln!(out, "{}", describe(&directory.join(name)));
+        }
+    }
+
+    let _ = writeln!(out, "\n# cache");
+    let cache = dirs::cache_home().map(|v| v.join("poe2filter"));
+    for directory in cache
+        .iter()
+        .flat_map(|v| fs::read_dir(v).into_iter().flatten().flatten())
+    {
+        let (mut count, mut size) = (0, 0);
+        for entry in fs::read_dir(directory.path())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            count += 1;
+            size += entry.metadata().map(|v| v.len()).unwrap_or_default();
+        }
+        let _ = writeln!(
+            out,
+            "{}: {count} file(s), {size} bytes",
+            directory.file_name().to_string_lossy()
+        );
+    }
+
+    let _ = writeln!(out, "\n# state");
+    let state = dirs::state_home().map(|v| v.join("poe2filter"));
+    for entry in state
+        .iter()
+        .flat_map(|v| fs::read_dir(v).into_iter().flatten().flatten())
+    {
+        if entry.path().is_file() {
+            let _ = writeln!(out, "{}", describe(&entry.path()));
+        }
+    }
+    out
+}
+
+/// The current and rotated logs of `--log-to-file`.
+fn logs() -> Vec<PathBuf> {
+    let mut logs: Vec<_> = logging::logs_directory()
+        .iter()
+        .flat_map(|v| fs::read_dir(v).into_iter().flatten().flatten())
+        .map(|v| v.path())
+        .filter(|v| v.is_file())
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// A file's path, size and age.
+fn describe(path: &Path) -> String {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|v| SystemTime::now().duration_since(v).ok())
+                .unwrap_or_default();
+            format!(
+                "{}: {} bytes, modified {} ago",
+                path.display(),
+                metadata.len(),
+                humanize(age)
+            )
+        }
+        Err(error) => format!("{}: {error}", path.display()),
+    }
+}
+
+fn humanize(age: Duration) -> String {
+    match age.as_secs() {
+        seconds @ ..3600 => format!("{} minute(s)", seconds / 60),
+        seconds @ ..86400 => format!("{} hour(s)", seconds / 3600),
+        seconds => format!("{} day(s)", seconds / 86400),
+    }
+}
+
+/// Hides secrets in URLs, see [`http::redact_url`]. Anything that isn't a
+/// URL is hidden completely.
+fn redact(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => http::redact_url(&url),
+        Err(_) => "REDACTED".to_string(),
+    }
+}
+
+fn redact_variable(name: &str, value: &str) -> String {
+    const SECRETS: [&str; 4] = ["TOKEN", "KEY", "SECRET", "PASSWORD"];
+
+    let name = name.to_uppercase();
+    if SECRETS.iter().any(|v| name.contains(v)) {
+        "REDACTED".to_string()
+    } else if name.contains("WEBHOOK") || name.contains("PROXY") {
+        value
+            .split_whitespace()
+            .map(redact)
+            .collect::<Vec<_>>()
+            .join(" ")
+    } else {
+        value.to_string()
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Writes a tar archive (in the ustar format) of regular files.
+struct Tar<W: Write> {
+    writer: W,
+    mtime: u64,
+}
+
+impl<W: Write> Tar<W> {
+    fn new(writer: W) -> Self {
+        Tar {
+            writer,
+            mtime: unix_time(SystemTime::now()),
+        }
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let name = format!("{ROOT}/{name}");
+        if name.len() > 100 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{name} is too long for a tar archive"),
+            ));
+        }
+
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is computed with its own field filled with spaces.
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|v| *v as u64).sum();
+        octal(&mut header[148..155], checksum);
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        self.writer
+            .write_all(&[0; 512][..data.len().next_multiple_of(512) - data.len()])
+    }
+
+    /// Ends the archive with two empty blocks.
+    fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0; 1024])?;
+        Ok(self.writer)
+    }
+}
+
+/// Fills a header field with a zero-padded octal number and a NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{value:0width$o}");
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
@@ -0,0 +1,194 @@
+//! `poe2filter serve --stdio`: JSON-RPC 2.0 over stdin and stdout, one
+//! message per line, so that GUIs and plugins can drive poe2filter without
+//! parsing its output.
+//!
+//! Methods:
+//!
+//! - `sources`: the sources poe2filter was started with, and the watermark
+//!   installed for each.
+//! - `check`: whether a newer version of each source is available, without
+//!   installing anything.
+//! - `update`: updates the sources and returns the summary. While it runs,
+//!   `progress` notifications are sent with the events of `--events jsonl`.
+//! - `shutdown`: stops serving, as does closing stdin.
+//!
+//! `check` and `update` take an optional `sources` parameter to work on
+//! other sources than the ones poe2filter was started with.
+
+use std::{
+    ffi::OsString,
+    io::{stdin, BufRead},
+};
+
+use color_eyre::Result;
+use log::{debug, info};
+use serde_json::{json, Value};
+
+use crate::{
+    block_on, cancel,
+    events::{event_json, summary_json, EventLog},
+    installed_versions,
+    progress::Progress,
+    resolve_alias, source, update_with, Globals, Options,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Updating or checking failed as a whole, e.g. the game directory wasn't
+/// found. Sources that fail on their own are part of the result instead.
+const FAILED: i64 = -32000;
+
+/// Answers requests from stdin until it is closed or `shutdown` is called.
+pub fn serve(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    let mut options = options.clone();
+    // stdout is for messages only.
+    options.events = None;
+    let output = EventLog::open("-")?;
+    info!("serving JSON-RPC on stdin and stdout");
+
+    for line in stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if cancel::is_cancelled() {
+            break;
+        }
+
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(error) => {
+                output.write(&failure(Value::Null, PARSE_ERROR, &error.to_string()));
+                continue;
+            }
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(|v| v.as_str()) else {
+            output.write(&failure(
+                id.unwrap_or_default(),
+                INVALID_REQUEST,
+                "method is missing",
+            ));
+            continue;
+        };
+        debug!("{method} was called");
+
+        let params = request.get("params").cloned().unwrap_or_default();
+        let result = match method {
+            "shutdown" => {
+                if let Some(id) = id {
+                    output.write(&json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+                }
+                break;
+            }
+            "sources" => Ok(list(&sources, &options)),
+            "check" => {
+                requested_sources(&params, &sources).and_then(|sources| check(sources, &options))
+            }
+            "update" => requested_sources(&params, &sources)
+                .and_then(|sources| update(sources, &options, &output)),
+            _ => Err((METHOD_NOT_FOUND, format!("{method} is not a method"))),
+        };
+
+        // Without an id it is a notification, which is never answered.
+        let Some(id) = id else {
+            continue;
+        };
+        match result {
+            Ok(result) => output.write(&json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+            Err((code, message)) => output.write(&failure(id, code, &message)),
+        }
+    }
+
+    Ok(())
+}
+
+fn failure(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// The `sources` parameter, or the sources poe2filter was started with.
+fn requested_sources(params: &Value, sources: &[OsString]) -> Result<Vec<OsString>, (i64, String)> {
+    match params.get("sources") {
+        None | Some(Value::Null) => Ok(sources.to_vec()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|v| v.as_str().map(OsString::from))
+            .collect::<Option<_>>()
+            .ok_or_else(|| (INVALID_PARAMS, "sources must be strings".to_string())),
+        Some(_) => Err((INVALID_PARAMS, "sources must be an array".to_string())),
+    }
+}
+
+fn list(sources: &[OsString], options: &Options) -> Value {
+    let installed = block_on(installed_versions(options));
+    sources
+        .iter()
+        .map(|v| {
+            let source = v.to_string_lossy();
+            let resolved = resolve_alias(&source);
+            json!({
+                "source": source,
+                "resolved": resolved,
+                "watermark": installed.get(resolved),
+            })
+        })
+        .collect()
+}
+
+fn check(sources: Vec<OsString>, options: &Options) -> Result<Value, (i64, String)> {
+    block_on(async {
+        let globals = Globals::new(options)
+            .await
+            .map_err(|error| (FAILED, format!("{error:#}")))?;
+
+        let mut results = Vec::new();
+        for source in sources {
+            let source = source.to_string_lossy();
+            let resolved = resolve_alias(&source);
+            let installed = globals.versions.get(resolved);
+            let mut result = json!({
+                "source": source,
+                "resolved": resolved,
+                "watermark": installed,
+            });
+            match source::latest(&globals, resolved, installed).await {
+                Ok(latest) => {
+                    result["update_available"] = json!(latest.is_some());
+                    result["latest"] = json!(latest.as_ref().or(installed));
+                }
+                Err(error) => result["error"] = json!(format!("{error:#}")),
+            }
+            results.push(result);
+        }
+        Ok(Value::Array(results))
+    })
+}
+
+fn update(
+    sources: Vec<OsString>,
+    options: &Options,
+    output: &EventLog,
+) -> Result<Value, (i64, String)> {
+    let progress = {
+        let output = output.clone();
+        Progress::new(move |event| {
+            output.write(&json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": event_json(&event),
+            }))
+        })
+    };
+    let summary = block_on(update_with(sources, options, |globals| {
+        globals.progress = progress
+    }))
+    .map_err(|error| (FAILED, format!("{error:#}")))?;
+    Ok(summary_json(&summary))
+}
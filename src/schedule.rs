@@ -0,0 +1,98 @@
+//! `poe2filter schedule install|uninstall`: periodic updates using the
+//! platform's scheduler, Task Scheduler on Windows and a systemd user timer
+//! everywhere else.
+
+use std::ffi::OsString;
+
+use color_eyre::Result;
+
+use crate::Options;
+
+#[cfg(unix)]
+pub async fn command(args: Vec<OsString>, options: &Options) -> Result<()> {
+    crate::systemd::command(args, options).await
+}
+
+#[cfg(windows)]
+pub async fn command(mut args: Vec<OsString>, options: &Options) -> Result<()> {
+    use color_eyre::eyre::bail;
+
+    if args.is_empty() {
+        bail!("usage: poe2filter schedule install <sources>|uninstall");
+    }
+
+    match args.remove(0).to_str() {
+        Some("install") => task_scheduler::install(args, options),
+        Some("uninstall") => task_scheduler::uninstall(),
+        _ => bail!("usage: poe2filter schedule install <sources>|uninstall"),
+    }
+}
+
+#[cfg(windows)]
+mod task_scheduler {
+    use std::{env::current_exe, ffi::OsString, process::Command};
+
+    use color_eyre::{
+        eyre::{bail, eyre, Context},
+        Result,
+    };
+    use log::info;
+
+    use crate::Options;
+
+    const TASK_NAME: &str = "poe2filter";
+
+    pub fn install(sources: Vec<OsString>, options: &Options) -> Result<()> {
+        if sources.is_empty() {
+            bail!("at least one source is required");
+        }
+
+        let exe = current_exe().wrap_err_with(|| "could not determine the poe2filter path")?;
+        let mut command = vec![format!("\"{}\"", exe.display())];
+        for source in &sources {
+            let source = source
+                .to_str()
+                .ok_or_else(|| eyre!("all arguments must be valid UTF-8"))?;
+            command.push(format!("\"{source}\""));
+        }
+
+        let minutes = (options.interval.as_secs() / 60).max(1);
+        schtasks(&[
+            "/Create",
+            "/F",
+            "/TN",
+            TASK_NAME,
+            "/SC",
+            "MINUTE",
+            "/MO",
+            &minutes.to_string(),
+            "/TR",
+            &command.join(" "),
+        ])?;
+
+        eprintln!(
+            "installed the {TASK_NAME} task, filters will be updated every {minutes} minutes"
+        );
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        schtasks(&["/Delete", "/F", "/TN", TASK_NAME])?;
+        eprintln!("uninstalled the {TASK_NAME} task");
+        Ok(())
+    }
+
+    fn schtasks(args: &[&str]) -> Result<()> {
+        info!("running schtasks {}", args.join(" "));
+        let status = Command::new("schtasks")
+            .args(args)
+            .status()
+            .wrap_err_with(|| "could not run schtasks")?;
+
+        if !status.success() {
+            bail!("schtasks {} failed with {status}", args.join(" "));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Transforms written in [Rhai](https://rhai.rs), which is embedded so that
+//! they work the same everywhere, without a shell or an interpreter.
+//!
+//! A transform is either the path of a `.rhai` file or the code itself. It
+//! is given the filter as the string `filter`, which it changes in place or
+//! replaces, along with `source` and `name` (the name of the filter in the
+//! release):
+//!
+//! ```rhai
+//! filter.replace("PlayAlertSound 1 300", "PlayAlertSound 1 100");
+//! if name.contains("Strict") { filter += "\nHide\n    BaseType \"Scroll of Wisdom\"\n"; }
+//! ```
+
+use std::path::Path;
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use log::{debug, info};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope};
+
+use crate::{cancel, Error};
+
+/// How many operations a transform may take, far more than going over every
+/// line of a filter a few times needs. A transform that loops forever fails
+/// instead of keeping the update going.
+const MAX_OPERATIONS: u64 = 100_000_000;
+
+/// Runs a transform on a filter and returns what `filter` holds once it is
+/// done. Unlike hooks, a failing transform fails the source: installing the
+/// untransformed filter would silently undo the user's changes. This blocks,
+/// the transform is stopped when `cancel` is cancelled.
+pub fn transform(
+    script: &str,
+    source: &str,
+    name: &str,
+    data: Vec<u8>,
+    cancel: &cancel::Token,
+) -> Result<Vec<u8>> {
+    debug!("transforming {name} with {script}");
+    let Ok(filter) = String::from_utf8(data) else {
+        bail!("{name} is not valid UTF-8 and can't be transformed");
+    };
+
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_progress({
+        let cancel = cancel.clone();
+        move |_| cancel.is_cancelled().then(|| Dynamic::from("cancelled"))
+    });
+    engine.on_print(|text| info!("transform: {text}"));
+    engine.on_debug(|text, _, position| debug!("transform at {position}: {text}"));
+
+    let ast = if script.ends_with(".rhai") {
+        engine
+            .compile_file(Path::new(script).into())
+            .map_err(|error| eyre!("could not compile transform {script:?}: {error}"))?
+    } else {
+        engine
+            .compile(script)
+            .map_err(|error| eyre!("could not compile transform {script:?}: {error}"))?
+    };
+
+    let mut scope = Scope::new();
+    scope
+        .push("filter", filter)
+        .push_constant("source", source.to_string())
+        .push_constant("name", name.to_string());
+    let result = engine.run_ast_with_scope(&mut scope, &ast);
+    if let Err(error) = result {
+        if matches!(*error, EvalAltResult::ErrorTerminated(..)) {
+            bail!(Error::Cancelled);
+        }
+        bail!("transform {script:?} failed on {name}: {error}");
+    }
+
+    match scope.get_value::<String>("filter") {
+        Some(filter) => Ok(filter.into_bytes()),
+        None => bail!("transform {script:?} did not leave a string in filter for {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transform;
+    use crate::{cancel, Error};
+
+    fn run(script: &str, filter: &str) -> color_eyre::Result<String> {
+        let cancel = cancel::Token::default();
+        let data = transform(script, "github:a/b", "A.filter", filter.into(), &cancel)?;
+        Ok(String::from_utf8(data).unwrap())
+    }
+
+    #[test]
+    fn changes_the_filter_in_place() {
+        let filter = run(r#"filter.replace("300", "100");"#, "PlayAlertSound 1 300").unwrap();
+        assert_eq!(filter, "PlayAlertSound 1 100");
+    }
+
+    #[test]
+    fn replaces_the_filter() {
+        let filter = run(r#"filter = `${source} ${name}`;"#, "Show").unwrap();
+        assert_eq!(filter, "github:a/b A.filter");
+    }
+
+    #[test]
+    fn runs_files() {
+        let path = std::env::temp_dir().join(format!("poe2filter-{}.rhai", std::process::id()));
+        std::fs::write(&path, r#"filter += "\nHide";"#).unwrap();
+        let filter = run(path.to_str().unwrap(), "Show");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(filter.unwrap(), "Show\nHide");
+    }
+
+    #[test]
+    fn fails_on_errors() {
+        assert!(run("filter.replace(", "Show").is_err());
+        assert!(run(r#"throw "no";"#, "Show").is_err());
+        assert!(run("filter = 5;", "Show").is_err());
+        assert!(run("missing.rhai", "Show").is_err());
+        let cancel = cancel::Token::default();
+        assert!(transform("filter", "a", "A.filter", vec![0xff], &cancel).is_err());
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let cancel = cancel::Token::default();
+        cancel.cancel();
+        let error = transform("loop {}", "a", "A.filter", b"Show".to_vec(), &cancel).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(Error::Cancelled)));
+    }
+}
@@ -0,0 +1,95 @@
+//! Where filters come from.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use futures_util::future::BoxFuture;
+
+use crate::{file, filterblade, github, gitlab, url, Error, Globals, VersionInfo};
+
+/// Sources by the scheme they handle (the part before the colon).
+static PROVIDERS: LazyLock<RwLock<BTreeMap<String, Arc<dyn Source>>>> = LazyLock::new(|| {
+    let mut providers: BTreeMap<String, Arc<dyn Source>> = BTreeMap::new();
+    providers.insert("file".to_string(), Arc::new(file::File));
+    providers.insert(
+        "filterblade".to_string(),
+        Arc::new(filterblade::FilterBlade),
+    );
+    providers.insert("github".to_string(), Arc::new(github::GitHub));
+    providers.insert("gitlab".to_string(), Arc::new(gitlab::GitLab));
+    providers.insert("url".to_string(), Arc::new(url::Url));
+    RwLock::new(providers)
+});
+
+/// A kind of source, such as `github:`. The value is everything after the
+/// colon of the source, e.g. `owner/repo` for `github:owner/repo`.
+///
+/// Errors without a more specific class can be returned as [`Error::Other`],
+/// which any `color_eyre::Report` converts into.
+pub trait Source: Send + Sync {
+    /// Looks up the latest version, `None` if it is still the `existing` one.
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>>;
+
+    /// Installs the filters of a version found by [`Source::resolve`] into
+    /// the game directory, and returns their paths.
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>>;
+}
+
+/// Makes a kind of source available as `scheme:value`, replacing the one
+/// that was registered for it before. This is how tools that embed
+/// poe2filter add their own sources.
+pub fn register(scheme: &str, source: impl Source + 'static) {
+    let mut providers = PROVIDERS.write().unwrap_or_else(|v| v.into_inner());
+    providers.insert(scheme.to_string(), Arc::new(source));
+}
+
+/// The source registered for a scheme.
+pub fn provider(scheme: &str) -> Result<Arc<dyn Source>, Error> {
+    let providers = PROVIDERS.read().unwrap_or_else(|v| v.into_inner());
+    providers.get(scheme).cloned().ok_or_else(|| {
+        let known: Vec<_> = providers.keys().map(|v| format!("{v}:")).collect();
+        Error::InvalidSourceSpec(format!(
+            "unknown source type {scheme}:, expected one of {}",
+            known.join(", ")
+        ))
+    })
+}
+
+/// Splits a source (with aliases resolved) into its scheme and value.
+pub(crate) fn split(source: &str) -> Result<(&str, &str), Error> {
+    match source.split_once(':') {
+        Some((scheme, value)) if !scheme.is_empty() => Ok((scheme, value)),
+        _ if source.starts_with('-') => Err(Error::InvalidSourceSpec(format!(
+            "{source} is not an option, see poe2filter --help"
+        ))),
+        _ => Err(Error::InvalidSourceSpec(format!(
+            "{source} is not a source, sources look like github:<owner>/<repo> \
+            (see poe2filter --help)"
+        ))),
+    }
+}
+
+/// The watermark of the latest version of a source, `None` if it is the
+/// `installed` one.
+pub(crate) async fn latest(
+    globals: &Globals,
+    source: &str,
+    installed: Option<&String>,
+) -> Result<Option<String>, Error> {
+    let (scheme, value) = split(source)?;
+    let version = provider(scheme)?.resolve(globals, value, installed).await?;
+    Ok(version.map(|v| v.watermark))
+}
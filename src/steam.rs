@@ -0,0 +1,383 @@
+use std::{
+    collections::HashSet,
+    env::{current_exe, var_os},
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Result,
+};
+use log::{debug, info, warn};
+use tokio::fs;
+
+use crate::{app_id, install, process, split_paths, vdf, Options};
+
+/// Where the ways of installing Steam put it, relative to the home directory.
+const STEAM_LAYOUTS: [(&str, &str); 5] = [
+    ("native", ".local/share/Steam"),
+    ("native", ".steam/steam"),
+    (
+        "Flatpak",
+        ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+    ),
+    ("Flatpak", ".var/app/com.valvesoftware.Steam/data/Steam"),
+    ("Snap", "snap/steam/common/.local/share/Steam"),
+];
+
+/// Candidate Steam installation roots (the directories containing `steamapps`
+/// and `userdata`), in order of preference.
+pub fn steam_roots() -> Vec<PathBuf> {
+    steam_installs().into_iter().map(|(_, v)| v).collect()
+}
+
+/// The Steam installation roots, along with how Steam was installed.
+pub fn steam_installs() -> Vec<(&'static str, PathBuf)> {
+    let mut candidates = Vec::new();
+
+    if let Some(base_path) = var_os("STEAM_BASE_FOLDER") {
+        candidates.push(("configured", PathBuf::from(base_path)));
+    }
+
+    if let Some(home) = var_os("HOME") {
+        let home = PathBuf::from(home);
+        for (layout, path) in STEAM_LAYOUTS {
+            candidates.push((layout, home.join(path)));
+        }
+    }
+
+    // ~/.steam/steam is usually a symlink to one of the others.
+    let mut seen = HashSet::new();
+    candidates
+        .into_iter()
+        .filter_map(|(layout, path)| Some((layout, path.canonicalize().ok()?)))
+        .filter(|(_, path)| path.is_dir() && seen.insert(path.clone()))
+        .collect()
+}
+
+/// The Steam libraries (the directories containing `steamapps`) listed in
+/// the `libraryfolders.vdf` of every Steam installation, e.g. on a second
+/// drive. Libraries that have the game come first.
+pub fn library_folders(app_id: &OsStr) -> Vec<PathBuf> {
+    let app_id = app_id.to_string_lossy();
+    let (mut with_game, mut others) = (Vec::new(), Vec::new());
+
+    for root in steam_roots() {
+        let path = root.join("steamapps/libraryfolders.vdf");
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let folders = match vdf::parse(&text) {
+            Ok(folders) => folders,
+            Err(error) => {
+                warn!("could not parse {path:?}: {error}");
+                continue;
+            }
+        };
+
+        let Some(folders) = folders.get("libraryfolders") else {
+            continue;
+        };
+        for (key, folder) in folders.children() {
+            // Older versions of Steam only stored the path, next to other
+            // settings.
+            let library = match folder {
+                vdf::Value::String(path) if key.parse::<u32>().is_ok() => path,
+                vdf::Value::String(_) => continue,
+                vdf::Value::Object(_) => match folder.get("path").and_then(|v| v.as_str()) {
+                    Some(path) => path,
+                    None => continue,
+                },
+            };
+            debug!("found Steam library {library:?} in {path:?}");
+            match folder.get("apps").and_then(|v| v.get(&app_id)) {
+                Some(_) => with_game.push(PathBuf::from(library)),
+                None => others.push(PathBuf::from(library)),
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    with_game
+        .into_iter()
+        .chain(others)
+        .filter(|v| seen.insert(v.clone()))
+        .collect()
+}
+
+/// Candidate `steamapps` directories that may contain the game.
+fn steamapps_directories(app_id: &OsStr) -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+
+    if let Some(compat_paths) = var_os("STEAM_COMPAT_LIBRARY_PATHS") {
+        directories.extend(split_paths(compat_paths));
+    }
+
+    directories.extend(steam_roots().into_iter().map(|v| v.join("steamapps")));
+    directories.extend(
+        library_folders(app_id)
+            .into_iter()
+            .map(|v| v.join("steamapps")),
+    );
+    directories
+}
+
+/// Finds and parses the Steam app manifest of the game, returning the
+/// `steamapps` directory it was found in along with it.
+async fn app_manifest(app_id: &OsStr) -> Option<(PathBuf, vdf::Value)> {
+    let name = format!("appmanifest_{}.acf", app_id.to_string_lossy());
+
+    for directory in steamapps_directories(app_id) {
+        let path = directory.join(&name);
+        let Ok(text) = fs::read_to_string(&path).await else {
+            continue;
+        };
+
+        match vdf::parse(&text) {
+            Ok(manifest) => return Some((directory, manifest)),
+            Err(error) => warn!("could not parse {path:?}: {error}"),
+        }
+    }
+
+    None
+}
+
+/// Reads the build id of the installed game from its Steam app manifest.
+pub async fn game_build(app_id: &OsStr) -> Option<String> {
+    let (_, manifest) = app_manifest(app_id).await?;
+    let build = manifest.get("AppState")?.get("buildid")?.as_str()?;
+    debug!("game build is {build}");
+    Some(build.to_string())
+}
+
+/// The directory the game is installed in, according to its app manifest.
+pub async fn install_directory(app_id: &OsStr) -> Option<PathBuf> {
+    let (directory, manifest) = app_manifest(app_id).await?;
+    let install_dir = manifest.get("AppState")?.get("installdir")?.as_str()?;
+    Some(directory.join("common").join(install_dir))
+}
+
+/// Handles `poe2filter install-steam <sources>`: sets the launch options of
+/// the game in every Steam account's `localconfig.vdf` so that it's wrapped
+/// by poe2filter.
+pub async fn install_launch_options(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    if sources.is_empty() {
+        bail!("usage: poe2filter install-steam <sources>");
+    }
+
+    if process::is_running("steam") {
+        bail!("Steam is running, it would overwrite the launch options when it exits. Close Steam and try again.");
+    }
+
+    let exe = current_exe().wrap_err_with(|| "could not determine the poe2filter path")?;
+    let mut wrapper =
+        vec![quote(exe.to_str().ok_or_else(|| {
+            eyre!("the poe2filter path must be valid UTF-8")
+        })?)];
+    for source in &sources {
+        let source = source
+            .to_str()
+            .ok_or_else(|| eyre!("all arguments must be valid UTF-8"))?;
+        wrapper.push(quote(source));
+    }
+    wrapper.push("--".to_string());
+    wrapper.push("%command%".to_string());
+    let wrapper = wrapper.join(" ");
+
+    let app_id = app_id(options);
+    let app_id = app_id.to_string_lossy();
+
+    let mut updated = 0;
+    for root in steam_roots() {
+        let Ok(mut users) = fs::read_dir(root.join("userdata")).await else {
+            continue;
+        };
+
+        while let Some(user) = users.next_entry().await? {
+            let path = user.path().join("config/localconfig.vdf");
+            if !path.is_file() {
+                continue;
+            }
+
+            info!("updating {path:?}");
+            let text = fs::read_to_string(&path).await?;
+            let mut config =
+                vdf::parse_exact(&text).wrap_err_with(|| format!("could not parse {path:?}"))?;
+
+            let app = config.object_at(&[
+                "UserLocalConfigStore",
+                "Software",
+                "Valve",
+                "Steam",
+                "apps",
+                &app_id,
+            ])?;
+            let existing = app
+                .get("LaunchOptions")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            let launch_options = wrap_launch_options(existing, &wrapper);
+            app.set("LaunchOptions", launch_options.clone())?;
+
+            // Only the first backup has the launch options from before
+            // poe2filter, later runs would back up their own changes.
+            let backup = path.with_extension("vdf.poe2filter-backup");
+            if !backup.exists() {
+                info!("backing up {path:?} to {backup:?}");
+                fs::copy(&path, &backup).await?;
+            }
+            let directory = path.parent().unwrap_or(&path);
+            install::write_file(directory, &path, vdf::to_string(&config).as_bytes()).await?;
+
+            eprintln!("{}: {launch_options}", path.display());
+            updated += 1;
+        }
+    }
+
+    if updated == 0 {
+        bail!("could not find any Steam localconfig.vdf");
+    }
+
+    Ok(())
+}
+
+/// Combines the existing launch options with the poe2filter invocation. Other
+/// wrappers are kept, an existing poe2filter invocation is replaced, and
+/// launch options without `%command%` (plain game arguments) are moved after
+/// the command.
+fn wrap_launch_options(existing: &str, wrapper: &str) -> String {
+    let words = split_words(existing);
+    let Some(command) = words.iter().position(|v| *v == "%command%") else {
+        return format!("{wrapper} {existing}").trim_end().to_string();
+    };
+
+    let start = words[..command]
+        .iter()
+        .position(|v| {
+            let word = unquote(v);
+            word.ends_with("poe2filter") || word.ends_with("poe2filter.exe")
+        })
+        .unwrap_or(command);
+
+    if start != command {
+        warn!("replacing the existing poe2filter launch options");
+    }
+
+    let mut result: Vec<&str> = words[..start].to_vec();
+    result.push(wrapper);
+    result.extend(&words[command + 1..]);
+    result.join(" ")
+}
+
+/// Splits launch options into words like the shell does, so that a quoted
+/// path with spaces is one word. The words keep their quotes.
+fn split_words(options: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in options.char_indices() {
+        if c.is_whitespace() && quote.is_none() && !escaped {
+            if let Some(start) = start.take() {
+                words.push(&options[start..index]);
+            }
+            continue;
+        }
+
+        start.get_or_insert(index);
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && matches!(c, '"' | '\'') {
+            quote = Some(c);
+        }
+    }
+    if let Some(start) = start {
+        words.push(&options[start..]);
+    }
+    words
+}
+
+/// A word of [`split_words`] without its quotes and escapes.
+fn unquote(word: &str) -> String {
+    let mut result = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quote != Some('\'') => result.extend(chars.next()),
+            _ if quote == Some(c) => quote = None,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn quote(arg: &str) -> String {
+    if arg.contains([' ', '"', '\'']) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, split_words, unquote, wrap_launch_options};
+
+    const WRAPPER: &str = "\"/home/user/My Apps/poe2filter\" neversink -- %command%";
+
+    #[test]
+    fn split_words_keeps_quoted_words_together() {
+        assert_eq!(
+            split_words(r#"  a "b c" 'd e'  f\ g "h \" i" "#),
+            [r#"a"#, r#""b c""#, r#"'d e'"#, r#"f\ g"#, r#""h \" i""#]
+        );
+        assert_eq!(split_words(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn unquote_removes_quotes_and_escapes() {
+        assert_eq!(unquote(r#""b c""#), "b c");
+        assert_eq!(unquote(r#"'d "e'"#), r#"d "e"#);
+        assert_eq!(unquote(r#"f\ g"#), "f g");
+        assert_eq!(unquote(r#""h \" i""#), r#"h " i"#);
+        assert_eq!(unquote(&quote(r#"a "b" c"#)), r#"a "b" c"#);
+    }
+
+    #[test]
+    fn wrap_launch_options_adds_the_wrapper() {
+        assert_eq!(wrap_launch_options("", WRAPPER), WRAPPER);
+        assert_eq!(
+            wrap_launch_options("gamemoderun %command%", WRAPPER),
+            format!("gamemoderun {WRAPPER}")
+        );
+        assert_eq!(
+            wrap_launch_options("--nologo", WRAPPER),
+            format!("{WRAPPER} --nologo")
+        );
+    }
+
+    #[test]
+    fn wrap_launch_options_replaces_the_previous_wrapper() {
+        let previous =
+            "mangohud \"/home/user/My Apps/poe2filter\" github:a/b -- %command% --nologo";
+        assert_eq!(
+            wrap_launch_options(previous, WRAPPER),
+            format!("mangohud {WRAPPER} --nologo")
+        );
+        assert_eq!(
+            wrap_launch_options(&wrap_launch_options("", WRAPPER), WRAPPER),
+            WRAPPER
+        );
+        assert_eq!(
+            wrap_launch_options("/usr/bin/poe2filter a -- %command%", WRAPPER),
+            WRAPPER
+        );
+    }
+}
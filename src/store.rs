@@ -0,0 +1,118 @@
+//! What is remembered about installed sources, in `filter_watermarks.json`:
+//! the watermark of each source and the files it installed, so that files a
+//! newer version no longer has can be removed.
+//!
+//! Older versions only stored the watermark, as a string.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::Result;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{guard, install, releases_file};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Installed {
+    /// `None` when the source has to be installed again, e.g. after a patch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+    /// Relative to the game directory, unless installed outside of it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Watermark(String),
+    Installed(Installed),
+}
+
+/// Reads the store, starting from scratch if it is missing or broken.
+pub(crate) async fn read(game_directory: &Path) -> BTreeMap<String, Installed> {
+    let Ok(store) = fs::read_to_string(releases_file(game_directory)).await else {
+        return BTreeMap::new();
+    };
+    match serde_json::from_str::<BTreeMap<String, Stored>>(&store) {
+        Ok(sources) => sources
+            .into_iter()
+            .map(|(source, stored)| {
+                let installed = match stored {
+                    Stored::Watermark(watermark) => Installed {
+                        watermark: Some(watermark),
+                        files: Vec::new(),
+                    },
+                    Stored::Installed(installed) => installed,
+                };
+                (source, installed)
+            })
+            .collect(),
+        Err(error) => {
+            error!("could not read existing files, starting from scratch: {error}");
+            BTreeMap::new()
+        }
+    }
+}
+
+pub(crate) async fn write(
+    game_directory: &Path,
+    sources: &BTreeMap<String, Installed>,
+) -> Result<()> {
+    let store = serde_json::to_string_pretty(sources)?;
+    install::write_file(
+        game_directory,
+        &releases_file(game_directory),
+        store.as_bytes(),
+    )
+    .await
+}
+
+/// Combines the watermarks and the installed files of every source.
+pub(crate) fn merge(
+    versions: &HashMap<String, String>,
+    files: &HashMap<String, Vec<PathBuf>>,
+) -> BTreeMap<String, Installed> {
+    let mut sources: BTreeMap<String, Installed> = BTreeMap::new();
+    for (source, watermark) in versions {
+        sources.entry(source.clone()).or_default().watermark = Some(watermark.clone());
+    }
+    for (source, files) in files.iter().filter(|(_, v)| !v.is_empty()) {
+        sources.entry(source.clone()).or_default().files = files.clone();
+    }
+    sources
+}
+
+/// How a file is recorded, relative to the game directory if it is inside.
+pub(crate) fn relative(game_directory: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(game_directory)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Removes the `previous` files of a source that no other source installed
+/// as well.
+pub(crate) async fn remove_files(
+    game_directory: &Path,
+    previous: &[PathBuf],
+    files: &HashMap<String, Vec<PathBuf>>,
+) {
+    for file in previous {
+        if files.values().flatten().any(|v| v == file) {
+            continue;
+        }
+        let path = game_directory.join(file);
+        info!("removing {path:?}");
+        let mut pause = guard::pause();
+        pause.removed(&path);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => warn!("could not remove {path:?}: {error}"),
+        }
+    }
+}
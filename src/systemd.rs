@@ -0,0 +1,133 @@
+use std::{env::current_exe, ffi::OsString, path::PathBuf, process::Command};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Result,
+};
+use log::{info, warn};
+use tokio::fs;
+
+use crate::{dirs, Options};
+
+const SERVICE: &str = "poe2filter.service";
+const TIMER: &str = "poe2filter.timer";
+
+/// Handles `poe2filter systemd install|uninstall`.
+pub async fn command(mut args: Vec<OsString>, options: &Options) -> Result<()> {
+    if args.is_empty() {
+        bail!("usage: poe2filter systemd install <sources>|uninstall");
+    }
+
+    match args.remove(0).to_str() {
+        Some("install") => install(args, options).await,
+        Some("uninstall") => uninstall().await,
+        _ => bail!("usage: poe2filter systemd install <sources>|uninstall"),
+    }
+}
+
+fn unit_directory() -> Result<PathBuf> {
+    dirs::config_home()
+        .map(|v| v.join("systemd/user"))
+        .ok_or_else(|| eyre!("could not determine the systemd user unit directory"))
+}
+
+/// Quotes an argument for `ExecStart=`.
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{escaped}\"")
+}
+
+async fn install(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    if sources.is_empty() {
+        bail!("at least one source is required");
+    }
+
+    let exe = current_exe().wrap_err_with(|| "could not determine the poe2filter path")?;
+    let mut command =
+        vec![quote(exe.to_str().ok_or_else(|| {
+            eyre!("the poe2filter path must be valid UTF-8")
+        })?)];
+    for source in &sources {
+        let source = source
+            .to_str()
+            .ok_or_else(|| eyre!("all arguments must be valid UTF-8"))?;
+        command.push(quote(source));
+    }
+
+    let service = format!(
+        "[Unit]\n\
+         Description=Update Path of Exile 2 item filters\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={}\n",
+        command.join(" ")
+    );
+
+    let interval = options.interval.as_secs();
+    let timer = format!(
+        "[Unit]\n\
+         Description=Periodically update Path of Exile 2 item filters\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=5min\n\
+         OnUnitActiveSec={interval}s\n\
+         RandomizedDelaySec={}s\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        interval / 10
+    );
+
+    let directory = unit_directory()?;
+    fs::create_dir_all(&directory).await?;
+    for (name, contents) in [(SERVICE, service), (TIMER, timer)] {
+        let path = directory.join(name);
+        info!("writing {path:?}");
+        fs::write(&path, contents).await?;
+    }
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", TIMER])?;
+    eprintln!("installed {TIMER}, filters will be updated every {interval}s");
+    Ok(())
+}
+
+async fn uninstall() -> Result<()> {
+    if let Err(error) = systemctl(&["disable", "--now", TIMER]) {
+        warn!("{error}");
+    }
+
+    let directory = unit_directory()?;
+    for name in [SERVICE, TIMER] {
+        let path = directory.join(name);
+        if let Ok(true) = fs::try_exists(&path).await {
+            info!("removing {path:?}");
+            fs::remove_file(&path).await?;
+        }
+    }
+
+    systemctl(&["daemon-reload"])?;
+    eprintln!("uninstalled {TIMER}");
+    Ok(())
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    info!("running systemctl --user {}", args.join(" "));
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .wrap_err_with(|| "could not run systemctl")?;
+
+    if !status.success() {
+        bail!("systemctl --user {} failed with {status}", args.join(" "));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! Converting filters to plain UTF-8. Some are saved by editors as UTF-16,
+//! with a byte order mark, or in the Windows code page, which the game may
+//! reject.
+
+use log::info;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Characters of Windows-1252 from 0x80 to 0x9F, where it differs from
+/// Latin-1. The five unassigned bytes are kept as the matching C1 control.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// Converts a file to UTF-8 without a byte order mark, detecting UTF-16 (by
+/// its byte order mark, or the zero bytes of mostly ASCII text without one)
+/// and falling back to Windows-1252 for anything that isn't valid UTF-8.
+pub fn to_utf8(name: &str, data: Vec<u8>) -> Vec<u8> {
+    let (encoding, text) = if let Some(data) = data.strip_prefix(UTF8_BOM) {
+        ("UTF-8 with a byte order mark", data.to_vec())
+    } else if let Some(data) = data.strip_prefix(UTF16_LE_BOM) {
+        ("UTF-16", decode_utf16(data, u16::from_le_bytes))
+    } else if let Some(data) = data.strip_prefix(UTF16_BE_BOM) {
+        ("UTF-16", decode_utf16(data, u16::from_be_bytes))
+    } else if looks_like_utf16_le(&data) {
+        ("UTF-16", decode_utf16(&data, u16::from_le_bytes))
+    } else if std::str::from_utf8(&data).is_ok() {
+        return data;
+    } else {
+        ("Windows-1252", decode_windows_1252(&data))
+    };
+
+    info!("converting {name} from {encoding} to UTF-8");
+    text
+}
+
+fn decode_utf16(data: &[u8], read: fn([u8; 2]) -> u16) -> Vec<u8> {
+    let units = data.chunks_exact(2).map(|v| read([v[0], v[1]]));
+    char::decode_utf16(units)
+        .map(|v| v.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Filters are mostly ASCII, which has a zero high byte in UTF-16, and never
+/// contain zero bytes otherwise.
+fn looks_like_utf16_le(data: &[u8]) -> bool {
+    let start = &data[..data.len().min(256) & !1];
+    !start.is_empty() && start.chunks_exact(2).all(|v| v[1] == 0)
+}
+
+fn decode_windows_1252(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .map(|&v| match v {
+            0x80..=0x9F => WINDOWS_1252[usize::from(v - 0x80)],
+            _ => char::from(v),
+        })
+        .collect::<String>()
+        .into_bytes()
+}
@@ -0,0 +1,349 @@
+//! Filters published as a plain file (`url:https://example.com/my.filter`),
+//! e.g. on a website or a CDN.
+//!
+//! The watermark is whatever the server offers to tell versions apart: the
+//! `ETag`, the `Last-Modified` date, or else a hash of the file. It says which
+//! it is (`etag:...`), so that the next request can be made conditional.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use color_eyre::{eyre::bail, Result};
+use futures_util::{future::BoxFuture, FutureExt as _, TryFutureExt as _};
+use log::{debug, info};
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    RequestBuilder, Response, StatusCode,
+};
+use tokio::fs;
+
+use crate::{
+    cache, checksum, http, install, logging, progress::Event, text, Error, Globals, Source,
+    VersionInfo,
+};
+
+/// Plain files on a web server.
+pub struct Url;
+
+impl Source for Url {
+    fn resolve<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        existing: Option<&'a String>,
+    ) -> BoxFuture<'a, Result<Option<VersionInfo>, Error>> {
+        resolve(globals, value, existing)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        globals: &'a Globals,
+        value: &'a str,
+        version: &'a VersionInfo,
+    ) -> BoxFuture<'a, Result<Vec<PathBuf>, Error>> {
+        fetch(globals, value, version).map_err(Error::from).boxed()
+    }
+}
+
+async fn resolve(
+    globals: &Globals,
+    value: &str,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    let cache_key = format!("url:{value}");
+    if globals.offline {
+        return cache::cached_version(globals, &cache_key, existing).await;
+    }
+
+    let url = reqwest::Url::parse(value)
+        .ok()
+        .filter(|v| matches!(v.scheme(), "http" | "https"))
+        .ok_or_else(|| {
+            Error::InvalidSourceSpec(format!("{cache_key} must be an http:// or https:// URL"))
+        })?;
+    latest(globals, &cache_key, url, existing).await
+}
+
+/// The validators of the `existing` version, to make a request conditional.
+fn conditional(request: RequestBuilder, existing: Option<&String>) -> RequestBuilder {
+    match existing.and_then(|v| v.split_once(':')) {
+        Some(("etag", etag)) => request.header(IF_NONE_MATCH, etag),
+        Some(("modified", date)) => request.header(IF_MODIFIED_SINCE, date),
+        _ => request,
+    }
+}
+
+/// The watermark offered by a response: its `ETag`, or else its
+/// `Last-Modified` date.
+fn watermark(response: &Response) -> Option<String> {
+    let header = |name| response.headers().get(name)?.to_str().ok();
+    match (header(ETAG), header(LAST_MODIFIED)) {
+        (Some(etag), _) => Some(format!("etag:{etag}")),
+        (None, Some(modified)) => Some(format!("modified:{modified}")),
+        (None, None) => None,
+    }
+}
+
+/// Looks up the version of the file, `None` if it is still the `existing`
+/// one. Only the headers are requested, the file itself is downloaded when it
+/// is installed. Servers that don't answer `HEAD` requests, or send neither
+/// an `ETag` nor a `Last-Modified` date, leave no choice but to download the
+/// file and hash it.
+pub(crate) async fn latest(
+    globals: &Globals,
+    cache_key: &str,
+    url: reqwest::Url,
+    existing: Option<&String>,
+) -> Result<Option<VersionInfo>> {
+    info!("checking {}", http::redact_url(&url));
+    let request = conditional(globals.client.head(url.clone()), existing);
+    let response = http::send(&*globals.transport, request, globals.retries).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        debug!("{cache_key} was not modified");
+        globals.counters.cache_hit();
+        return Ok(None);
+    }
+
+    let watermark = match watermark(&response).filter(|_| response.status().is_success()) {
+        Some(watermark) => watermark,
+        None => {
+            debug!("{cache_key} has no usable validators, downloading it");
+            match download_unversioned(globals, cache_key, &url, existing).await? {
+                Some(watermark) => watermark,
+                None => return Ok(None),
+            }
+        }
+    };
+    if existing == Some(&watermark) {
+        return Ok(None);
+    }
+
+    Ok(Some(VersionInfo {
+        zipball_url: url.to_string(),
+        watermark,
+        body: None,
+        files: Vec::new(),
+        checksums_url: None,
+        signature_url: None,
+    }))
+}
+
+/// Downloads the file into the cache to find out its watermark, `None` if it
+/// is still the `existing` version.
+async fn download_unversioned(
+    globals: &Globals,
+    cache_key: &str,
+    url: &reqwest::Url,
+    existing: Option<&String>,
+) -> Result<Option<String>> {
+    // Not a `.zip`, so that it is never mistaken for a complete download.
+    let path = cache::zipball_path(cache_key, "")
+        .await?
+        .with_extension("download");
+    let offered = Mutex::new(None);
+    let not_modified = AtomicBool::new(false);
+    let result = http::download(
+        globals,
+        conditional(globals.client.get(url.clone()), existing),
+        &path,
+        |response| {
+            if response.status() == StatusCode::NOT_MODIFIED {
+                not_modified.store(true, Ordering::Relaxed);
+                bail!("{cache_key} was not modified");
+            }
+            *offered.lock().unwrap_or_else(|v| v.into_inner()) = watermark(response);
+            Ok(())
+        },
+        |downloaded, total| progress(globals, cache_key, downloaded, total),
+    )
+    .await;
+    if not_modified.load(Ordering::Relaxed) {
+        debug!("{cache_key} was not modified");
+        globals.counters.cache_hit();
+        return Ok(None);
+    }
+    result?;
+
+    let offered = offered.into_inner().unwrap_or_else(|v| v.into_inner());
+    let watermark = match offered {
+        Some(watermark) => watermark,
+        None => format!("sha256:{}", checksum::sha256(&fs::read(&path).await?)),
+    };
+    if existing == Some(&watermark) {
+        let _ = fs::remove_file(&path).await;
+        return Ok(None);
+    }
+
+    let cached = cache::zipball_path(cache_key, &watermark).await?;
+    fs::rename(&path, &cached).await?;
+    cache::remove_zipballs(cache_key, Some(&cached)).await;
+    Ok(Some(watermark))
+}
+
+fn progress(globals: &Globals, cache_key: &str, downloaded: u64, total: Option<u64>) {
+    globals.progress.emit(Event::Downloading {
+        source: cache_key.to_string(),
+        downloaded,
+        total,
+    })
+}
+
+/// Downloads the file of a version into the cache, unless it is there
+/// already, and returns its path. Like archives, the download continues where
+/// it left off if it was interrupted.
+pub(crate) async fn download(
+    globals: &Globals,
+    cache_key: &str,
+    version: &VersionInfo,
+) -> Result<PathBuf> {
+    if globals.signing_keys.contains_key(cache_key) {
+        bail!("only releases can be signed, not files");
+    }
+
+    let path = cache::zipball_path(cache_key, &version.watermark).await?;
+    if path.exists() {
+        info!("using cached {path:?}");
+        globals.counters.cache_hit();
+        return Ok(path);
+    }
+    if globals.offline {
+        bail!("{cache_key} at {} is not in the cache", version.watermark);
+    }
+
+    info!("downloading {cache_key}");
+    http::download(
+        globals,
+        globals.client.get(&version.zipball_url),
+        &path,
+        |_| Ok(()),
+        |downloaded, total| progress(globals, cache_key, downloaded, total),
+    )
+    .await?;
+    cache::remove_zipballs(cache_key, Some(&path)).await;
+    Ok(path)
+}
+
+async fn fetch(globals: &Globals, value: &str, version: &VersionInfo) -> Result<Vec<PathBuf>> {
+    let cache_key = format!("url:{value}");
+    let path = download(globals, &cache_key, version).await?;
+    install(
+        globals,
+        &cache_key,
+        &filename(value),
+        fs::read(&path).await?,
+    )
+    .await
+}
+
+/// Installs a downloaded file as `name`.
+pub(crate) async fn install(
+    globals: &Globals,
+    cache_key: &str,
+    name: &str,
+    data: Vec<u8>,
+) -> Result<Vec<PathBuf>> {
+    let pipeline = globals
+        .pipelines
+        .get(cache_key)
+        .cloned()
+        .unwrap_or_default();
+    if !pipeline.selects(name) {
+        info!("{name} isn't selected, nothing to install");
+        return Ok(Vec::new());
+    }
+    let Some(target) = globals.targets.iter().find(|v| v.matches(name)) else {
+        bail!("no target for {name}, add one with --target to install it");
+    };
+
+    // Other files (like sounds) are installed as they are. Transforms can
+    // take a while, and must not hold up the deadline.
+    let is_filter = Path::new(name).extension().is_some_and(|v| v == "filter");
+    let data = match is_filter {
+        true => {
+            logging::spawn_blocking({
+                let (source, name, cancel) = (
+                    cache_key.to_string(),
+                    name.to_string(),
+                    globals.cancel.clone(),
+                );
+                let pipeline = pipeline.clone();
+                move || pipeline.process(&source, &name, text::to_utf8(&name, data), &cancel)
+            })
+            .await??
+        }
+        false => data,
+    };
+    let destination = install::destination(&target.directory, Path::new(&pipeline.rename(name)))?;
+    fs::create_dir_all(&target.directory).await?;
+    globals.progress.emit(Event::Writing {
+        source: cache_key.to_string(),
+        path: destination.clone(),
+    });
+    install::write_file(&target.directory, &destination, &data).await?;
+    info!("updated {cache_key}");
+
+    Ok(vec![destination])
+}
+
+/// The name the file is installed as: the last part of its path, with its
+/// own extension so that the targets decide where it goes. Only a name
+/// without any extension is made a `.filter`, files without a name are named
+/// after the host.
+pub(crate) fn filename(value: &str) -> String {
+    let url = reqwest::Url::parse(value).ok();
+    let last = url
+        .as_ref()
+        .and_then(|v| v.path_segments())
+        .and_then(|mut v| v.next_back())
+        .map(|v| percent_decode_str(v).decode_utf8_lossy().into_owned())
+        .and_then(|v| install::sanitize_filename(&v))
+        .filter(|v| !v.is_empty());
+    let host = || {
+        url.as_ref()
+            .and_then(|v| v.host_str())
+            .and_then(install::sanitize_filename)
+            .unwrap_or_else(|| "filter".to_string())
+    };
+
+    match last {
+        Some(name) if Path::new(&name).extension().is_some() => name,
+        Some(name) => format!("{name}.filter"),
+        None => format!("{}.filter", host()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::filename;
+
+    #[test]
+    fn keeps_the_extension_of_the_url() {
+        assert_eq!(
+            filename("https://example.com/a/NeverSink.filter"),
+            "NeverSink.filter"
+        );
+        assert_eq!(
+            filename("https://example.com/Strict.FILTER?v=2"),
+            "Strict.FILTER"
+        );
+        assert_eq!(
+            filename("https://example.com/sounds/alert.mp3"),
+            "alert.mp3"
+        );
+        assert_eq!(
+            filename("https://example.com/My%20Filter.filter"),
+            "My Filter.filter"
+        );
+        assert_eq!(filename("https://example.com/download"), "download.filter");
+        assert_eq!(filename("https://example.com/"), "example.com.filter");
+        assert_eq!(filename("https://example.com/CON.filter"), "CON_.filter");
+    }
+}
@@ -0,0 +1,429 @@
+//! A minimal reader and writer for Valve's text KeyValues format (`.vdf`),
+//! as used by Steam's `localconfig.vdf` and `libraryfolders.vdf`.
+
+use std::fmt::Write as _;
+
+use color_eyre::{eyre::bail, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(v) => Some(v),
+            Value::Object(_) => None,
+        }
+    }
+
+    /// Looks up a child, keys are case-insensitive like in Steam.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(children) => children
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v),
+            Value::String(_) => None,
+        }
+    }
+
+    pub fn children(&self) -> &[(String, Value)] {
+        match self {
+            Value::Object(children) => children,
+            Value::String(_) => &[],
+        }
+    }
+
+    /// Follows the path of keys, creating missing objects along the way.
+    /// Fails if one of the keys is a string rather than an object.
+    pub fn object_at(&mut self, path: &[&str]) -> Result<&mut Value> {
+        let mut current = self;
+        for key in path {
+            let Value::Object(children) = current else {
+                bail!("expected {key:?} to be in an object");
+            };
+
+            let index = match children
+                .iter()
+                .position(|(k, _)| k.eq_ignore_ascii_case(key))
+            {
+                Some(index) => index,
+                None => {
+                    children.push((key.to_string(), Value::Object(Vec::new())));
+                    children.len() - 1
+                }
+            };
+            current = &mut children[index].1;
+        }
+
+        Ok(current)
+    }
+
+    /// Sets a string child, replacing an existing one with the same key.
+    pub fn set(&mut self, key: &str, value: String) -> Result<()> {
+        let Value::Object(children) = self else {
+            bail!("can't set {key:?} on a string");
+        };
+
+        match children
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some((_, existing)) => *existing = Value::String(value),
+            None => children.push((key.to_string(), Value::String(value))),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    String(String),
+    Open,
+    Close,
+}
+
+/// Splits a document into tokens. Comments and platform conditionals are
+/// skipped, the second value is whether there were any.
+fn tokenize(text: &str) -> Result<(Vec<Token>, bool)> {
+    let mut tokens = Vec::new();
+    let mut skipped = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '/' if chars.peek() == Some(&'/') => {
+                skipped = true;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            // Platform conditionals such as [$WIN32], they don't matter here.
+            '[' => {
+                skipped = true;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(other) => value.push(other),
+                            None => bail!("unterminated string"),
+                        },
+                        Some(other) => value.push(other),
+                        None => bail!("unterminated string"),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut value = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '{' || next == '}' || next == '"' {
+                        break;
+                    }
+                    value.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::String(value));
+            }
+        }
+    }
+
+    Ok((tokens, skipped))
+}
+
+/// Parses a document, returning its top-level entries as an object.
+pub fn parse(text: &str) -> Result<Value> {
+    let (tokens, _) = tokenize(text)?;
+    parse_object(&mut tokens.into_iter(), true)
+}
+
+/// Like [`parse`], for a document that is written back with [`to_string`].
+/// Fails on comments and platform conditionals, which would be lost.
+pub fn parse_exact(text: &str) -> Result<Value> {
+    let (tokens, skipped) = tokenize(text)?;
+    if skipped {
+        bail!("the file has comments or platform conditionals, which can't be kept");
+    }
+    parse_object(&mut tokens.into_iter(), true)
+}
+
+fn parse_object(tokens: &mut impl Iterator<Item = Token>, root: bool) -> Result<Value> {
+    let mut children = Vec::new();
+
+    loop {
+        let key = match tokens.next() {
+            Some(Token::String(key)) => key,
+            Some(Token::Close) if !root => break,
+            None if root => break,
+            Some(other) => bail!("unexpected {other:?}"),
+            None => bail!("unexpected end of file"),
+        };
+
+        let value = match tokens.next() {
+            Some(Token::String(value)) => Value::String(value),
+            Some(Token::Open) => parse_object(tokens, false)?,
+            Some(Token::Close) => bail!("missing value for {key:?}"),
+            None => bail!("unexpected end of file"),
+        };
+
+        children.push((key, value));
+    }
+
+    Ok(Value::Object(children))
+}
+
+/// Serializes the top-level entries of a document, in Steam's own style.
+pub fn to_string(root: &Value) -> String {
+    let mut result = String::new();
+    write_children(&mut result, root.children(), 0);
+    result
+}
+
+fn write_children(result: &mut String, children: &[(String, Value)], depth: usize) {
+    let indent = "\t".repeat(depth);
+    for (key, value) in children {
+        match value {
+            Value::String(value) => {
+                let _ = writeln!(
+                    result,
+                    "{indent}\"{}\"\t\t\"{}\"",
+                    escape(key),
+                    escape(value)
+                );
+            }
+            Value::Object(children) => {
+                let _ = writeln!(result, "{indent}\"{}\"", escape(key));
+                let _ = writeln!(result, "{indent}{{");
+                write_children(result, children, depth + 1);
+                let _ = writeln!(result, "{indent}}}");
+            }
+        }
+    }
+}
+
+/// The reverse of what the tokenizer unescapes.
+fn escape(v: &str) -> String {
+    let mut escaped = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_exact, to_string, tokenize, Token, Value};
+
+    /// A `localconfig.vdf` as Steam writes it, trimmed down.
+    const LOCALCONFIG: &str = r#""UserLocalConfigStore"
+{
+	"Broadcast"
+	{
+		"Permissions"		"1"
+	}
+	"friends"
+	{
+		"PersonaName"		"Exile \\o/ \"Wraeclast\""
+		"Status"		"line one\nline two\tand a tab"
+	}
+	"Software"
+	{
+		"Valve"
+		{
+			"Steam"
+			{
+				"apps"
+				{
+					"2694490"
+					{
+						"LastPlayed"		"1735689600"
+						"LaunchOptions"		"gamemoderun \"/home/exile/My Tools/poe2filter\" -- %command%"
+						"cloud"
+						{
+							"last_sync_state"		"synchronized"
+						}
+					}
+					"238960"
+					{
+						"LaunchOptions"		"--nologo"
+					}
+				}
+				"ShaderCacheManager"
+				{
+					"HasCurrentBucket"		"1"
+				}
+			}
+		}
+	}
+	"WebStorage"
+	{
+		"LibraryDisplaySize"		"{\"size\":\"medium\"}"
+		"CloudPath"		"C:\\Program Files (x86)\\Steam"
+	}
+}
+"#;
+
+    #[test]
+    fn round_trips_localconfig() {
+        let config = parse_exact(LOCALCONFIG).unwrap();
+        assert_eq!(to_string(&config), LOCALCONFIG);
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        let config = parse(LOCALCONFIG).unwrap();
+        let friends = config
+            .get("UserLocalConfigStore")
+            .and_then(|v| v.get("friends"))
+            .unwrap();
+        assert_eq!(
+            friends.get("PersonaName").and_then(Value::as_str),
+            Some(r#"Exile \o/ "Wraeclast""#)
+        );
+        assert_eq!(
+            friends.get("Status").and_then(Value::as_str),
+            Some("line one\nline two\tand a tab")
+        );
+    }
+
+    #[test]
+    fn round_trips_changes() {
+        let mut config = parse_exact(LOCALCONFIG).unwrap();
+        let app = config
+            .object_at(&[
+                "UserLocalConfigStore",
+                "Software",
+                "Valve",
+                "Steam",
+                "apps",
+                "2694490",
+            ])
+            .unwrap();
+        app.set(
+            "LaunchOptions",
+            "poe2filter -- \"%command%\"\t-x".to_string(),
+        )
+        .unwrap();
+
+        let written = to_string(&config);
+        let reparsed = parse_exact(&written).unwrap();
+        assert_eq!(reparsed, config);
+        assert_eq!(to_string(&reparsed), written);
+    }
+
+    #[test]
+    fn refuses_to_drop_comments_and_conditionals() {
+        let text = "\"a\"\n{\n\t// comment\n\t\"b\"\t\t\"c\"\t[$WIN32]\n}\n";
+        assert!(parse(text).is_ok());
+        assert!(parse_exact(text).is_err());
+    }
+
+    #[test]
+    fn tokenizes_unquoted_and_adjacent_tokens() {
+        let (tokens, skipped) = tokenize("a{\"b\"\"c\"d \"\"}// x\n").unwrap();
+        assert!(skipped);
+        assert_eq!(
+            tokens,
+            [
+                Token::String("a".to_string()),
+                Token::Open,
+                Token::String("b".to_string()),
+                Token::String("c".to_string()),
+                Token::String("d".to_string()),
+                Token::String(String::new()),
+                Token::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_libraryfolders() {
+        let text = r#""libraryfolders"
+{
+	"0"
+	{
+		"path"		"/home/exile/.local/share/Steam"
+		"apps"
+		{
+			"228980"		"1234"
+		}
+	}
+	"1"
+	{
+		"path"		"/run/media/mmcblk0p1"
+		"apps"
+		{
+			"2694490"		"5678"
+		}
+	}
+}
+"#;
+        let folders = parse(text).unwrap();
+        let folders = folders.get("LibraryFolders").unwrap().children();
+        assert_eq!(folders.len(), 2);
+        assert_eq!(
+            folders[1].1.get("PATH").and_then(Value::as_str),
+            Some("/run/media/mmcblk0p1")
+        );
+        assert!(folders[1].1.get("apps").unwrap().get("2694490").is_some());
+    }
+
+    #[test]
+    fn fails_on_broken_documents() {
+        for text in [
+            "\"a\" \"unterminated",
+            "\"a\" \"b\\",
+            "\"a\" { \"b\" }",
+            "\"a\" { \"b\" \"c\"",
+            "\"a\"",
+            "}",
+            "{ \"a\" \"b\" }",
+        ] {
+            assert!(parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn writes_steam_style() {
+        let mut root = Value::Object(Vec::new());
+        root.object_at(&["a", "empty"]).unwrap();
+        let a = root.object_at(&["A"]).unwrap();
+        a.set("key", "old".to_string()).unwrap();
+        a.set("KEY", r#"C:\Steam "quoted""#.to_string()).unwrap();
+        assert!(root.object_at(&["a", "key", "deeper"]).is_err());
+
+        let expected = r#""a"
+{
+	"empty"
+	{
+	}
+	"key"		"C:\\Steam \"quoted\""
+}
+"#;
+        assert_eq!(to_string(&root), expected);
+    }
+}
@@ -0,0 +1,102 @@
+//! Checking for new releases of poe2filter itself.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+use tokio::fs;
+
+use crate::{dirs, github, notify, Globals};
+
+const OWNER: &str = "jcdickinson";
+const REPO: &str = "poe2filter";
+
+/// Releases are checked at most this often.
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many lines of the changelog are shown.
+const CHANGELOG_LINES: usize = 10;
+
+fn last_check_file() -> Option<PathBuf> {
+    Some(dirs::state_home()?.join("poe2filter/last_version_check"))
+}
+
+/// Tells the user about a newer release of poe2filter, at most once a day.
+/// Failures are only logged, this must never get in the way of updates.
+pub async fn check(globals: &Globals, show_notification: bool) {
+    let Some(path) = last_check_file() else {
+        return;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let last_check = fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    if now.saturating_sub(last_check) < CHECK_INTERVAL.as_secs() {
+        debug!("checked for a new version recently, skipping");
+        return;
+    }
+
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent).await;
+    }
+    if let Err(error) = fs::write(&path, now.to_string()).await {
+        debug!("could not save the version check time: {error}");
+    }
+
+    let release = match github::latest_release(globals, OWNER, REPO, None).await {
+        Ok(Some(release)) => release,
+        Ok(None) => return,
+        Err(error) => {
+            debug!("could not check for a new version: {error}");
+            return;
+        }
+    };
+
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&release.watermark, current) {
+        debug!("poe2filter {current} is up to date");
+        return;
+    }
+
+    let changelog: Vec<_> = release
+        .body
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .take(CHANGELOG_LINES)
+        .collect();
+    let title = format!("poe2filter {} is available", release.watermark);
+    let body = format!(
+        "You have {current}.\n{}\nhttps://github.com/{OWNER}/{REPO}/releases",
+        changelog.join("\n")
+    );
+
+    eprintln!("# {title}");
+    eprintln!("{body}");
+    eprintln!();
+
+    if show_notification {
+        notify::send(&title, &body);
+    }
+}
+
+/// Compares `v1.2.3`-style versions numerically.
+fn is_newer(tag: &str, current: &str) -> bool {
+    fn parse(v: &str) -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|v| v.parse().ok())
+            .collect()
+    }
+
+    parse(tag) > parse(current)
+}
@@ -0,0 +1,93 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use log::{error, info};
+use rand::Rng as _;
+
+use crate::{block_on, cancel, client_log, locate_game_directory, notify, run, Options};
+
+/// How often sources are checked, unless overridden.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Randomizes each interval by up to this fraction, so that many users
+/// don't hit GitHub at exactly the same time.
+const JITTER: f64 = 0.1;
+
+/// How often the game log is checked for filter errors after an update.
+const LOG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stays resident and keeps updating the sources every interval.
+pub fn watch(sources: Vec<OsString>, options: &Options) -> Result<()> {
+    info!(
+        "watching {} source(s) every {:?}",
+        sources.len(),
+        options.interval
+    );
+
+    #[cfg(target_os = "linux")]
+    if options.guard {
+        let directory = block_on(locate_game_directory(options))?;
+        crate::guard::spawn(directory)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    if options.guard {
+        log::warn!("--guard needs inotify, which is only available on Linux");
+    }
+
+    // Where to look for filter errors in the game log, from the last install on.
+    let mut log_position: Option<(PathBuf, u64)> = None;
+
+    while !cancel::is_cancelled() {
+        match run(sources.clone(), options) {
+            Ok(summary) => {
+                if !summary.updated.is_empty() {
+                    log_position = block_on(client_log::position(options));
+                }
+                if options.notify.unwrap_or(false) {
+                    notify::summary(&summary);
+                }
+            }
+            Err(error) => {
+                error!("could not update filters: {error}");
+                if options.notify.unwrap_or(false) {
+                    notify::failure(&error);
+                }
+            }
+        }
+
+        let jitter = rand::thread_rng().gen_range(-JITTER..=JITTER);
+        let delay = options.interval.mul_f64(1.0 + jitter);
+        info!("next check in {}s", delay.as_secs());
+
+        let deadline = Instant::now() + delay;
+        let mut next_log_check = Instant::now() + LOG_CHECK_INTERVAL;
+        while !cancel::is_cancelled() && Instant::now() < deadline {
+            sleep(Duration::from_secs(1));
+
+            if Instant::now() >= next_log_check {
+                next_log_check += LOG_CHECK_INTERVAL;
+                if let Some((path, from)) = log_position.take() {
+                    log_position = block_on(check_filter_errors(options, &path, from));
+                }
+            }
+        }
+    }
+
+    info!("stopped watching");
+    Ok(())
+}
+
+/// Reports filter errors the game logged since the given position, and
+/// returns the new position to continue from.
+async fn check_filter_errors(options: &Options, path: &Path, from: u64) -> Option<(PathBuf, u64)> {
+    if let Ok(directory) = locate_game_directory(options).await {
+        client_log::report_filter_errors(path, from, &directory, options.notify.unwrap_or(false))
+            .await;
+    }
+    client_log::position(options).await
+}
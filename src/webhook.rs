@@ -0,0 +1,101 @@
+use log::{info, warn};
+use serde_json::json;
+
+use crate::{http, Globals, Summary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Discord,
+    Ntfy,
+    Generic,
+}
+
+/// Splits an optional `discord:`, `ntfy:` or `post:` prefix from a webhook,
+/// guessing the kind from the URL otherwise.
+fn parse(webhook: &str) -> (Kind, &str) {
+    for (prefix, kind) in [
+        ("discord:", Kind::Discord),
+        ("ntfy:", Kind::Ntfy),
+        ("post:", Kind::Generic),
+    ] {
+        if let Some(url) = webhook.strip_prefix(prefix) {
+            return (kind, url);
+        }
+    }
+
+    let kind = if webhook.contains("discord.com/api/webhooks/")
+        || webhook.contains("discordapp.com/api/webhooks/")
+    {
+        Kind::Discord
+    } else if webhook.contains("://ntfy.") {
+        Kind::Ntfy
+    } else {
+        Kind::Generic
+    };
+    (kind, webhook)
+}
+
+fn message(summary: &Summary) -> String {
+    let mut lines: Vec<_> = summary
+        .updated
+        .iter()
+        .map(|update| format!("{} updated to {}", update.source, update.watermark))
+        .collect();
+    lines.extend(
+        summary
+            .failed
+            .iter()
+            .map(|(source, error)| format!("{source} failed: {error}")),
+    );
+    lines.join("\n")
+}
+
+/// Sends the summary to every webhook, failures are only logged.
+pub async fn send(globals: &Globals, webhooks: &[String], summary: &Summary) {
+    let client = &globals.client;
+    if summary.updated.is_empty() && summary.failed.is_empty() {
+        return;
+    }
+
+    for webhook in webhooks {
+        let (kind, url) = parse(webhook);
+        let request = match kind {
+            Kind::Discord => client.post(url).json(&json!({
+                "username": "poe2filter",
+                "content": message(summary),
+            })),
+            Kind::Ntfy => client
+                .post(url)
+                .header("Title", "poe2filter")
+                .header(
+                    "Tags",
+                    if summary.failed.is_empty() {
+                        "package"
+                    } else {
+                        "warning"
+                    },
+                )
+                .body(message(summary)),
+            Kind::Generic => client.post(url).json(&json!({
+                "updated": summary.updated.iter().map(|update| json!({
+                    "source": update.source,
+                    "watermark": update.watermark,
+                    "files": update.files,
+                })).collect::<Vec<_>>(),
+                "failed": summary.failed.iter().map(|(source, error)| json!({
+                    "source": source,
+                    "error": format!("{error:#}"),
+                })).collect::<Vec<_>>(),
+            })),
+        };
+
+        info!("sending {kind:?} webhook");
+        match http::send(&*globals.transport, request, globals.retries)
+            .await
+            .and_then(|v| v.error_for_status())
+        {
+            Ok(_) => {}
+            Err(error) => warn!("webhook failed: {error}"),
+        }
+    }
+}
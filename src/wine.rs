@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+use tokio::fs;
+
+use crate::Game;
+
+const UNINSTALL_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+const SHELL_FOLDERS_KEY: &str =
+    "Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\Shell Folders";
+
+/// Names of the Documents folder in a user's profile, under Wine, Proton and
+/// with OneDrive. Localized names are found by looking for `My Games`.
+const DOCUMENTS_FOLDERS: [&str; 3] = ["Documents", "My Documents", "OneDrive/Documents"];
+
+/// A parsed Wine registry hive (`user.reg`, `system.reg`), keyed by the
+/// lowercased key path and then by the lowercased value name. The default
+/// value of a key is stored under the name `@`.
+#[derive(Debug, Default)]
+pub struct Registry {
+    keys: HashMap<String, HashMap<String, String>>,
+}
+
+impl Registry {
+    pub async fn load(path: &Path) -> Option<Self> {
+        let raw = fs::read(path).await.ok()?;
+        Some(Self::parse(&String::from_utf8_lossy(&raw)))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut keys: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut current = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix('[') {
+                // [Software\\Wine] 1700000000
+                current = rest.rfind(']').map(|end| {
+                    let key = unescape(&rest[..end]).to_lowercase();
+                    keys.entry(key.clone()).or_default();
+                    key
+                });
+                continue;
+            }
+
+            let Some(key) = current.as_ref() else {
+                continue;
+            };
+
+            let Some((name, value)) = split_value(line) else {
+                continue;
+            };
+
+            // Only plain string values are interesting, skip dword:, hex:, str(2): etc.
+            let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+                continue;
+            };
+
+            if let Some(values) = keys.get_mut(key) {
+                values.insert(name.to_lowercase(), unescape(value));
+            }
+        }
+
+        Registry { keys }
+    }
+
+    pub fn has_key(&self, key: &str) -> bool {
+        self.keys.contains_key(&key.to_lowercase())
+    }
+
+    pub fn get(&self, key: &str, name: &str) -> Option<&str> {
+        self.keys
+            .get(&key.to_lowercase())?
+            .get(&name.to_lowercase())
+            .map(|v| v.as_str())
+    }
+
+    pub fn subkeys<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a str> {
+        let prefix = format!("{}\\", key.to_lowercase());
+        self.keys
+            .keys()
+            .filter_map(move |k| k.strip_prefix(&prefix))
+            .filter(|k| !k.contains('\\'))
+    }
+}
+
+fn split_value(line: &str) -> Option<(String, &str)> {
+    if let Some(value) = line.strip_prefix("@=") {
+        return Some(("@".to_string(), value));
+    }
+
+    let rest = line.strip_prefix('"')?;
+    let mut escaped = false;
+    for (idx, c) in rest.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                let value = rest[idx + 1..].strip_prefix('=')?;
+                return Some((unescape(&rest[..idx]), value));
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn unescape(v: &str) -> String {
+    let mut result = String::with_capacity(v.len());
+    let mut chars = v.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
+            Some('x') => {
+                let mut code = 0u32;
+                while let Some(digit) = chars.peek().and_then(|v| v.to_digit(16)) {
+                    code = code * 16 + digit;
+                    chars.next();
+                }
+                result.extend(char::from_u32(code));
+            }
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+/// Maps a Windows path such as `C:\users\me\Documents` to its location inside
+/// the given prefix.
+pub fn to_host_path(prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let mut parts = windows_path.split(['\\', '/']).filter(|v| !v.is_empty());
+    let drive = parts.next()?.to_lowercase();
+    if drive.len() != 2 || !drive.ends_with(':') {
+        return None;
+    }
+
+    let dosdevice = prefix.join("dosdevices").join(&drive);
+    let mut path = if dosdevice.exists() {
+        dosdevice
+    } else if drive == "c:" {
+        prefix.join("drive_c")
+    } else {
+        return None;
+    };
+
+    for part in parts {
+        path.push(part);
+    }
+
+    Some(path)
+}
+
+/// Finds the game directory in a Wine prefix, which may not exist yet. One
+/// that the game already created is preferred, otherwise it goes in the
+/// Documents folder from the registry. Unless the prefix was given
+/// explicitly, the standalone (non-Steam) client has to be installed in it.
+pub async fn game_directory(prefix: &Path, game: Game, explicit: bool) -> Option<PathBuf> {
+    let user = Registry::load(&prefix.join("user.reg"))
+        .await
+        .unwrap_or_default();
+
+    let mut documents: Vec<_> = user
+        .get(SHELL_FOLDERS_KEY, "Personal")
+        .and_then(|v| to_host_path(prefix, v))
+        .into_iter()
+        .collect();
+    documents.extend(users_documents(prefix).await);
+
+    // Where the game keeps its filters, inside the Documents folder.
+    let folder = Path::new("My Games").join(game.name());
+    for documents in &documents {
+        let path = documents.join(&folder);
+        if let Ok(true) = fs::try_exists(&path).await {
+            info!("found {path:?} in {prefix:?}");
+            return Some(path);
+        }
+    }
+
+    if !explicit {
+        if !is_standalone_installed(prefix, game, &user).await {
+            debug!("no standalone client registered in {prefix:?}");
+            return None;
+        }
+        info!("found standalone client in {prefix:?}");
+    }
+    Some(documents.first()?.join(folder))
+}
+
+/// The Documents folders of every user in `drive_c/users`, including the
+/// localized ones that the game created `My Games` in.
+async fn users_documents(prefix: &Path) -> Vec<PathBuf> {
+    let mut documents = Vec::new();
+    let Ok(mut users) = fs::read_dir(prefix.join("drive_c/users")).await else {
+        return documents;
+    };
+
+    while let Ok(Some(user)) = users.next_entry().await {
+        if user.file_name() == "Public" {
+            continue;
+        }
+        for name in DOCUMENTS_FOLDERS {
+            let path = user.path().join(name);
+            if let Ok(true) = fs::try_exists(&path).await {
+                documents.push(path);
+            }
+        }
+
+        let Ok(mut folders) = fs::read_dir(user.path()).await else {
+            continue;
+        };
+        while let Ok(Some(folder)) = folders.next_entry().await {
+            let path = folder.path();
+            if !documents.contains(&path) {
+                if let Ok(true) = fs::try_exists(path.join("My Games")).await {
+                    documents.push(path);
+                }
+            }
+        }
+    }
+
+    documents
+}
+
+async fn is_standalone_installed(prefix: &Path, game: Game, user: &Registry) -> bool {
+    if user.has_key(&format!("Software\\GrindingGearGames\\{}", game.name())) {
+        return true;
+    }
+
+    let Some(system) = Registry::load(&prefix.join("system.reg")).await else {
+        return false;
+    };
+
+    let uninstall = format!(
+        "Software\\Wow6432Node\\{}",
+        &UNINSTALL_KEY["Software\\".len()..]
+    );
+    [UNINSTALL_KEY, uninstall.as_str()].iter().any(|key| {
+        system.subkeys(key).any(|sub| {
+            system
+                .get(&format!("{key}\\{sub}"), "DisplayName")
+                .is_some_and(|name| name.eq_ignore_ascii_case(game.name()))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{game_directory, to_host_path, Registry, SHELL_FOLDERS_KEY};
+    use crate::{block_on, Game};
+
+    /// A `user.reg` as Wine writes it, trimmed down.
+    const USER_REG: &str = r#"WINE REGISTRY Version 2
+;; All keys relative to \\User\\S-1-5-21-0-0-0-1000
+
+#arch=win64
+
+[Software\\GrindingGearGames\\Path of Exile 2] 1735689600
+#time=1db5b5a1b2c3d4e
+@="default"
+"InstallLocation"="C:\\Program Files (x86)\\Grinding Gear Games\\Path of Exile 2"
+
+[Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\Shell Folders] 1735689600
+"Personal"="C:\\users\\steamuser\\Documents"
+"My \"Quoted\" Folder"="line\none\ttab"
+"Unicode"="\x4e2d\x6587"
+"Version"=dword:00000001
+"Binary"=hex:01,02,03
+"Expand"=str(2):"%USERPROFILE%\\Desktop"
+"#;
+
+    #[test]
+    fn parses_string_values() {
+        let registry = Registry::parse(USER_REG);
+        let key = "software\\grindinggeargames\\PATH OF EXILE 2";
+        assert!(registry.has_key(key));
+        assert_eq!(registry.get(key, "@"), Some("default"));
+        assert_eq!(
+            registry.get(key, "installlocation"),
+            Some("C:\\Program Files (x86)\\Grinding Gear Games\\Path of Exile 2")
+        );
+
+        assert_eq!(
+            registry.get(SHELL_FOLDERS_KEY, "Personal"),
+            Some("C:\\users\\steamuser\\Documents")
+        );
+        assert_eq!(
+            registry.get(SHELL_FOLDERS_KEY, "My \"Quoted\" Folder"),
+            Some("line\none\ttab")
+        );
+        assert_eq!(registry.get(SHELL_FOLDERS_KEY, "Unicode"), Some("中文"));
+        for name in ["Version", "Binary", "Expand"] {
+            assert_eq!(registry.get(SHELL_FOLDERS_KEY, name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn lists_direct_subkeys() {
+        let registry = Registry::parse(USER_REG);
+        let mut subkeys: Vec<_> = registry.subkeys("Software\\GrindingGearGames").collect();
+        subkeys.sort();
+        assert_eq!(subkeys, ["path of exile 2"]);
+        assert_eq!(registry.subkeys("Software").count(), 0);
+    }
+
+    #[test]
+    fn maps_windows_paths_into_the_prefix() {
+        let prefix = std::env::temp_dir().join(format!("poe2filter-wine-{}", std::process::id()));
+        fs::create_dir_all(prefix.join("dosdevices/d:")).unwrap();
+
+        assert_eq!(
+            to_host_path(&prefix, "C:\\users\\steamuser\\Documents"),
+            Some(prefix.join("drive_c/users/steamuser/Documents"))
+        );
+        assert_eq!(
+            to_host_path(&prefix, "d:/Games//PoE2\\"),
+            Some(prefix.join("dosdevices/d:/Games/PoE2"))
+        );
+        assert_eq!(to_host_path(&prefix, "E:\\Games"), None);
+        assert_eq!(to_host_path(&prefix, "\\\\server\\share"), None);
+        assert_eq!(to_host_path(&prefix, "Documents"), None);
+        assert_eq!(to_host_path(&prefix, ""), None);
+
+        fs::remove_dir_all(&prefix).unwrap();
+    }
+
+    #[test]
+    fn finds_the_game_directory() {
+        let prefix =
+            std::env::temp_dir().join(format!("poe2filter-wine-game-{}", std::process::id()));
+        let documents = prefix.join("drive_c/users/steamuser/Documents");
+        fs::create_dir_all(&documents).unwrap();
+        fs::write(prefix.join("user.reg"), USER_REG).unwrap();
+        let expected = documents.join(Path::new("My Games").join("Path of Exile 2"));
+
+        // The standalone client is registered, so the directory is used
+        // before the game created it.
+        assert_eq!(
+            block_on(game_directory(&prefix, Game::Poe2, false)),
+            Some(expected.clone())
+        );
+        assert_eq!(block_on(game_directory(&prefix, Game::Poe1, false)), None);
+        assert_eq!(
+            block_on(game_directory(&prefix, Game::Poe1, true)),
+            Some(documents.join(Path::new("My Games").join("Path of Exile")))
+        );
+
+        // One that the game created in a localized folder is preferred.
+        let localized = prefix.join("drive_c/users/steamuser/Dokumente/My Games/Path of Exile");
+        fs::create_dir_all(&localized).unwrap();
+        assert_eq!(
+            block_on(game_directory(&prefix, Game::Poe1, false)),
+            Some(localized)
+        );
+
+        fs::remove_dir_all(&prefix).unwrap();
+    }
+}